@@ -0,0 +1,7 @@
+-- down migration script
+ALTER TABLE ip_addresses DROP COLUMN geo_resolved_at;
+ALTER TABLE ip_addresses DROP COLUMN autonomous_system_organization;
+ALTER TABLE ip_addresses DROP COLUMN autonomous_system_number;
+ALTER TABLE ip_addresses DROP COLUMN city_name;
+ALTER TABLE ip_addresses DROP COLUMN region_name;
+ALTER TABLE ip_addresses DROP COLUMN country_code;
@@ -0,0 +1,10 @@
+-- down migration script
+DROP INDEX IF EXISTS ix_user_agents_02;
+DROP INDEX IF EXISTS ix_user_agents_01;
+ALTER TABLE user_agents DROP COLUMN parsed_at;
+ALTER TABLE user_agents DROP COLUMN device_class;
+ALTER TABLE user_agents DROP COLUMN device_model;
+ALTER TABLE user_agents DROP COLUMN os_version;
+ALTER TABLE user_agents DROP COLUMN os_name;
+ALTER TABLE user_agents DROP COLUMN browser_version;
+ALTER TABLE user_agents DROP COLUMN browser_name;
@@ -0,0 +1,15 @@
+-- up migration script
+-- table: `fingerprints`
+CREATE TABLE IF NOT EXISTS fingerprints (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_fingerprints_01 ON fingerprints (value);
+INSERT INTO fingerprints (id, value)
+    SELECT * FROM (SELECT 0, '') AS fingerprints
+    WHERE NOT EXISTS (SELECT * FROM fingerprints WHERE id = 0);
+
+-- the browser fingerprint of the visit; `0` if it is not enabled
+ALTER TABLE logs ADD COLUMN fingerprint_id INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS ix_logs_07 ON logs (fingerprint_id);
@@ -0,0 +1,21 @@
+-- up migration script
+-- table: `sessions`
+-- a visit of a BICMID, `value` is the session ID kept in `sessionStorage`, empty without it.
+-- a hit more than `session.timeout_secs` after `last_seen_at` starts another session.
+CREATE TABLE IF NOT EXISTS sessions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    started_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_seen_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL,
+    bicmid_id INTEGER NOT NULL,
+    hits INTEGER NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS ix_sessions_01 ON sessions (bicmid_id, value);
+CREATE INDEX IF NOT EXISTS ix_sessions_02 ON sessions (started_at);
+INSERT INTO sessions (id, value, bicmid_id)
+    SELECT * FROM (SELECT 0, '', 0) AS sessions
+    WHERE NOT EXISTS (SELECT * FROM sessions WHERE id = 0);
+
+-- the session of the visit; `0` without a session ID and a BICMID
+ALTER TABLE logs ADD COLUMN session_id INTEGER NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS ix_logs_08 ON logs (session_id);
@@ -0,0 +1,5 @@
+-- down migration script
+ALTER TABLE sessions DROP COLUMN page_views;
+
+DROP TABLE IF EXISTS page_views;
+DROP TABLE IF EXISTS paths;
@@ -0,0 +1,32 @@
+-- up migration script
+-- table: `paths`
+CREATE TABLE IF NOT EXISTS paths (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_paths_01 ON paths (value);
+INSERT INTO paths (id, value)
+    SELECT * FROM (SELECT 0, '') AS paths
+    WHERE NOT EXISTS (SELECT * FROM paths WHERE id = 0);
+
+-- table: `page_views`
+-- a route of a single page app; `referrer_id` is the previous route, or `document.referrer` first
+CREATE TABLE IF NOT EXISTS page_views (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    path_id INTEGER NOT NULL,
+    title TEXT NOT NULL,
+    referrer_id INTEGER NOT NULL,
+    bicmid_id INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ix_page_views_01 ON page_views (path_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_02 ON page_views (referrer_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_03 ON page_views (bicmid_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_04 ON page_views (session_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_05 ON page_views (user_id);
+
+-- the page views of the session, besides the visits of `hits`
+ALTER TABLE sessions ADD COLUMN page_views INTEGER NOT NULL DEFAULT 0;
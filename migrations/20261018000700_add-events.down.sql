@@ -0,0 +1,3 @@
+-- down migration script
+DROP TABLE IF EXISTS events;
+DROP TABLE IF EXISTS event_names;
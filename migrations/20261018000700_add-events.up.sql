@@ -0,0 +1,28 @@
+-- up migration script
+-- table: `event_names`
+-- the normalized names of the events, ex.) `checkout_started`
+CREATE TABLE IF NOT EXISTS event_names (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_event_names_01 ON event_names (value);
+INSERT INTO event_names (id, value)
+    SELECT * FROM (SELECT 0, '') AS event_names
+    WHERE NOT EXISTS (SELECT * FROM event_names WHERE id = 0);
+
+-- table: `events`
+-- a custom event of the application; `properties` is a JSON object
+CREATE TABLE IF NOT EXISTS events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    name_id INTEGER NOT NULL,
+    properties TEXT NOT NULL,
+    bicmid_id INTEGER NOT NULL,
+    session_id INTEGER NOT NULL,
+    user_id INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ix_events_01 ON events (name_id);
+CREATE INDEX IF NOT EXISTS ix_events_02 ON events (bicmid_id);
+CREATE INDEX IF NOT EXISTS ix_events_03 ON events (session_id);
+CREATE INDEX IF NOT EXISTS ix_events_04 ON events (user_id);
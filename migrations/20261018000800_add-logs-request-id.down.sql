@@ -0,0 +1,3 @@
+-- down migration script
+DROP INDEX IF EXISTS ui_logs_01;
+ALTER TABLE logs DROP COLUMN request_id;
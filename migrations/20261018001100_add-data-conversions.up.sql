@@ -0,0 +1,10 @@
+-- up migration script
+-- table: `data_conversions`
+-- the one-off conversions of the stored data done in Rust, recorded by name when they are done,
+-- so that they run once instead of on every open of the database
+CREATE TABLE IF NOT EXISTS data_conversions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_data_conversions_01 ON data_conversions (value);
@@ -0,0 +1,3 @@
+-- down migration script
+DROP INDEX IF EXISTS ui_user_agent_requests_01;
+DROP TABLE IF EXISTS user_agent_requests;
@@ -0,0 +1,10 @@
+-- up migration script
+-- table: `user_agent_requests`
+-- the client-generated IDs of the `save_user_agent` requests;
+-- a retried request with the same ID is not stored again
+CREATE TABLE IF NOT EXISTS user_agent_requests (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    create_at TEXT NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_user_agent_requests_01 ON user_agent_requests (value);
@@ -0,0 +1,9 @@
+-- down migration script
+DROP TABLE IF EXISTS logs;
+
+DROP TABLE IF EXISTS jsinfos;
+DROP TABLE IF EXISTS user_agents;
+DROP TABLE IF EXISTS referrers;
+DROP TABLE IF EXISTS ip_addresses;
+DROP TABLE IF EXISTS bicmids;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,74 @@
+-- up migration script
+-- `create_at` is kept as UTC text, the same format as SQLite's CURRENT_TIMESTAMP
+-- table: `js_info`
+CREATE TABLE IF NOT EXISTS jsinfos (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    hash TEXT NOT NULL,
+    value TEXT NOT NULL
+);
+-- by `hash` only, since a long `value` exceeds the size limit of a btree index entry
+CREATE UNIQUE INDEX IF NOT EXISTS ui_jsinfos_01 ON jsinfos (hash);
+
+-- table: `user_agents`
+CREATE TABLE IF NOT EXISTS user_agents (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_user_agents_01 ON user_agents (value);
+INSERT INTO user_agents (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `referrers`
+CREATE TABLE IF NOT EXISTS referrers (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_referrers_01 ON referrers (value);
+INSERT INTO referrers (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `ip_addresses`
+CREATE TABLE IF NOT EXISTS ip_addresses (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_ip_addresses_01 ON ip_addresses (value);
+INSERT INTO ip_addresses (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `bicmids`
+CREATE TABLE IF NOT EXISTS bicmids (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_bicmids ON bicmids (value);
+INSERT INTO bicmids (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `users`
+CREATE TABLE IF NOT EXISTS users (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_users_01 ON users (value);
+INSERT INTO users (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `logs`
+CREATE TABLE IF NOT EXISTS logs (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    jsinfo_id BIGINT NOT NULL,
+    user_agent_id BIGINT NOT NULL,
+    referrer_id BIGINT NOT NULL,
+    ip_address_id BIGINT NOT NULL,
+    bicmid_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ix_logs_01 ON logs (jsinfo_id);
+CREATE INDEX IF NOT EXISTS ix_logs_02 ON logs (user_agent_id);
+CREATE INDEX IF NOT EXISTS ix_logs_03 ON logs (referrer_id);
+CREATE INDEX IF NOT EXISTS ix_logs_04 ON logs (ip_address_id);
+CREATE INDEX IF NOT EXISTS ix_logs_05 ON logs (bicmid_id);
+CREATE INDEX IF NOT EXISTS ix_logs_06 ON logs (user_id);
@@ -0,0 +1,2 @@
+-- down migration script
+ALTER TABLE logs DROP COLUMN ip_address_mode;
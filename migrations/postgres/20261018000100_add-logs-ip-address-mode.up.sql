@@ -0,0 +1,3 @@
+-- up migration script
+-- how the IP address of `ip_address_id` was anonymized: `full`, `truncate`, `hash` or `drop`
+ALTER TABLE logs ADD COLUMN ip_address_mode TEXT NOT NULL DEFAULT 'full';
@@ -0,0 +1,8 @@
+-- up migration script
+-- the GeoIP of `value`, resolved by the `geoip` feature; `geo_resolved_at` is NULL until resolved
+ALTER TABLE ip_addresses ADD COLUMN country_code TEXT;
+ALTER TABLE ip_addresses ADD COLUMN region_name TEXT;
+ALTER TABLE ip_addresses ADD COLUMN city_name TEXT;
+ALTER TABLE ip_addresses ADD COLUMN autonomous_system_number BIGINT;
+ALTER TABLE ip_addresses ADD COLUMN autonomous_system_organization TEXT;
+ALTER TABLE ip_addresses ADD COLUMN geo_resolved_at TEXT;
@@ -0,0 +1,12 @@
+-- up migration script
+-- the parsed `value`; `parsed_at` is NULL until parsed
+ALTER TABLE user_agents ADD COLUMN browser_name TEXT;
+ALTER TABLE user_agents ADD COLUMN browser_version TEXT;
+ALTER TABLE user_agents ADD COLUMN os_name TEXT;
+ALTER TABLE user_agents ADD COLUMN os_version TEXT;
+ALTER TABLE user_agents ADD COLUMN device_model TEXT;
+-- `desktop`, `mobile`, `tablet`, `bot` or `unknown`
+ALTER TABLE user_agents ADD COLUMN device_class TEXT;
+ALTER TABLE user_agents ADD COLUMN parsed_at TEXT;
+CREATE INDEX IF NOT EXISTS ix_user_agents_01 ON user_agents (browser_name);
+CREATE INDEX IF NOT EXISTS ix_user_agents_02 ON user_agents (os_name);
@@ -0,0 +1,5 @@
+-- down migration script
+DROP INDEX IF EXISTS ix_logs_07;
+ALTER TABLE logs DROP COLUMN fingerprint_id;
+
+DROP TABLE IF EXISTS fingerprints;
@@ -0,0 +1,13 @@
+-- up migration script
+-- table: `fingerprints`
+CREATE TABLE IF NOT EXISTS fingerprints (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_fingerprints_01 ON fingerprints (value);
+INSERT INTO fingerprints (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- the browser fingerprint of the visit; `0` if it is not enabled
+ALTER TABLE logs ADD COLUMN fingerprint_id BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS ix_logs_07 ON logs (fingerprint_id);
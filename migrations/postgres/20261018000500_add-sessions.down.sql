@@ -0,0 +1,5 @@
+-- down migration script
+DROP INDEX IF EXISTS ix_logs_08;
+ALTER TABLE logs DROP COLUMN session_id;
+
+DROP TABLE IF EXISTS sessions;
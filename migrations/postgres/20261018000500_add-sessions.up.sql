@@ -0,0 +1,19 @@
+-- up migration script
+-- table: `sessions`
+-- a visit of a BICMID, `value` is the session ID kept in `sessionStorage`, empty without it.
+-- a hit more than `session.timeout_secs` after `last_seen_at` starts another session.
+CREATE TABLE IF NOT EXISTS sessions (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    started_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    last_seen_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL,
+    bicmid_id BIGINT NOT NULL,
+    hits BIGINT NOT NULL DEFAULT 0
+);
+CREATE INDEX IF NOT EXISTS ix_sessions_01 ON sessions (bicmid_id, value);
+CREATE INDEX IF NOT EXISTS ix_sessions_02 ON sessions (started_at);
+INSERT INTO sessions (id, value, bicmid_id) VALUES (0, '', 0) ON CONFLICT DO NOTHING;
+
+-- the session of the visit; `0` without a session ID and a BICMID
+ALTER TABLE logs ADD COLUMN session_id BIGINT NOT NULL DEFAULT 0;
+CREATE INDEX IF NOT EXISTS ix_logs_08 ON logs (session_id);
@@ -0,0 +1,30 @@
+-- up migration script
+-- table: `paths`
+CREATE TABLE IF NOT EXISTS paths (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_paths_01 ON paths (value);
+INSERT INTO paths (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `page_views`
+-- a route of a single page app; `referrer_id` is the previous route, or `document.referrer` first
+CREATE TABLE IF NOT EXISTS page_views (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    path_id BIGINT NOT NULL,
+    title TEXT NOT NULL,
+    referrer_id BIGINT NOT NULL,
+    bicmid_id BIGINT NOT NULL,
+    session_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ix_page_views_01 ON page_views (path_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_02 ON page_views (referrer_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_03 ON page_views (bicmid_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_04 ON page_views (session_id);
+CREATE INDEX IF NOT EXISTS ix_page_views_05 ON page_views (user_id);
+
+-- the page views of the session, besides the visits of `hits`
+ALTER TABLE sessions ADD COLUMN page_views BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,26 @@
+-- up migration script
+-- table: `event_names`
+-- the normalized names of the events, ex.) `checkout_started`
+CREATE TABLE IF NOT EXISTS event_names (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_event_names_01 ON event_names (value);
+INSERT INTO event_names (id, value) VALUES (0, '') ON CONFLICT DO NOTHING;
+
+-- table: `events`
+-- a custom event of the application; `properties` is a JSON object
+CREATE TABLE IF NOT EXISTS events (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    name_id BIGINT NOT NULL,
+    properties TEXT NOT NULL,
+    bicmid_id BIGINT NOT NULL,
+    session_id BIGINT NOT NULL,
+    user_id BIGINT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS ix_events_01 ON events (name_id);
+CREATE INDEX IF NOT EXISTS ix_events_02 ON events (bicmid_id);
+CREATE INDEX IF NOT EXISTS ix_events_03 ON events (session_id);
+CREATE INDEX IF NOT EXISTS ix_events_04 ON events (user_id);
@@ -0,0 +1,5 @@
+-- up migration script
+-- the client-generated ID of the request of the visit, NULL without it;
+-- a retried request with the same ID is not stored again
+ALTER TABLE logs ADD COLUMN request_id TEXT;
+CREATE UNIQUE INDEX IF NOT EXISTS ui_logs_01 ON logs (request_id);
@@ -0,0 +1,4 @@
+-- up migration script
+-- the consent of the visit: `essential`, `analytics` or `full`;
+-- empty from a client without the consent management
+ALTER TABLE logs ADD COLUMN consent TEXT NOT NULL DEFAULT '';
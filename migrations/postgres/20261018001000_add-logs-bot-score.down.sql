@@ -0,0 +1,2 @@
+-- down migration script
+ALTER TABLE logs DROP COLUMN bot_score;
@@ -0,0 +1,3 @@
+-- up migration script
+-- the bot score of the visit computed by the server, from 0, a person, to 100, surely automated
+ALTER TABLE logs ADD COLUMN bot_score BIGINT NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- down migration script
+DROP INDEX IF EXISTS ui_data_conversions_01;
+DROP TABLE IF EXISTS data_conversions;
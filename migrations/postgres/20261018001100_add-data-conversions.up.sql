@@ -0,0 +1,10 @@
+-- up migration script
+-- table: `data_conversions`
+-- the one-off conversions of the stored data done in Rust, recorded by name when they are done,
+-- so that they run once instead of on every open of the database
+CREATE TABLE IF NOT EXISTS data_conversions (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_data_conversions_01 ON data_conversions (value);
@@ -0,0 +1,10 @@
+-- up migration script
+-- table: `user_agent_requests`
+-- the client-generated IDs of the `save_user_agent` requests;
+-- a retried request with the same ID is not stored again
+CREATE TABLE IF NOT EXISTS user_agent_requests (
+    id BIGINT GENERATED BY DEFAULT AS IDENTITY PRIMARY KEY,
+    create_at TEXT NOT NULL DEFAULT to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS'),
+    value TEXT NOT NULL
+);
+CREATE UNIQUE INDEX IF NOT EXISTS ui_user_agent_requests_01 ON user_agent_requests (value);
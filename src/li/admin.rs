@@ -0,0 +1,288 @@
+//! Admin dashboard for browsing the collected browser information.
+//! Renders the stored visits with filters and pagination, a per-BICMID drill-down
+//! and the numbers of visits by browser and by operating system, with the database path.
+
+use dioxus::prelude::*;
+
+use super::{get_db_path, query_log_stats, query_logs, LogCount, LogEntry, LogQuery};
+
+/// The number of visits per page in the dashboard.
+const ADMIN_PER_PAGE: u32 = 50;
+
+/// A Dioxus component that renders the admin dashboard.
+///
+/// The admin token is asked for first, and is sent with every query.
+/// It is kept only in the component's memory.
+#[component]
+pub fn BrowserInfoAdmin() -> Element {
+    let mut token_input = use_signal(String::new);
+    let mut token = use_signal(String::new);
+
+    if token.read().is_empty() {
+        return rsx! {
+            div {
+                h3 { "Browser Info Admin" }
+                form {
+                    onsubmit: move |e: FormEvent| {
+                        e.prevent_default();
+                        token.set(token_input.read().clone());
+                    },
+                    input {
+                        r#type: "password",
+                        placeholder: "admin token",
+                        value: "{token_input}",
+                        oninput: move |e| token_input.set(e.value()),
+                    }
+                    button { r#type: "submit", "Sign in" }
+                }
+            }
+        };
+    }
+
+    rsx! {
+        AdminDashboard { token }
+    }
+}
+
+/// The dashboard shown after the admin token is entered.
+#[component]
+fn AdminDashboard(mut token: Signal<String>) -> Element {
+    let mut filter = use_signal(LogQuery::default);
+    let mut page = use_signal(|| 0u32);
+    let mut selected_bicmid = use_signal(|| None::<String>);
+
+    let logs = use_resource(move || async move {
+        let query = LogQuery {
+            page: page(),
+            per_page: ADMIN_PER_PAGE,
+            ..filter()
+        };
+        query_logs(&token(), query).await
+    });
+    let stats = use_resource(move || async move { query_log_stats(&token(), filter()).await });
+    let db_path = use_resource(move || async move { get_db_path(&token()).await });
+    let db_path_s = match &*db_path.read() {
+        Some(Ok(s)) => s.clone(),
+        _ => String::new(),
+    };
+
+    let logs_view = match &*logs.read() {
+        None => rsx! { div { "Loading..." } },
+        Some(Err(e)) => rsx! {
+            div { "Failed to query logs: {e}" }
+            button { onclick: move |_| token.set(String::new()), "Sign out" }
+        },
+        Some(Ok(log_page)) => {
+            let page_count = log_page.page_count().max(1);
+            let page_no = log_page.page + 1;
+            let total = log_page.total;
+            let has_prev = log_page.page > 0;
+            let has_next = page_no < page_count;
+            rsx! {
+                div {
+                    button {
+                        disabled: !has_prev,
+                        onclick: move |_| page -= 1,
+                        "<"
+                    }
+                    " page {page_no} / {page_count} ({total} visits) "
+                    button {
+                        disabled: !has_next,
+                        onclick: move |_| page += 1,
+                        ">"
+                    }
+                }
+                table {
+                    thead {
+                        tr {
+                            th { "Time (UTC)" }
+                            th { "BICMID" }
+                            th { "User" }
+                            th { "IP Address" }
+                            th { "Referrer" }
+                            th { "User Agent" }
+                            th { "Bot" }
+                        }
+                    }
+                    tbody {
+                        {log_page.entries.iter().map(|entry| {
+                            let bicmid = entry.bicmid.clone();
+                            rsx! {
+                                tr { key: "{entry.id}",
+                                    td { "{entry.create_at}" }
+                                    td {
+                                        a {
+                                            href: "#",
+                                            onclick: move |e: MouseEvent| {
+                                                e.prevent_default();
+                                                selected_bicmid.set(Some(bicmid.clone()));
+                                            },
+                                            "{entry.bicmid}"
+                                        }
+                                    }
+                                    td { "{entry.user}" }
+                                    td { title: "{entry.ip_address_mode}", "{entry.ip_address}" }
+                                    td { "{entry.referrer}" }
+                                    td { "{entry.user_agent}" }
+                                    td { "{entry.bot_score}" }
+                                }
+                            }
+                        })}
+                    }
+                }
+            }
+        }
+    };
+
+    let stats_view = match &*stats.read() {
+        None => rsx! { div { "Loading..." } },
+        Some(Err(e)) => rsx! { div { "Failed to query statistics: {e}" } },
+        Some(Ok(stats)) => rsx! {
+            div { style: "display: flex; gap: 2em;",
+                CountTable { title: "Browser", counts: stats.browsers.clone() }
+                CountTable { title: "OS", counts: stats.oses.clone() }
+            }
+        },
+    };
+
+    rsx! {
+        div {
+            h3 { "Browser Info Admin" }
+            div { "Database: {db_path_s}" }
+            AdminFilter {
+                on_apply: move |q: LogQuery| {
+                    page.set(0);
+                    filter.set(q);
+                },
+            }
+            hr {}
+            h4 { "Visits by Browser / OS" }
+            {stats_view}
+            hr {}
+            h4 { "Visits" }
+            {logs_view}
+            if let Some(bicmid) = selected_bicmid() {
+                hr {}
+                BicmidDetail {
+                    token,
+                    bicmid,
+                    on_close: move |_| selected_bicmid.set(None),
+                }
+            }
+        }
+    }
+}
+
+/// The filter form of the dashboard.
+#[component]
+fn AdminFilter(on_apply: EventHandler<LogQuery>) -> Element {
+    let mut bicmid = use_signal(String::new);
+    let mut user = use_signal(String::new);
+    let mut since = use_signal(String::new);
+    let mut until = use_signal(String::new);
+
+    let non_empty = |s: String| if s.is_empty() { None } else { Some(s) };
+
+    rsx! {
+        form {
+            onsubmit: move |e: FormEvent| {
+                e.prevent_default();
+                on_apply
+                    .call(LogQuery {
+                        bicmid: non_empty(bicmid()),
+                        user: non_empty(user()),
+                        since: non_empty(since()),
+                        until: non_empty(until()),
+                        ..Default::default()
+                    });
+            },
+            input {
+                placeholder: "BICMID",
+                value: "{bicmid}",
+                oninput: move |e| bicmid.set(e.value()),
+            }
+            input {
+                placeholder: "user",
+                value: "{user}",
+                oninput: move |e| user.set(e.value()),
+            }
+            input {
+                placeholder: "since: YYYY-MM-DD HH:MM:SS",
+                value: "{since}",
+                oninput: move |e| since.set(e.value()),
+            }
+            input {
+                placeholder: "until: YYYY-MM-DD HH:MM:SS",
+                value: "{until}",
+                oninput: move |e| until.set(e.value()),
+            }
+            button { r#type: "submit", "Filter" }
+        }
+    }
+}
+
+/// A table of the numbers of visits.
+#[component]
+fn CountTable(title: String, counts: Vec<LogCount>) -> Element {
+    rsx! {
+        table {
+            thead {
+                tr {
+                    th { "{title}" }
+                    th { "Visits" }
+                }
+            }
+            tbody {
+                for c in counts.iter() {
+                    tr { key: "{c.name}",
+                        td { "{c.name}" }
+                        td { "{c.count}" }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The visits of one BICMID, with the decoded JS information.
+#[component]
+fn BicmidDetail(
+    token: Signal<String>,
+    bicmid: ReadSignal<String>,
+    on_close: EventHandler<()>,
+) -> Element {
+    let logs = use_resource(move || async move {
+        let query = LogQuery {
+            bicmid: Some(bicmid()),
+            per_page: ADMIN_PER_PAGE,
+            ..Default::default()
+        };
+        query_logs(&token(), query).await
+    });
+
+    let view = match &*logs.read() {
+        None => rsx! { div { "Loading..." } },
+        Some(Err(e)) => rsx! { div { "Failed to query logs: {e}" } },
+        Some(Ok(log_page)) => {
+            let total = log_page.total;
+            let entries: Vec<LogEntry> = log_page.entries.clone();
+            rsx! {
+                div { "{total} visits" }
+                for entry in entries.iter() {
+                    div { key: "{entry.id}",
+                        h5 { "{entry.create_at} / {entry.ip_address} / {entry.user_agent}" }
+                        pre { "{entry.jsinfo_pretty()}" }
+                    }
+                }
+            }
+        }
+    };
+
+    rsx! {
+        div {
+            h4 { "BICMID: {bicmid}" }
+            button { onclick: move |_| on_close.call(()), "Close" }
+            {view}
+        }
+    }
+}
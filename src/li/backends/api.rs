@@ -0,0 +1,544 @@
+//! Server functions of the `/api/v1` endpoints.
+//! Each one is a thin wrapper that hands the request to the configured `BroInfoStore`.
+//! The save endpoints are authenticated by `[api]`, and the admin ones by the admin token.
+
+use anyhow::Result;
+use dioxus::prelude::*;
+
+#[cfg(feature = "server")]
+use super::auth::{verify_admin_token, verify_api_request};
+
+#[cfg(feature = "server")]
+use super::{get_ip_address_string, peer_addr, rejected};
+
+#[cfg(feature = "server")]
+use dioxus::fullstack::StatusCode;
+
+#[cfg(feature = "server")]
+use super::store::{store, BroInfoStore};
+
+#[cfg(feature = "server")]
+use super::config::BackendConfig;
+
+#[cfg(feature = "server")]
+use super::fingerprint::Fingerprinter;
+
+#[cfg(feature = "server")]
+use super::limits::limiter;
+
+#[cfg(feature = "server")]
+use super::bot::{BotCheck, BotHeaders};
+
+#[cfg(feature = "server")]
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats};
+
+#[cfg(feature = "server")]
+use super::CollectionPolicy;
+
+#[cfg(feature = "server")]
+use super::{FingerprintGroup, FingerprintQuery, SaveBroInfoRequest, SaveBroInfoResponse};
+
+#[cfg(feature = "server")]
+use super::{normalize_event_name, ConsentLevel, EventRequest, PageViewRequest};
+
+#[cfg(feature = "server")]
+#[cfg(feature = "backend_user_agent")]
+use super::SaveUserAgentRequest;
+
+/// Server function to get the current database path as a string.
+///
+/// Requires the admin token.
+#[post("/api/v1/mikan1")]
+pub async fn get_db_path(admin_token: String) -> Result<String> {
+    verify_admin_token(&admin_token)?;
+    let db_path_s = store().db_path().await?;
+    dioxus::logger::tracing::debug!("db_path: {db_path_s:?}");
+    Ok(db_path_s)
+}
+
+/// Server function to check the health of the backend store.
+///
+/// Returns the kind of the store.
+#[post("/api/v1/health1")]
+pub async fn backend_health() -> Result<String> {
+    let store = store();
+    store.health().await?;
+    Ok(store.kind().to_string())
+}
+
+/// Server function to get the client's IP address.
+#[post(
+    "/api/v1/ringo1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn get_ip_address() -> Result<String> {
+    verify_api_request(&headers, &extensions, "/api/v1/ringo1")?;
+    let ipaddr = get_ip_address_string(&headers, peer_addr(&extensions));
+    dioxus::logger::tracing::debug!("ipaddr: {ipaddr:?}");
+    Ok(ipaddr)
+}
+
+/// Server function to save the user agent.
+#[cfg(feature = "backend_user_agent")]
+#[post(
+    "/api/v1/useragent1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn save_user_agent(req: super::SaveUserAgentRequest) -> Result<()> {
+    verify_api_request(&headers, &extensions, "/api/v1/useragent1")?;
+    handle_save_user_agent(store(), req).await
+}
+
+/// Server function to save full browser information.
+#[post(
+    "/api/v1/browserinfo1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn save_broinfo(req: super::SaveBroInfoRequest) -> Result<super::SaveBroInfoResponse> {
+    verify_api_request(&headers, &extensions, "/api/v1/browserinfo1")?;
+    let ip_address = get_ip_address_string(&headers, peer_addr(&extensions));
+    limiter().check_broinfo(&req, &ip_address, std::time::Instant::now())?;
+    let cfg = BackendConfig::global();
+    let fingerprinter = Fingerprinter::from_config(&cfg.fingerprint)?;
+    let policy = cfg.collection.policy()?;
+    let bot = BotCheck {
+        headers: BotHeaders::from_headers(&headers),
+        skip_threshold: cfg.bot.skip_threshold,
+    };
+    let fingerprinter = fingerprinter.as_ref();
+    handle_save_broinfo(store(), req, &ip_address, fingerprinter, &policy, &bot).await
+}
+
+/// Server function to get the collection policy of the JS information.
+///
+/// Returns an empty policy, collecting everything, unless `collection.push_to_client` is set.
+/// The server applies the policy anyway.
+#[post(
+    "/api/v1/policy1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn get_collection_policy() -> Result<super::CollectionPolicy> {
+    verify_api_request(&headers, &extensions, "/api/v1/policy1")?;
+    let cfg = &BackendConfig::global().collection;
+    if !cfg.push_to_client {
+        return Ok(CollectionPolicy::default());
+    }
+    cfg.policy()
+}
+
+/// Server function to save one page view.
+#[post(
+    "/api/v1/pageview1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn save_pageview(req: super::PageViewRequest) -> Result<()> {
+    verify_api_request(&headers, &extensions, "/api/v1/pageview1")?;
+    let ip_address = get_ip_address_string(&headers, peer_addr(&extensions));
+    limiter().check_pageview(&req, &ip_address, std::time::Instant::now())?;
+    handle_save_pageview(store(), req).await
+}
+
+/// Server function to save one custom event.
+#[post(
+    "/api/v1/event1",
+    headers: dioxus::fullstack::HeaderMap,
+    extensions: dioxus::fullstack::http::Extensions
+)]
+pub async fn save_event(req: super::EventRequest) -> Result<()> {
+    verify_api_request(&headers, &extensions, "/api/v1/event1")?;
+    let ip_address = get_ip_address_string(&headers, peer_addr(&extensions));
+    limiter().check_event(&req, &ip_address, std::time::Instant::now())?;
+    handle_save_event(store(), req).await
+}
+
+/// Server function to query the stored visits, newest first.
+///
+/// Requires the admin token.
+#[post("/api/v1/logs1")]
+pub async fn query_logs(admin_token: String, query: super::LogQuery) -> Result<super::LogPage> {
+    handle_query_logs(store(), &admin_token, query).await
+}
+
+/// Server function to count the stored visits by browser and by operating system.
+///
+/// Requires the admin token.
+#[post("/api/v1/logstats1")]
+pub async fn query_log_stats(
+    admin_token: String,
+    query: super::LogQuery,
+) -> Result<super::LogStats> {
+    handle_query_log_stats(store(), &admin_token, query).await
+}
+
+/// Server function to get the numbers of the save requests accepted and rejected by `[limits]`.
+///
+/// Requires the admin token. They are of this server, not of the next one of a forwarder.
+#[post("/api/v1/metrics1")]
+pub async fn query_save_metrics(admin_token: String) -> Result<super::SaveMetrics> {
+    verify_admin_token(&admin_token)?;
+    Ok(limiter().metrics())
+}
+
+/// Server function to delete everything stored about one BICMID or one user.
+///
+/// Requires the admin token.
+#[post("/api/v1/erase1")]
+pub async fn erase_subject(
+    admin_token: String,
+    subject: super::EraseSubject,
+) -> Result<super::EraseReport> {
+    handle_erase_subject(store(), &admin_token, subject).await
+}
+
+/// Server function to list the BICMIDs that share a browser fingerprint.
+///
+/// Requires the admin token.
+#[post("/api/v1/fingerprints1")]
+pub async fn query_fingerprints(
+    admin_token: String,
+    query: super::FingerprintQuery,
+) -> Result<Vec<super::FingerprintGroup>> {
+    handle_query_fingerprints(store(), &admin_token, query).await
+}
+
+#[cfg(feature = "server")]
+#[cfg(feature = "backend_user_agent")]
+async fn handle_save_user_agent(store: &dyn BroInfoStore, req: SaveUserAgentRequest) -> Result<()> {
+    let ua_s = req.ua.get();
+    //
+    #[cfg(feature = "backend_text")]
+    write_backend_text("user_agent.txt", ua_s)?;
+    //
+    store.save_user_agent(&req).await?;
+    //
+    dioxus::logger::tracing::debug!("save_user_agent: {ua_s:?}");
+    //
+    #[cfg(feature = "backend_delay")]
+    let _ = sleep_x(2000).await;
+    //
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn handle_save_broinfo(
+    store: &dyn BroInfoStore,
+    req: SaveBroInfoRequest,
+    ip_address: &str,
+    fingerprinter: Option<&Fingerprinter>,
+    policy: &CollectionPolicy,
+    bot: &BotCheck,
+) -> Result<SaveBroInfoResponse> {
+    let consent = req.consent_level();
+    if consent == ConsentLevel::None {
+        return Err(rejected(
+            StatusCode::BAD_REQUEST,
+            "the user does not consent to saving the visit",
+        ));
+    }
+    let mut req = req;
+    // scores what the browser sent, before the consent and the policy drop some of it
+    req.bot_score = bot.score(&req);
+    req.apply_consent();
+    policy.apply(&mut req.broinfo.jsinfo);
+    //
+    #[cfg(feature = "backend_text")]
+    write_backend_text("jsinfo.txt", &toml::to_string(&req.broinfo.jsinfo)?)?;
+    //
+    if bot.skips(req.bot_score) {
+        dioxus::logger::tracing::debug!("save_broinfo: skips a bot: {}", req.bot_score);
+    } else {
+        store.save_broinfo(&req, ip_address).await?;
+    }
+    //
+    #[cfg(feature = "backend_delay")]
+    let _ = sleep_x(2000).await;
+    //
+    let browser = if req.return_browser {
+        Some(req.broinfo.to_browser()?)
+    } else {
+        None
+    };
+    let fingerprint = fingerprinter
+        .filter(|_| consent.allows_fingerprint())
+        .map(|f| f.compute(&req.broinfo.jsinfo))
+        .unwrap_or_default();
+    Ok(SaveBroInfoResponse {
+        browser,
+        fingerprint,
+        bot_score: req.bot_score,
+    })
+}
+
+#[cfg(feature = "server")]
+async fn handle_save_pageview(store: &dyn BroInfoStore, req: PageViewRequest) -> Result<()> {
+    if req.path.is_empty() {
+        return Err(rejected(
+            StatusCode::BAD_REQUEST,
+            "the path of the page view is empty",
+        ));
+    }
+    store.save_pageview(&req).await?;
+    dioxus::logger::tracing::debug!("save_pageview: {:?}", req.path);
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn handle_save_event(store: &dyn BroInfoStore, req: EventRequest) -> Result<()> {
+    let name = normalize_event_name(&req.name);
+    if name.is_empty() {
+        return Err(rejected(
+            StatusCode::BAD_REQUEST,
+            "the name of the event is empty",
+        ));
+    }
+    // checks the properties before the store
+    let _ = req.properties_json()?;
+    let req = EventRequest { name, ..req };
+    store.save_event(&req).await?;
+    dioxus::logger::tracing::debug!("save_event: {:?}", req.name);
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+async fn handle_query_logs(
+    store: &dyn BroInfoStore,
+    admin_token: &str,
+    query: LogQuery,
+) -> Result<LogPage> {
+    verify_admin_token(admin_token)?;
+    let page = store.query_logs(&query).await?;
+    dioxus::logger::tracing::debug!("query_logs: {query:?}: {} entries", page.entries.len());
+    Ok(page)
+}
+
+#[cfg(feature = "server")]
+async fn handle_query_log_stats(
+    store: &dyn BroInfoStore,
+    admin_token: &str,
+    query: LogQuery,
+) -> Result<LogStats> {
+    verify_admin_token(admin_token)?;
+    let stats = store.query_log_stats(&query).await?;
+    dioxus::logger::tracing::debug!("query_log_stats: {query:?}");
+    Ok(stats)
+}
+
+#[cfg(feature = "server")]
+async fn handle_erase_subject(
+    store: &dyn BroInfoStore,
+    admin_token: &str,
+    subject: EraseSubject,
+) -> Result<EraseReport> {
+    verify_admin_token(admin_token)?;
+    if subject.value().is_empty() {
+        anyhow::bail!("the subject of the erasure is empty");
+    }
+    let report = store.erase_subject(&subject).await?;
+    dioxus::logger::tracing::info!("erase_subject: {report:?}");
+    Ok(report)
+}
+
+#[cfg(feature = "server")]
+async fn handle_query_fingerprints(
+    store: &dyn BroInfoStore,
+    admin_token: &str,
+    query: FingerprintQuery,
+) -> Result<Vec<FingerprintGroup>> {
+    verify_admin_token(admin_token)?;
+    let groups = store.query_fingerprints(&query).await?;
+    dioxus::logger::tracing::debug!("query_fingerprints: {query:?}: {} groups", groups.len());
+    Ok(groups)
+}
+
+/// Appends data to a text file. Used when `backend_text` feature is enabled.
+#[cfg(feature = "backend_text")]
+#[cfg(feature = "server")]
+fn write_backend_text(fnm: &str, data: &str) -> Result<()> {
+    use std::io::Write;
+    //
+    // Open file in append-only mode, creating it if it doesn't exist;
+    let mut file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(fnm)?;
+    // And then write a newline to it with data
+    let _ = file.write_fmt(format_args!("{data}\n"));
+    Ok(())
+}
+
+/// Sleeps for a specified duration. Used for testing delays when `backend_delay` is enabled.
+#[cfg(feature = "backend_delay")]
+#[cfg(feature = "server")]
+async fn sleep_x(millis: u64) -> Result<()> {
+    use std::time::Duration;
+    async_sleep_aki::async_sleep(Duration::from_millis(millis)).await;
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+#[cfg(test)]
+mod test {
+    use super::super::db_sqlite::SqliteStore;
+    use super::super::FieldAction;
+    use super::*;
+
+    #[tokio::test]
+    async fn test_handle_save_broinfo_00() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let all = CollectionPolicy::default();
+        let bot = BotCheck::default();
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let mut req = SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            return_browser: true,
+            ..Default::default()
+        };
+        req.broinfo.basic.user_agent = firefox.into();
+        //
+        let res = handle_save_broinfo(&store, req.clone(), "192.0.2.1", None, &all, &bot)
+            .await
+            .unwrap();
+        assert_eq!(res.browser.unwrap().name, "Firefox");
+        assert_eq!(res.fingerprint, "");
+        req.return_browser = false;
+        let fingerprinter = Fingerprinter::new("secret").unwrap();
+        let res = handle_save_broinfo(&store, req, "192.0.2.1", Some(&fingerprinter), &all, &bot)
+            .await
+            .unwrap();
+        assert_eq!(res.browser, None);
+        assert_eq!(res.fingerprint.len(), 43);
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries[0].bicmid, "b1");
+        assert_eq!(page.entries[0].ip_address, "192.0.2.1");
+        assert_eq!(page.entries[0].user_agent, firefox);
+    }
+
+    #[tokio::test]
+    async fn test_handle_save_event_01() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let req = |name: &str, properties: serde_json::Value| EventRequest {
+            name: name.to_string(),
+            properties,
+            bicmid: "b1".to_string(),
+            ..Default::default()
+        };
+        let props = serde_json::json!({"plan": "pro"});
+        handle_save_event(&store, req("Checkout Started", props.clone()))
+            .await
+            .unwrap();
+        handle_save_event(&store, req("video_played", serde_json::Value::Null))
+            .await
+            .unwrap();
+        assert!(handle_save_event(&store, req(" - ", props)).await.is_err());
+        let bad = serde_json::json!("not an object");
+        assert!(handle_save_event(&store, req("x", bad)).await.is_err());
+        //
+        let report = store
+            .erase_subject(&EraseSubject::Bicmid("b1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(report.events, 2);
+    }
+
+    #[tokio::test]
+    async fn test_handle_save_broinfo_consent_02() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let all = CollectionPolicy::default();
+        let bot = BotCheck::default();
+        let fingerprinter = Fingerprinter::new("secret").unwrap();
+        let mut req = SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            user: "u1".to_string(),
+            consent: Some(ConsentLevel::None),
+            ..Default::default()
+        };
+        let r = handle_save_broinfo(
+            &store,
+            req.clone(),
+            "192.0.2.1",
+            Some(&fingerprinter),
+            &all,
+            &bot,
+        )
+        .await;
+        assert!(r.is_err());
+        //
+        req.consent = Some(ConsentLevel::Essential);
+        let res = handle_save_broinfo(
+            &store,
+            req.clone(),
+            "192.0.2.1",
+            Some(&fingerprinter),
+            &all,
+            &bot,
+        )
+        .await
+        .unwrap();
+        assert_eq!(res.fingerprint, "");
+        req.consent = Some(ConsentLevel::Analytics);
+        let res = handle_save_broinfo(&store, req, "192.0.2.1", Some(&fingerprinter), &all, &bot)
+            .await
+            .unwrap();
+        assert_eq!(res.fingerprint, "");
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries[0].bicmid, "b1");
+        assert_eq!(page.entries[1].bicmid, "");
+        assert_eq!(page.entries[1].user, "");
+    }
+
+    #[tokio::test]
+    async fn test_handle_save_broinfo_policy_03() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut req = SaveBroInfoRequest::default();
+        req.broinfo.jsinfo.screen_width = Some(1366);
+        req.broinfo.jsinfo.timezone = "Asia/Tokyo".to_string();
+        req.broinfo.jsinfo.oscpu = "Linux x86_64".to_string();
+        let fields = [
+            ("screen_width".to_string(), FieldAction::Coarsen),
+            ("oscpu".to_string(), FieldAction::Drop),
+        ];
+        let policy = CollectionPolicy::new(fields.into_iter().collect()).unwrap();
+        let bot = BotCheck::default();
+        handle_save_broinfo(&store, req, "192.0.2.1", None, &policy, &bot)
+            .await
+            .unwrap();
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        let jsinfo = page.entries[0].parse_jsinfo().unwrap();
+        assert_eq!(jsinfo.screen_width, Some(1400));
+        assert_eq!(jsinfo.timezone, "Asia/Tokyo");
+        assert_eq!(jsinfo.oscpu, "");
+    }
+
+    #[tokio::test]
+    async fn test_handle_save_broinfo_bot_04() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let all = CollectionPolicy::default();
+        let mut bot = BotCheck::default();
+        let mut req = SaveBroInfoRequest::default();
+        req.broinfo.basic.user_agent = "Mozilla/5.0 (X11; Linux x86_64) HeadlessChrome/120".into();
+        req.automation.webdriver = true;
+        let res = handle_save_broinfo(&store, req.clone(), "192.0.2.1", None, &all, &bot)
+            .await
+            .unwrap();
+        assert_eq!(res.bot_score, 100);
+        bot.skip_threshold = 90;
+        let res = handle_save_broinfo(&store, req, "192.0.2.1", None, &all, &bot)
+            .await
+            .unwrap();
+        assert_eq!(res.bot_score, 100);
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].bot_score, 100);
+    }
+}
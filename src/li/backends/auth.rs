@@ -0,0 +1,219 @@
+//! Authentication of the admin API and of the save endpoints.
+//! Admin callers present the admin token configured in `BackendConfig`. With `[api]`, the save
+//! endpoints require the API key, or a request signed with the HMAC-SHA256 of its timestamp,
+//! its path and its raw body, as captured by `capture_body` before the body is deserialized.
+
+use anyhow::Result;
+use dioxus::fullstack::axum::middleware::Next;
+use dioxus::fullstack::body::{to_bytes, Body, Bytes};
+use dioxus::fullstack::extract::Request;
+use dioxus::fullstack::http::Extensions;
+use dioxus::fullstack::response::Response;
+use dioxus::fullstack::{HeaderMap, Method, StatusCode};
+
+use super::config::{ApiConfig, BackendConfig};
+use super::{rejected, rejected_response};
+
+/// The header of the API key.
+pub const API_KEY_HEADER: &str = "x-api-key";
+
+/// The header of the signature timestamp, in seconds since the Unix epoch.
+pub const API_TIMESTAMP_HEADER: &str = "x-api-timestamp";
+
+/// The header of the signature.
+pub const API_SIGNATURE_HEADER: &str = "x-api-signature";
+
+/// The paths of the endpoints authenticated by `[api]`.
+const API_PATHS: &[&str] = &[
+    "/api/v1/browserinfo1",
+    "/api/v1/useragent1",
+    "/api/v1/pageview1",
+    "/api/v1/event1",
+    "/api/v1/ringo1",
+    "/api/v1/policy1",
+];
+
+/// The maximum size of a captured body, the default body limit of axum.
+const RAW_BODY_MAX: usize = 2 * 1024 * 1024;
+
+/// The raw body of a request, as it was signed.
+#[derive(Debug, Clone)]
+pub struct RawBody(pub Bytes);
+
+/// Verifies the admin token presented by a caller.
+///
+/// Fails when the token does not match, or when no admin token is configured.
+pub fn verify_admin_token(token: &str) -> Result<()> {
+    let cfg = super::config::BackendConfig::global();
+    if cfg.admin.token.is_empty() {
+        return Err(rejected(StatusCode::FORBIDDEN, "the admin API is disabled"));
+    }
+    if !token_eq(&cfg.admin.token, token) {
+        return Err(rejected(StatusCode::UNAUTHORIZED, "unauthorized"));
+    }
+    Ok(())
+}
+
+/// Verifies that a request to the save endpoint `path` is authenticated as `[api]` requires.
+/// Any request passes if neither the API key nor the secret is configured.
+///
+/// The signature is verified against the `RawBody` in `extensions`; without it, only the API key
+/// authenticates.
+pub fn verify_api_request(headers: &HeaderMap, extensions: &Extensions, path: &str) -> Result<()> {
+    let cfg = &BackendConfig::global().api;
+    if cfg.is_open() {
+        return Ok(());
+    }
+    let body = extensions.get::<RawBody>().map(|b| b.0.as_ref());
+    verify_api_headers(cfg, headers, path, body, unix_now())
+}
+
+/// The middleware that keeps the raw body of a request to an endpoint authenticated by `[api]`
+/// in its extensions as `RawBody`, so that its signature is verified against the bytes sent,
+/// not against the arguments deserialized from them.
+pub async fn capture_body(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if BackendConfig::global().api.is_open()
+        || req.method() != Method::POST
+        || !API_PATHS.iter().any(|p| path.ends_with(p))
+    {
+        return next.run(req).await;
+    }
+    let (mut parts, body) = req.into_parts();
+    let Ok(bytes) = to_bytes(body, RAW_BODY_MAX).await else {
+        return rejected_response(StatusCode::PAYLOAD_TOO_LARGE, "the request is too large");
+    };
+    parts.extensions.insert(RawBody(bytes.clone()));
+    next.run(Request::from_parts(parts, Body::from(bytes)))
+        .await
+}
+
+/// Verifies the API key, or else the signature, in `headers` at the time `now`.
+fn verify_api_headers(
+    cfg: &ApiConfig,
+    headers: &HeaderMap,
+    path: &str,
+    body: Option<&[u8]>,
+    now: u64,
+) -> Result<()> {
+    let header = |name: &str| {
+        headers
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+    };
+    if !cfg.key.is_empty() && token_eq(&cfg.key, header(API_KEY_HEADER)) {
+        return Ok(());
+    }
+    let signature = header(API_SIGNATURE_HEADER);
+    if let Some(body) = body.filter(|_| !cfg.secret.is_empty() && !signature.is_empty()) {
+        let timestamp: u64 = header(API_TIMESTAMP_HEADER)
+            .parse()
+            .map_err(|_| rejected(StatusCode::UNAUTHORIZED, "invalid signature timestamp"))?;
+        if timestamp.abs_diff(now) > cfg.max_skew_secs {
+            let msg = "the signature timestamp is out of range";
+            return Err(rejected(StatusCode::UNAUTHORIZED, msg));
+        }
+        if token_eq(
+            &sign_api_request(&cfg.secret, timestamp, path, body),
+            signature,
+        ) {
+            return Ok(());
+        }
+    }
+    Err(rejected(StatusCode::UNAUTHORIZED, "unauthorized"))
+}
+
+/// Signs a request: the HMAC-SHA256 keyed with `secret` of `"{timestamp}\n{path}\n"`
+/// followed by the raw `body`, encoded in Base64.
+pub fn sign_api_request(secret: &str, timestamp: u64, path: &str, body: &[u8]) -> String {
+    use base64::Engine;
+
+    let mut msg = format!("{timestamp}\n{path}\n").into_bytes();
+    msg.extend_from_slice(body);
+    let mac = hmac_sha256::HMAC::mac(&msg, secret.as_bytes());
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac)
+}
+
+/// Returns the seconds since the Unix epoch.
+pub fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Compares two tokens in constant time, by comparing their SHA-256 hashes.
+fn token_eq(expected: &str, given: &str) -> bool {
+    let a = hmac_sha256::Hash::hash(expected.as_bytes());
+    let b = hmac_sha256::Hash::hash(given.as_bytes());
+    a.iter()
+        .zip(b.iter())
+        .fold(0u8, |acc, (x, y)| acc | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_token_eq_00() {
+        assert!(token_eq("secret", "secret"));
+        assert!(!token_eq("secret", "secreT"));
+        assert!(!token_eq("secret", ""));
+    }
+
+    #[test]
+    fn test_verify_api_headers_01() {
+        use dioxus::fullstack::http::HeaderValue;
+
+        let cfg = ApiConfig {
+            key: "key1".to_string(),
+            secret: "secret1".to_string(),
+            max_skew_secs: 300,
+        };
+        let path = "/api/v1/browserinfo1";
+        let body = Some(br#"{"req":{"user":"u1","bicmid":"b1"}}"#.as_slice());
+        let mut headers = HeaderMap::new();
+        assert!(verify_api_headers(&cfg, &headers, path, body, 1000).is_err());
+        // the API key
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("key1"));
+        assert!(verify_api_headers(&cfg, &headers, path, body, 1000).is_ok());
+        assert!(verify_api_headers(&cfg, &headers, path, None, 1000).is_ok());
+        headers.insert(API_KEY_HEADER, HeaderValue::from_static("key2"));
+        assert!(verify_api_headers(&cfg, &headers, path, body, 1000).is_err());
+        // the signature
+        let sig = sign_api_request("secret1", 1000, path, body.unwrap());
+        headers.insert(API_TIMESTAMP_HEADER, HeaderValue::from_static("1000"));
+        headers.insert(API_SIGNATURE_HEADER, HeaderValue::from_str(&sig).unwrap());
+        assert!(verify_api_headers(&cfg, &headers, path, body, 1100).is_ok());
+        assert!(verify_api_headers(&cfg, &headers, path, body, 1400).is_err());
+        assert!(verify_api_headers(&cfg, &headers, "/api/v1/event1", body, 1100).is_err());
+        // without the raw body
+        assert!(verify_api_headers(&cfg, &headers, path, None, 1100).is_err());
+    }
+
+    #[test]
+    fn test_raw_body_02() {
+        use dioxus::fullstack::http::HeaderValue;
+
+        let cfg = ApiConfig {
+            key: String::new(),
+            secret: "secret1".to_string(),
+            max_skew_secs: 300,
+        };
+        let path = "/api/v1/browserinfo1";
+        let sent = br#"{"req":{"user":"u1","bicmid":"b1"}}"#;
+        let mut headers = HeaderMap::new();
+        let sig = sign_api_request("secret1", 1000, path, sent);
+        headers.insert(API_TIMESTAMP_HEADER, HeaderValue::from_static("1000"));
+        headers.insert(API_SIGNATURE_HEADER, HeaderValue::from_str(&sig).unwrap());
+        assert!(verify_api_headers(&cfg, &headers, path, Some(sent), 1000).is_ok());
+        // the same arguments in other bytes are not what was signed
+        let reordered = br#"{"req":{"bicmid":"b1","user":"u1"}}"#;
+        assert!(verify_api_headers(&cfg, &headers, path, Some(reordered), 1000).is_err());
+        let spaced = br#"{"req": {"user":"u1","bicmid":"b1"}}"#;
+        assert!(verify_api_headers(&cfg, &headers, path, Some(spaced), 1000).is_err());
+    }
+}
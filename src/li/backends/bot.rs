@@ -0,0 +1,199 @@
+//! The bot score of a visit, computed on the server from the user agent, the JS information,
+//! the automation signals of the browser and the request headers.
+//! It is from `0`, a browser used by a person, to `BOT_SCORE_MAX`, surely automated, and is
+//! stored in `logs.bot_score`. A visit at `bot.skip_threshold` or more is not stored.
+
+use dioxus::fullstack::HeaderMap;
+
+use super::SaveBroInfoRequest;
+
+/// The maximum bot score.
+pub const BOT_SCORE_MAX: u8 = 100;
+
+/// The user agent tokens of the automated browsers.
+const AUTOMATION_TOKENS: [&str; 6] = [
+    "headless",
+    "selenium",
+    "webdriver",
+    "puppeteer",
+    "playwright",
+    "phantomjs",
+];
+
+/// The user agent tokens of the crawlers and the HTTP libraries.
+const CRAWLER_TOKENS: [&str; 13] = [
+    "bot",
+    "crawl",
+    "spider",
+    "slurp",
+    "curl/",
+    "wget/",
+    "python",
+    "go-http-client",
+    "java/",
+    "okhttp",
+    "httpclient",
+    "scrapy",
+    "node-fetch",
+];
+
+/// The request headers that the bot score reads.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct BotHeaders {
+    /// The `User-Agent` header, empty from the forwarder.
+    pub user_agent: String,
+    /// The `Sec-CH-UA` client hint.
+    pub sec_ch_ua: String,
+}
+
+impl BotHeaders {
+    pub fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: &str| {
+            headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .unwrap_or("")
+                .to_string()
+        };
+        Self {
+            user_agent: header("user-agent"),
+            sec_ch_ua: header("sec-ch-ua"),
+        }
+    }
+}
+
+/// The bot scoring of the visits.
+#[derive(Debug, Default, Clone)]
+pub struct BotCheck {
+    /// The headers of the request.
+    pub headers: BotHeaders,
+    /// A visit at this score or more is not stored. `0` stores every visit.
+    pub skip_threshold: u8,
+}
+
+impl BotCheck {
+    /// Returns whether a visit of `score` is not stored.
+    pub fn skips(&self, score: u8) -> bool {
+        self.skip_threshold > 0 && score >= self.skip_threshold
+    }
+
+    /// Returns the bot score of the visit `req`, the sum of the weights of its signals.
+    pub fn score(&self, req: &SaveBroInfoRequest) -> u8 {
+        let ua = req.broinfo.basic.user_agent.get();
+        let ua_lc = ua.to_ascii_lowercase();
+        let has = |s: &str, pats: &[&str]| pats.iter().any(|p| s.contains(p));
+        let jsinfo = &req.broinfo.jsinfo;
+        let mobile = has(&ua_lc, &["mobi", "android", "iphone", "ipad"]);
+        let header_ua = &self.headers.user_agent;
+        let signals = [
+            (ua.is_empty(), 40),
+            (has(&ua_lc, &AUTOMATION_TOKENS), 60),
+            (has(&ua_lc, &CRAWLER_TOKENS), 60),
+            (req.automation.webdriver, 80),
+            (req.automation.plugins == Some(0) && !mobile, 20),
+            (
+                jsinfo.screen_width == Some(0) || jsinfo.screen_height == Some(0),
+                30,
+            ),
+            (platform_mismatch(&ua_lc, &jsinfo.platform), 30),
+            (!header_ua.is_empty() && header_ua != ua, 30),
+            (
+                self.headers
+                    .sec_ch_ua
+                    .to_ascii_lowercase()
+                    .contains("headless"),
+                60,
+            ),
+        ];
+        let sum: u32 = signals.iter().filter(|(b, _)| *b).map(|(_, w)| w).sum();
+        sum.min(BOT_SCORE_MAX as u32) as u8
+    }
+}
+
+/// Returns whether `platform` of `navigator.platform` is of another OS than the lowercase `ua`.
+fn platform_mismatch(ua: &str, platform: &str) -> bool {
+    const OSES: [(&str, &[&str]); 6] = [
+        ("windows", &["win"]),
+        ("android", &["linux", "android"]),
+        ("iphone", &["iphone", "ipod", "ipad"]),
+        // iPadOS says it is a Mac
+        ("ipad", &["ipad", "macintel"]),
+        ("mac os x", &["mac"]),
+        ("linux", &["linux"]),
+    ];
+    if platform.is_empty() {
+        return false;
+    }
+    let platform = platform.to_ascii_lowercase();
+    OSES.iter()
+        .find(|(token, _)| ua.contains(token))
+        .is_some_and(|(_, prefixes)| !prefixes.iter().any(|p| platform.starts_with(p)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const CHROME: &str = concat!(
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36",
+        " (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36"
+    );
+
+    fn req(ua: &str, platform: &str) -> SaveBroInfoRequest {
+        let mut req = SaveBroInfoRequest::default();
+        req.broinfo.basic.user_agent = ua.into();
+        req.broinfo.jsinfo.platform = platform.to_string();
+        req.broinfo.jsinfo.screen_width = Some(1920);
+        req.broinfo.jsinfo.screen_height = Some(1080);
+        req.automation.plugins = Some(5);
+        req
+    }
+
+    #[test]
+    fn test_score_00() {
+        let check = BotCheck {
+            headers: BotHeaders {
+                user_agent: CHROME.to_string(),
+                sec_ch_ua: r#""Chromium";v="120""#.to_string(),
+            },
+            skip_threshold: 0,
+        };
+        assert_eq!(check.score(&req(CHROME, "Win32")), 0);
+        // inconsistent platform
+        assert_eq!(check.score(&req(CHROME, "Linux x86_64")), 30);
+        // headless, with a zero screen and no plugins
+        let headless = CHROME.replace("Chrome/", "HeadlessChrome/");
+        let mut r = req(&headless, "Win32");
+        r.broinfo.jsinfo.screen_width = Some(0);
+        r.automation.plugins = Some(0);
+        assert_eq!(check.score(&r), 100);
+        // webdriver
+        let mut r = req(CHROME, "Win32");
+        r.automation.webdriver = true;
+        assert_eq!(check.score(&r), 80);
+        // a script with a copied user agent
+        let check = BotCheck {
+            headers: BotHeaders {
+                user_agent: "python-requests/2.31".to_string(),
+                ..Default::default()
+            },
+            skip_threshold: 50,
+        };
+        assert_eq!(check.score(&req(CHROME, "Win32")), 30);
+        assert!(!check.skips(30));
+        assert!(check.skips(60));
+        assert!(!BotCheck::default().skips(100));
+    }
+
+    #[test]
+    fn test_platform_mismatch_01() {
+        let ipad = "mozilla/5.0 (ipad; cpu os 17_0 like mac os x) applewebkit/605.1.15";
+        assert!(!platform_mismatch(ipad, "MacIntel"));
+        assert!(!platform_mismatch(ipad, "iPad"));
+        assert!(platform_mismatch(ipad, "Win32"));
+        let android = "mozilla/5.0 (linux; android 14; pixel 8) applewebkit/537.36";
+        assert!(!platform_mismatch(android, "Linux armv8l"));
+        assert!(!platform_mismatch("curl/8.0", "Win32"));
+        assert!(!platform_mismatch(ipad, ""));
+    }
+}
@@ -0,0 +1,292 @@
+//! Extraction of the client's IP address.
+//! The forwarding headers are believed only as far as they were appended by `proxy.trusted_proxies`:
+//! the hops are walked from the socket peer leftwards, and the first untrusted one is the client.
+
+use anyhow::{bail, Context, Result};
+use dioxus::fullstack::HeaderMap;
+use serde::{Deserialize, Deserializer};
+use std::net::{IpAddr, SocketAddr};
+use std::str::FromStr;
+
+/// A network in CIDR notation, ex.) `10.0.0.0/8`, `fd00::/8`. A bare address is a single host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpCidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl IpCidr {
+    /// Returns `true` if `ip` is in this network.
+    /// An IPv4-mapped IPv6 address is matched as IPv4.
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.addr, canonical(ip)) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                prefix_eq(&net.octets(), &ip.octets(), self.prefix)
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for IpCidr {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let s = s.trim();
+        let (addr_s, prefix_s) = match s.split_once('/') {
+            Some((a, p)) => (a, Some(p)),
+            None => (s, None),
+        };
+        let addr = canonical(
+            addr_s
+                .parse::<IpAddr>()
+                .with_context(|| format!("invalid trusted proxy: {s:?}"))?,
+        );
+        let max = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix_s {
+            Some(p) => p
+                .parse::<u8>()
+                .with_context(|| format!("invalid trusted proxy: {s:?}"))?,
+            None => max,
+        };
+        if prefix > max {
+            bail!("invalid trusted proxy: {s:?}");
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+/// Compares the first `prefix` bits.
+fn prefix_eq(a: &[u8], b: &[u8], prefix: u8) -> bool {
+    let n = usize::from(prefix / 8);
+    let rest = prefix % 8;
+    if a[..n] != b[..n] {
+        return false;
+    }
+    rest == 0 || (a[n] ^ b[n]) >> (8 - rest) == 0
+}
+
+fn canonical(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V6(v6) => v6
+            .to_ipv4_mapped()
+            .map(IpAddr::V4)
+            .unwrap_or(IpAddr::V6(v6)),
+        v4 => v4,
+    }
+}
+
+/// Deserializes `proxy.trusted_proxies` from a list, or from a comma separated string
+/// as given by the environment variable.
+pub fn deserialize_trusted_proxies<'de, D>(deserializer: D) -> Result<Vec<IpCidr>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ListOrString {
+        List(Vec<String>),
+        String(String),
+    }
+    let items = match ListOrString::deserialize(deserializer)? {
+        ListOrString::List(v) => v,
+        ListOrString::String(s) => s.split(',').map(|s| s.to_string()).collect(),
+    };
+    items
+        .iter()
+        .filter(|s| !s.trim().is_empty())
+        .map(|s| s.parse().map_err(serde::de::Error::custom))
+        .collect()
+}
+
+/// Returns the client's IP address of a request from `peer`.
+///
+/// If `peer` is unknown, that is the server is not served with the connect info,
+/// the forwarding headers are believed only if `trusted` is not empty.
+pub fn client_ip(
+    headers: &HeaderMap,
+    peer: Option<SocketAddr>,
+    trusted: &[IpCidr],
+) -> Option<IpAddr> {
+    let peer = peer.map(|p| canonical(p.ip()));
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|net| net.contains(ip));
+    match peer {
+        Some(ip) if !is_trusted(ip) => return Some(ip),
+        None if trusted.is_empty() => return None,
+        _ => {}
+    }
+    let hops = forwarded_hops(headers);
+    if !hops.is_empty() {
+        let mut client = None;
+        for hop in hops.iter().rev() {
+            // an obfuscated or unknown hop can not be walked through
+            let Some(ip) = hop else {
+                break;
+            };
+            client = Some(*ip);
+            if !is_trusted(*ip) {
+                break;
+            }
+        }
+        return client.or(peer);
+    }
+    real_ip(headers).or(peer)
+}
+
+/// Returns the hops of `Forwarded`, or else of `X-Forwarded-For`, from the client to the last proxy.
+/// `None` is a hop that is not an IP address.
+fn forwarded_hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<Option<IpAddr>> = header_values(headers, "forwarded")
+        .flat_map(|v| v.split(','))
+        .filter_map(|elem| {
+            elem.split(';').find_map(|pair| {
+                let (k, v) = pair.split_once('=')?;
+                k.trim().eq_ignore_ascii_case("for").then(|| parse_node(v))
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    header_values(headers, "x-forwarded-for")
+        .flat_map(|v| v.split(','))
+        .filter(|s| !s.trim().is_empty())
+        .map(parse_node)
+        .collect()
+}
+
+fn real_ip(headers: &HeaderMap) -> Option<IpAddr> {
+    header_values(headers, "x-real-ip").find_map(parse_node)
+}
+
+fn header_values<'a>(headers: &'a HeaderMap, name: &str) -> impl Iterator<Item = &'a str> {
+    headers
+        .get_all(name)
+        .into_iter()
+        .filter_map(|v| v.to_str().ok())
+}
+
+/// Parses a node of the forwarding headers: `192.0.2.1`, `"192.0.2.1:8080"`, `"[2001:db8::1]:8080"`.
+fn parse_node(s: &str) -> Option<IpAddr> {
+    let s = s.trim().trim_matches('"');
+    if let Ok(ip) = s.parse::<IpAddr>() {
+        return Some(canonical(ip));
+    }
+    if let Ok(sa) = s.parse::<SocketAddr>() {
+        return Some(canonical(sa.ip()));
+    }
+    let s = s.strip_prefix('[')?;
+    let (ip_s, _) = s.split_once(']')?;
+    ip_s.parse().ok().map(canonical)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use dioxus::fullstack::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (k, v) in pairs {
+            map.append(*k, HeaderValue::from_str(v).unwrap());
+        }
+        map
+    }
+
+    fn trusted(list: &[&str]) -> Vec<IpCidr> {
+        list.iter().map(|s| s.parse().unwrap()).collect()
+    }
+
+    fn ip(s: &str) -> Option<IpAddr> {
+        Some(s.parse().unwrap())
+    }
+
+    #[test]
+    fn test_cidr_00() {
+        let net: IpCidr = "10.1.0.0/16".parse().unwrap();
+        assert!(net.contains("10.1.255.1".parse().unwrap()));
+        assert!(net.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!net.contains("10.2.0.1".parse().unwrap()));
+        let net: IpCidr = "2001:db8::/33".parse().unwrap();
+        assert!(net.contains("2001:db8:7fff::1".parse().unwrap()));
+        assert!(!net.contains("2001:db8:8000::1".parse().unwrap()));
+        let net: IpCidr = "127.0.0.1".parse().unwrap();
+        assert!(net.contains("127.0.0.1".parse().unwrap()));
+        assert!(!net.contains("127.0.0.2".parse().unwrap()));
+        assert!("10.0.0.0/33".parse::<IpCidr>().is_err());
+        assert!("proxy.local".parse::<IpCidr>().is_err());
+    }
+
+    #[test]
+    fn test_peer_01() {
+        let trusted = trusted(&["10.0.0.0/8"]);
+        let peer = Some("198.51.100.7:50000".parse().unwrap());
+        // an untrusted peer can not forward
+        let h = headers(&[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("198.51.100.7"));
+        assert_eq!(client_ip(&HeaderMap::new(), peer, &[]), ip("198.51.100.7"));
+        // a trusted peer without headers
+        let peer = Some("10.0.0.2:50000".parse().unwrap());
+        assert_eq!(client_ip(&HeaderMap::new(), peer, &trusted), ip("10.0.0.2"));
+        assert_eq!(client_ip(&HeaderMap::new(), None, &trusted), None);
+    }
+
+    #[test]
+    fn test_x_forwarded_for_02() {
+        let trusted = trusted(&["10.0.0.0/8"]);
+        let peer = Some("10.0.0.2:50000".parse().unwrap());
+        // the spoofed leftmost entry is skipped
+        let h = headers(&[("x-forwarded-for", "203.0.113.9, 192.0.2.1, 10.0.0.3")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("192.0.2.1"));
+        assert_eq!(client_ip(&h, None, &trusted), ip("192.0.2.1"));
+        // split into several headers
+        let h = headers(&[
+            ("x-forwarded-for", "203.0.113.9"),
+            ("x-forwarded-for", "192.0.2.1"),
+        ]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("192.0.2.1"));
+        // all trusted
+        let h = headers(&[("x-forwarded-for", "10.0.0.5, 10.0.0.3")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("10.0.0.5"));
+        // garbage stops the walk
+        let h = headers(&[("x-forwarded-for", "192.0.2.1, garbage")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("10.0.0.2"));
+    }
+
+    #[test]
+    fn test_forwarded_03() {
+        let trusted = trusted(&["10.0.0.0/8", "fd00::/8"]);
+        let peer = Some("[fd00::1]:50000".parse().unwrap());
+        let h = headers(&[
+            (
+                "forwarded",
+                r#"for=203.0.113.9, for="[2001:db8:cafe::17]:4711";proto=https"#,
+            ),
+            ("forwarded", "for=10.0.0.3;by=10.0.0.2"),
+            ("x-forwarded-for", "192.0.2.1"),
+        ]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("2001:db8:cafe::17"));
+        let h = headers(&[("forwarded", "for=unknown")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("fd00::1"));
+        // X-Real-IP
+        let h = headers(&[("x-real-ip", "192.0.2.1")]);
+        assert_eq!(client_ip(&h, peer, &trusted), ip("192.0.2.1"));
+    }
+
+    #[test]
+    fn test_unknown_peer_04() {
+        // without the connect info nor trusted proxies, the headers are not believed
+        let h = headers(&[("x-forwarded-for", "192.0.2.1")]);
+        assert_eq!(client_ip(&h, None, &[]), None);
+        let h = headers(&[("forwarded", "for=192.0.2.1")]);
+        assert_eq!(client_ip(&h, None, &[]), None);
+        let h = headers(&[("x-real-ip", "192.0.2.1")]);
+        assert_eq!(client_ip(&h, None, &[]), None);
+        // with trusted proxies, the unknown peer is one of them
+        let trusted = trusted(&["10.0.0.0/8"]);
+        assert_eq!(client_ip(&h, None, &trusted), ip("192.0.2.1"));
+    }
+}
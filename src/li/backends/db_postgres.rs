@@ -0,0 +1,1623 @@
+//! PostgreSQL backend implementation for storing browser information.
+//! Uses the same normalized schema as the SQLite backend, with the migrations in `migrations/postgres`.
+
+use anyhow::{bail, Result};
+use async_trait::async_trait;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions};
+use sqlx::{Postgres, Row, Transaction};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use super::config::{BackendConfig, RetentionConfig, SessionConfig, VacuumMode};
+use super::fingerprint::Fingerprinter;
+#[cfg(feature = "geoip")]
+use super::geoip::{GeoInfo, GeoIpReader};
+use super::privacy::IpAddressPrivacy;
+use super::retention::{PruneReport, DELETE_UNREFERENCED};
+use super::store::{BroInfoStore, SaveBroInfoItem, SessionHit, VisitValues};
+use super::user_agent::ParsedUserAgent;
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats, SaveBroInfoRequest};
+use super::{EventRequest, FingerprintGroup, FingerprintQuery, PageViewRequest};
+
+#[cfg(feature = "backend_user_agent")]
+use super::SaveUserAgentRequest;
+
+/// The IDs of the lookup values already stored in a transaction, by table and value.
+type LookupIds = HashMap<(&'static str, String), i64>;
+
+/// The store of the browser information in a PostgreSQL database.
+pub struct PostgresStore {
+    pool: PgPool,
+    /// The database location without the credentials.
+    db_path: String,
+    ip_privacy: IpAddressPrivacy,
+    fingerprinter: Option<Fingerprinter>,
+    session: SessionConfig,
+    #[cfg(feature = "geoip")]
+    geoip: Option<GeoIpReader>,
+}
+
+impl PostgresStore {
+    /// Opens the database at `database.url`.
+    /// Also runs pending migrations if necessary.
+    pub async fn open(cfg: &BackendConfig) -> Result<Self> {
+        if cfg.database.url.is_empty() {
+            bail!("`database.url` is not set. Please set it to the PostgreSQL connection URL.");
+        }
+        let mut store = Self::connect(&cfg.database.url).await?;
+        store.ip_privacy = IpAddressPrivacy::from_config(&cfg.privacy)?;
+        store.fingerprinter = Fingerprinter::from_config(&cfg.fingerprint)?;
+        store.session = cfg.session.clone();
+        #[cfg(feature = "geoip")]
+        {
+            store.geoip = GeoIpReader::from_config(&cfg.geoip)?;
+        }
+        Ok(store)
+    }
+
+    /// Opens the database at `url`.
+    /// Also runs pending migrations if necessary.
+    pub async fn connect(url: &str) -> Result<Self> {
+        // Create or upgrade tables to the latest schema, on a pool of its own,
+        // since the store may be opened on a runtime that ends right after it,
+        // e.g. by `init_store()`, and the connections would be bound to that runtime
+        let (setup_pool, db_path) = connect_sqlx_pool(url).await?;
+        let r = create_tables(&setup_pool).await;
+        setup_pool.close().await;
+        r?;
+        let pool = connect_lazy_sqlx_pool(url)?;
+        Ok(Self {
+            pool,
+            db_path,
+            ip_privacy: IpAddressPrivacy::default(),
+            fingerprinter: None,
+            session: SessionConfig::default(),
+            #[cfg(feature = "geoip")]
+            geoip: None,
+        })
+    }
+
+    /// Returns the values of the visit `req` from `ip_address` as they are stored.
+    fn visit_values(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<VisitValues> {
+        Ok(VisitValues {
+            ip_address: self.ip_privacy.apply(ip_address),
+            ip_address_mode: self.ip_privacy.mode().as_str(),
+            fingerprint: self
+                .fingerprinter
+                .as_ref()
+                .filter(|_| req.consent_level().allows_fingerprint())
+                .map(|f| f.compute(&req.broinfo.jsinfo))
+                .unwrap_or_default(),
+            jsinfo: jsinfo_db_string(req)?,
+            consent: req.consent.map(|c| c.as_str()).unwrap_or_default(),
+            bot_score: req.bot_score as i64,
+        })
+    }
+
+    /// Resolves the GeoIP of the stored `ip_address`, if it is not resolved yet.
+    #[cfg(feature = "geoip")]
+    async fn resolve_geoip<'c, E>(&self, e: E, ip_address: &str) -> Result<()>
+    where
+        E: sqlx::PgExecutor<'c>,
+    {
+        let Some(reader) = &self.geoip else {
+            return Ok(());
+        };
+        if ip_address.is_empty() {
+            return Ok(());
+        }
+        let info = reader.lookup(ip_address)?.unwrap_or_default();
+        store_geo_info(e, ip_address, &info).await
+    }
+}
+
+#[async_trait]
+impl BroInfoStore for PostgresStore {
+    fn kind(&self) -> &'static str {
+        "postgres"
+    }
+
+    async fn db_path(&self) -> Result<String> {
+        Ok(self.db_path.clone())
+    }
+
+    async fn health(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Normalizes and stores JS info, User Agent, Referrer, IP Address, BICMID, and User ID.
+    async fn save_broinfo(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<()> {
+        let values = self.visit_values(req, ip_address)?;
+        store_broinfo(&self.pool, req, &values, self.session.timeout_secs).await?;
+        #[cfg(feature = "geoip")]
+        self.resolve_geoip(&self.pool, &values.ip_address).await?;
+        //
+        dioxus::logger::tracing::debug!("save_broinfo: {:?}", values.jsinfo);
+        Ok(())
+    }
+
+    /// Stores the visits of `batch` in one transaction.
+    async fn save_broinfo_batch(&self, batch: &[SaveBroInfoItem]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut ids = LookupIds::new();
+        for item in batch {
+            let values = self.visit_values(&item.req, &item.ip_address)?;
+            let timeout_secs = self.session.timeout_secs;
+            store_broinfo_tx(&mut tx, &mut ids, &item.req, &values, timeout_secs).await?;
+            #[cfg(feature = "geoip")]
+            self.resolve_geoip(&mut *tx, &values.ip_address).await?;
+        }
+        tx.commit().await?;
+        dioxus::logger::tracing::debug!("save_broinfo_batch: {} visits", batch.len());
+        Ok(())
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    async fn save_user_agent(&self, req: &SaveUserAgentRequest) -> Result<()> {
+        let ua_s = req.ua.get();
+        //
+        let mut tx = self.pool.begin().await?;
+        // a retried request, whose `request_id` is already stored, is not stored again
+        if !req.request_id.is_empty() {
+            let r = sqlx::query(concat!(
+                r#"INSERT INTO user_agent_requests (value) VALUES ($1)"#,
+                r#" ON CONFLICT (value) DO NOTHING"#
+            ))
+            .bind(&req.request_id)
+            .execute(&mut *tx)
+            .await?;
+            if r.rows_affected() == 0 {
+                return Ok(());
+            }
+        }
+        //
+        let _user_agent_id = get_or_store_user_agent(&mut tx, &mut LookupIds::new(), ua_s).await?;
+        //
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn save_pageview(&self, req: &PageViewRequest) -> Result<()> {
+        store_pageview(&self.pool, req, self.session.timeout_secs).await
+    }
+
+    async fn save_event(&self, req: &EventRequest) -> Result<()> {
+        store_event(&self.pool, req, self.session.timeout_secs).await
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<LogPage> {
+        fetch_logs(&self.pool, query).await
+    }
+
+    async fn query_log_stats(&self, query: &LogQuery) -> Result<LogStats> {
+        fetch_log_stats(&self.pool, query).await
+    }
+
+    async fn erase_subject(&self, subject: &EraseSubject) -> Result<EraseReport> {
+        erase_subject(&self.pool, subject).await
+    }
+
+    async fn query_fingerprints(&self, query: &FingerprintQuery) -> Result<Vec<FingerprintGroup>> {
+        fetch_fingerprints(&self.pool, query).await
+    }
+
+    async fn prune(&self, policy: &RetentionConfig) -> Result<PruneReport> {
+        let report = delete_expired(&self.pool, policy).await?;
+        if report.logs + report.page_views + report.events > 0 {
+            vacuum(&self.pool, policy.vacuum).await?;
+        }
+        Ok(report)
+    }
+
+    async fn reparse_user_agents(&self, all: bool) -> Result<u64> {
+        reparse_user_agents(&self.pool, all).await
+    }
+
+    #[cfg(feature = "geoip")]
+    async fn backfill_geoip(&self) -> Result<u64> {
+        match &self.geoip {
+            Some(reader) => backfill_geoip(&self.pool, reader).await,
+            None => Ok(0),
+        }
+    }
+}
+
+/// Opens the PostgreSQL connection pool without touching the schema.
+///
+/// Returns the pool and the database location without the credentials.
+async fn connect_sqlx_pool(url: &str) -> Result<(PgPool, String)> {
+    let opts = PgConnectOptions::from_str(url)?;
+    let db_path = format!(
+        "postgres://{}:{}/{}",
+        opts.get_host(),
+        opts.get_port(),
+        opts.get_database().unwrap_or_default()
+    );
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .connect_with(opts)
+        .await?;
+    Ok((pool, db_path))
+}
+
+/// Creates the PostgreSQL connection pool of the store without connecting yet.
+///
+/// The connections are opened on first use, on the runtime that serves the requests.
+/// The pool has no idle timeout and no max lifetime, which would spawn its maintenance task
+/// on the runtime that creates it; a broken connection is still replaced, since it is
+/// tested before it is acquired.
+fn connect_lazy_sqlx_pool(url: &str) -> Result<PgPool> {
+    let opts = PgConnectOptions::from_str(url)?;
+    let pool = PgPoolOptions::new()
+        .max_connections(5)
+        .idle_timeout(None)
+        .max_lifetime(None)
+        .test_before_acquire(true)
+        .connect_lazy_with(opts);
+    Ok(pool)
+}
+
+/// Reverts the database schema to the migration `target` version.
+///
+/// `target = 0` reverts all migrations, dropping every table.
+pub async fn migrate_undo(cfg: &BackendConfig, target: i64) -> Result<()> {
+    use super::migrate::{undo_migrations, POSTGRES_MIGRATOR};
+    //
+    let (pool, _db_path) = connect_sqlx_pool(&cfg.database.url).await?;
+    undo_migrations(&POSTGRES_MIGRATOR, &pool, target).await?;
+    pool.close().await;
+    Ok(())
+}
+
+/// The `FROM` and `WHERE` clauses shared by the logs queries.
+///
+/// Binds: `$1` bicmid, `$2` user, `$3` since, `$4` until.
+macro_rules! logs_from_where {
+    () => {
+        concat!(
+            r#" FROM logs"#,
+            r#" INNER JOIN jsinfos ON jsinfos.id = logs.jsinfo_id"#,
+            r#" INNER JOIN user_agents ON user_agents.id = logs.user_agent_id"#,
+            r#" INNER JOIN referrers ON referrers.id = logs.referrer_id"#,
+            r#" INNER JOIN ip_addresses ON ip_addresses.id = logs.ip_address_id"#,
+            r#" INNER JOIN bicmids ON bicmids.id = logs.bicmid_id"#,
+            r#" INNER JOIN users ON users.id = logs.user_id"#,
+            r#" WHERE ($1::TEXT IS NULL OR bicmids.value = $1)"#,
+            r#" AND ($2::TEXT IS NULL OR users.value = $2)"#,
+            r#" AND ($3::TEXT IS NULL OR logs.create_at >= $3)"#,
+            r#" AND ($4::TEXT IS NULL OR logs.create_at < $4)"#,
+        )
+    };
+}
+
+/// Fetches one page of the stored visits matching `query`.
+async fn fetch_logs(pool: &PgPool, query: &LogQuery) -> Result<LogPage> {
+    use super::LogEntry;
+    //
+    let total: i64 = sqlx::query(concat!(r#"SELECT COUNT(*)"#, logs_from_where!()))
+        .bind(&query.bicmid)
+        .bind(&query.user)
+        .bind(&query.since)
+        .bind(&query.until)
+        .fetch_one(pool)
+        .await?
+        .get(0);
+    //
+    let rows = sqlx::query(concat!(
+        r#"SELECT logs.id, logs.create_at, bicmids.value AS bicmid, users.value AS user,"#,
+        r#" user_agents.value AS user_agent, referrers.value AS referrer,"#,
+        r#" ip_addresses.value AS ip_address, logs.ip_address_mode, jsinfos.value AS jsinfo,"#,
+        r#" logs.bot_score"#,
+        logs_from_where!(),
+        r#" ORDER BY logs.id DESC LIMIT $5 OFFSET $6"#
+    ))
+    .bind(&query.bicmid)
+    .bind(&query.user)
+    .bind(&query.since)
+    .bind(&query.until)
+    .bind(query.limit() as i64)
+    .bind(query.offset() as i64)
+    .fetch_all(pool)
+    .await?;
+    //
+    let entries = rows
+        .iter()
+        .map(|row| {
+            Ok(LogEntry {
+                id: row.try_get("id")?,
+                create_at: row.try_get("create_at")?,
+                bicmid: row.try_get("bicmid")?,
+                user: row.try_get("user")?,
+                user_agent: row.try_get("user_agent")?,
+                referrer: row.try_get("referrer")?,
+                ip_address: row.try_get("ip_address")?,
+                ip_address_mode: row.try_get("ip_address_mode")?,
+                jsinfo: row.try_get("jsinfo")?,
+                bot_score: row.try_get("bot_score")?,
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(LogPage {
+        entries,
+        total,
+        page: query.page,
+        per_page: query.limit(),
+    })
+}
+
+/// Counts the stored visits matching `query` by browser and by operating system.
+///
+/// The pagination of `query` is ignored.
+async fn fetch_log_stats(pool: &PgPool, query: &LogQuery) -> Result<LogStats> {
+    let rows = sqlx::query(concat!(
+        r#"SELECT user_agents.value, COUNT(*)"#,
+        logs_from_where!(),
+        r#" GROUP BY user_agents.value"#
+    ))
+    .bind(&query.bicmid)
+    .bind(&query.user)
+    .bind(&query.since)
+    .bind(&query.until)
+    .fetch_all(pool)
+    .await?;
+    let counts = rows
+        .iter()
+        .map(|row| Ok((row.try_get::<String, _>(0)?, row.try_get::<i64, _>(1)?)))
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(LogStats::from_user_agent_counts(
+        counts.iter().map(|(ua, n)| (ua.as_str(), *n)),
+    ))
+}
+
+/// Fetches the fingerprints matching `query` and the BICMIDs that share them,
+/// the fingerprints of the most BICMIDs first.
+///
+/// The visits without a fingerprint or a BICMID are not linked.
+async fn fetch_fingerprints(
+    pool: &PgPool,
+    query: &FingerprintQuery,
+) -> Result<Vec<FingerprintGroup>> {
+    use super::FingerprintBicmid;
+    //
+    let rows = sqlx::query(concat!(
+        r#"SELECT fingerprints.value AS fingerprint, bicmids.value AS bicmid,"#,
+        r#" COUNT(*) AS visits, MIN(logs.create_at) AS first_seen,"#,
+        r#" MAX(logs.create_at) AS last_seen"#,
+        r#" FROM (SELECT fingerprint_id, COUNT(DISTINCT bicmid_id) AS n FROM logs"#,
+        r#" WHERE fingerprint_id <> 0 AND bicmid_id <> 0"#,
+        r#" AND ($1::TEXT IS NULL OR fingerprint_id IN"#,
+        r#" (SELECT id FROM fingerprints WHERE value = $1))"#,
+        r#" AND ($2::TEXT IS NULL OR fingerprint_id IN (SELECT linked.fingerprint_id FROM logs AS linked"#,
+        r#" INNER JOIN bicmids ON bicmids.id = linked.bicmid_id WHERE bicmids.value = $2))"#,
+        r#" GROUP BY fingerprint_id HAVING COUNT(DISTINCT bicmid_id) >= $3"#,
+        r#" ORDER BY n DESC, fingerprint_id LIMIT $4) AS shared"#,
+        r#" INNER JOIN logs ON logs.fingerprint_id = shared.fingerprint_id AND logs.bicmid_id <> 0"#,
+        r#" INNER JOIN fingerprints ON fingerprints.id = shared.fingerprint_id"#,
+        r#" INNER JOIN bicmids ON bicmids.id = logs.bicmid_id"#,
+        r#" GROUP BY shared.fingerprint_id, shared.n, fingerprints.value, bicmids.value"#,
+        r#" ORDER BY shared.n DESC, shared.fingerprint_id, last_seen DESC, bicmids.value"#
+    ))
+    .bind(&query.fingerprint)
+    .bind(&query.bicmid)
+    .bind(query.min_bicmids() as i64)
+    .bind(query.limit() as i64)
+    .fetch_all(pool)
+    .await?;
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let bicmid = FingerprintBicmid {
+                bicmid: row.try_get("bicmid")?,
+                visits: row.try_get("visits")?,
+                first_seen: row.try_get("first_seen")?,
+                last_seen: row.try_get("last_seen")?,
+            };
+            Ok((row.try_get("fingerprint")?, bicmid))
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(FingerprintGroup::from_rows(rows))
+}
+
+/// The key of the advisory lock between the saves and the garbage collection of the lookup tables.
+///
+/// A save holds it shared from its first lookup to its commit, so that the lookup values
+/// it refers to are not deleted before its `logs` row is committed.
+const LOOKUP_GC_LOCK: i64 = 0x6272_6f69_6e66_6f00;
+
+/// Deletes the visits, the page views and the events of `subject`, its `bicmids` or `users` row,
+/// and the lookup values no longer referenced, in one transaction.
+///
+/// The special rows of ID 0, i.e. the empty values, are never deleted.
+async fn erase_subject(pool: &PgPool, subject: &EraseSubject) -> Result<EraseReport> {
+    let (rows_sqls, subject_sql) = match subject {
+        EraseSubject::Bicmid(_) => (
+            [
+                concat!(
+                    r#"DELETE FROM logs WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = $1 AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM page_views WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = $1 AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM events WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = $1 AND id <> 0)"#
+                ),
+            ],
+            r#"DELETE FROM bicmids WHERE value = $1 AND id <> 0"#,
+        ),
+        EraseSubject::User(_) => (
+            [
+                concat!(
+                    r#"DELETE FROM logs WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = $1 AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM page_views WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = $1 AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM events WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = $1 AND id <> 0)"#
+                ),
+            ],
+            r#"DELETE FROM users WHERE value = $1 AND id <> 0"#,
+        ),
+    };
+    let mut report = EraseReport::default();
+    let mut tx = pool.begin().await?;
+    sqlx::query(r#"SELECT pg_advisory_xact_lock($1)"#)
+        .bind(LOOKUP_GC_LOCK)
+        .execute(&mut *tx)
+        .await?;
+    let deleted = [&mut report.logs, &mut report.page_views, &mut report.events];
+    for (sql, deleted) in rows_sqls.into_iter().zip(deleted) {
+        let r = sqlx::query(sql)
+            .bind(subject.value())
+            .execute(&mut *tx)
+            .await?;
+        *deleted = r.rows_affected();
+    }
+    let r = sqlx::query(subject_sql)
+        .bind(subject.value())
+        .execute(&mut *tx)
+        .await?;
+    report.subjects = r.rows_affected();
+    for sql in DELETE_UNREFERENCED {
+        let r = sqlx::query(sql).execute(&mut *tx).await?;
+        report.lookups += r.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// Deletes the visits, the page views and the events beyond the limits of `policy`,
+/// then the lookup values no longer referenced.
+async fn delete_expired(pool: &PgPool, policy: &RetentionConfig) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+    let mut tx = pool.begin().await?;
+    let tables = [
+        (
+            concat!(
+                r#"DELETE FROM logs WHERE create_at < to_char("#,
+                r#"now() AT TIME ZONE 'UTC' - make_interval(days => $1), 'YYYY-MM-DD HH24:MI:SS')"#
+            ),
+            concat!(
+                r#"DELETE FROM logs WHERE id <= "#,
+                r#"(SELECT id FROM logs ORDER BY id DESC LIMIT 1 OFFSET $1)"#
+            ),
+            &mut report.logs,
+        ),
+        (
+            concat!(
+                r#"DELETE FROM page_views WHERE create_at < to_char("#,
+                r#"now() AT TIME ZONE 'UTC' - make_interval(days => $1), 'YYYY-MM-DD HH24:MI:SS')"#
+            ),
+            concat!(
+                r#"DELETE FROM page_views WHERE id <= "#,
+                r#"(SELECT id FROM page_views ORDER BY id DESC LIMIT 1 OFFSET $1)"#
+            ),
+            &mut report.page_views,
+        ),
+        (
+            concat!(
+                r#"DELETE FROM events WHERE create_at < to_char("#,
+                r#"now() AT TIME ZONE 'UTC' - make_interval(days => $1), 'YYYY-MM-DD HH24:MI:SS')"#
+            ),
+            concat!(
+                r#"DELETE FROM events WHERE id <= "#,
+                r#"(SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET $1)"#
+            ),
+            &mut report.events,
+        ),
+    ];
+    for (age_sql, rows_sql, deleted) in tables {
+        if policy.max_age_days > 0 {
+            let r = sqlx::query(age_sql)
+                .bind(policy.max_age_days as i32)
+                .execute(&mut *tx)
+                .await?;
+            *deleted += r.rows_affected();
+        }
+        if policy.max_rows > 0 {
+            let r = sqlx::query(rows_sql)
+                .bind(policy.max_rows as i64)
+                .execute(&mut *tx)
+                .await?;
+            *deleted += r.rows_affected();
+        }
+    }
+    if report.logs + report.page_views + report.events > 0 {
+        sqlx::query(r#"SELECT pg_advisory_xact_lock($1)"#)
+            .bind(LOOKUP_GC_LOCK)
+            .execute(&mut *tx)
+            .await?;
+        for sql in DELETE_UNREFERENCED {
+            let r = sqlx::query(sql).execute(&mut *tx).await?;
+            report.lookups += r.rows_affected();
+        }
+    }
+    tx.commit().await?;
+    Ok(report)
+}
+
+/// Reclaims the space of the deleted rows.
+async fn vacuum(pool: &PgPool, mode: VacuumMode) -> Result<()> {
+    let sql = match mode {
+        VacuumMode::None => return Ok(()),
+        VacuumMode::Incremental => "VACUUM (ANALYZE)",
+        VacuumMode::Full => "VACUUM (FULL, ANALYZE)",
+    };
+    sqlx::query(sql).execute(pool).await?;
+    Ok(())
+}
+
+/// Stores `info` as the GeoIP of the IP address `value`, unless it is already resolved.
+#[cfg(feature = "geoip")]
+async fn store_geo_info<'c, E>(e: E, value: &str, info: &GeoInfo) -> Result<()>
+where
+    E: sqlx::PgExecutor<'c>,
+{
+    sqlx::query(concat!(
+        r#"UPDATE ip_addresses SET country_code = $1, region_name = $2, city_name = $3,"#,
+        r#" autonomous_system_number = $4, autonomous_system_organization = $5,"#,
+        r#" geo_resolved_at = to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')"#,
+        r#" WHERE value = $6 AND id <> 0 AND geo_resolved_at IS NULL"#
+    ))
+    .bind(&info.country_code)
+    .bind(&info.region_name)
+    .bind(&info.city_name)
+    .bind(info.autonomous_system_number.map(i64::from))
+    .bind(&info.autonomous_system_organization)
+    .bind(value)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+/// Resolves the GeoIP of every stored IP address not resolved yet, and returns their number.
+#[cfg(feature = "geoip")]
+async fn backfill_geoip(pool: &PgPool, reader: &GeoIpReader) -> Result<u64> {
+    let mut count = 0;
+    loop {
+        let rows = sqlx::query(concat!(
+            r#"SELECT value FROM ip_addresses"#,
+            r#" WHERE id <> 0 AND geo_resolved_at IS NULL ORDER BY id LIMIT 1000"#
+        ))
+        .fetch_all(pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(count);
+        }
+        let mut tx = pool.begin().await?;
+        for row in &rows {
+            let value: String = row.try_get(0)?;
+            let info = reader.lookup(&value)?.unwrap_or_default();
+            store_geo_info(&mut *tx, &value, &info).await?;
+        }
+        tx.commit().await?;
+        count += rows.len() as u64;
+    }
+}
+
+/// Stores `parsed` into the structured columns of the `user_agents` row `id`.
+async fn store_parsed_user_agent<'c, E>(e: E, id: i64, parsed: &ParsedUserAgent) -> Result<()>
+where
+    E: sqlx::PgExecutor<'c>,
+{
+    sqlx::query(concat!(
+        r#"UPDATE user_agents SET browser_name = $1, browser_version = $2,"#,
+        r#" os_name = $3, os_version = $4, device_model = $5, device_class = $6,"#,
+        r#" parsed_at = to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')"#,
+        r#" WHERE id = $7"#
+    ))
+    .bind(&parsed.browser_name)
+    .bind(&parsed.browser_version)
+    .bind(&parsed.os_name)
+    .bind(&parsed.os_version)
+    .bind(&parsed.device_model)
+    .bind(parsed.device_class.as_str())
+    .bind(id)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+/// Parses the stored user agents into the structured columns, and returns their number.
+/// Only the ones not parsed yet unless `all`.
+async fn reparse_user_agents(pool: &PgPool, all: bool) -> Result<u64> {
+    let mut count = 0;
+    let mut last_id: i64 = 0;
+    loop {
+        let rows = sqlx::query(concat!(
+            r#"SELECT id, value FROM user_agents"#,
+            r#" WHERE id > $1 AND ($2 OR parsed_at IS NULL) ORDER BY id LIMIT 1000"#
+        ))
+        .bind(last_id)
+        .bind(all)
+        .fetch_all(pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(count);
+        }
+        let mut tx = pool.begin().await?;
+        for row in &rows {
+            let id: i64 = row.try_get(0)?;
+            let value: String = row.try_get(1)?;
+            store_parsed_user_agent(&mut *tx, id, &ParsedUserAgent::parse(&value)).await?;
+            last_id = id;
+        }
+        tx.commit().await?;
+        count += rows.len() as u64;
+    }
+}
+
+/// Converts the JS info of `req` to the JSON stored in `jsinfos`, the same as the SQLite backend.
+fn jsinfo_db_string(req: &SaveBroInfoRequest) -> Result<String> {
+    Ok(serde_json::to_string(&req.broinfo.jsinfo)?)
+}
+
+/// Stores one visit into `logs` in its own transaction.
+async fn store_broinfo(
+    pool: &PgPool,
+    req: &SaveBroInfoRequest,
+    values: &VisitValues,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    store_broinfo_tx(
+        &mut tx,
+        &mut LookupIds::new(),
+        req,
+        values,
+        session_timeout_secs,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stores one visit into `logs`, normalizing every value into its lookup table,
+/// and counts it in its session.
+///
+/// The values found in `ids` are not looked up again.
+///
+/// A retried request, whose `request_id` is already stored, is deduplicated by `ui_logs_01`:
+/// the `logs` row is inserted first, without a `SELECT` before it, and the session is counted
+/// only if it was inserted.
+async fn store_broinfo_tx(
+    tx: &mut Transaction<'_, Postgres>,
+    ids: &mut LookupIds,
+    req: &SaveBroInfoRequest,
+    values: &VisitValues,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let user_agent = &req.broinfo.basic.user_agent;
+    let referrer = &req.broinfo.basic.referrer;
+    //
+    // once per transaction: `ids` is empty only before its first visit
+    if ids.is_empty() {
+        lock_lookups_shared(tx).await?;
+    }
+    let user_agent_id = get_or_store_user_agent(tx, ids, user_agent.get()).await?;
+    let referrer_id = get_or_store_referrer(tx, ids, referrer.get()).await?;
+    let ip_address_id = get_or_store_ip_address(tx, ids, &values.ip_address).await?;
+    let bicmid_id = get_or_store_bicmid(tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(tx, ids, &req.user).await?;
+    let jsinfo_id = get_or_store_jsinfo(tx, ids, &values.jsinfo).await?;
+    let fingerprint_id = get_or_store_fingerprint(tx, ids, &values.fingerprint).await?;
+    //
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO logs"#,
+        r#" (jsinfo_id, user_agent_id, referrer_id, ip_address_id, bicmid_id, user_id,"#,
+        r#" ip_address_mode, fingerprint_id, request_id, consent, bot_score)"#,
+        r#" VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)"#,
+        r#" ON CONFLICT (request_id) DO NOTHING RETURNING id"#
+    ))
+    .bind(jsinfo_id)
+    .bind(user_agent_id)
+    .bind(referrer_id)
+    .bind(ip_address_id)
+    .bind(bicmid_id)
+    .bind(user_id)
+    .bind(values.ip_address_mode)
+    .bind(fingerprint_id)
+    .bind(Some(req.request_id.as_str()).filter(|s| !s.is_empty()))
+    .bind(values.consent)
+    .bind(values.bot_score)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let Some(row) = r else {
+        // a retry of a stored request
+        return Ok(());
+    };
+    let log_id: i64 = row.get(0);
+    //
+    let session_id = get_or_start_session(
+        tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::Visit,
+    )
+    .await?;
+    if session_id != 0 {
+        sqlx::query(r#"UPDATE logs SET session_id = $1 WHERE id = $2"#)
+            .bind(session_id)
+            .bind(log_id)
+            .execute(&mut **tx)
+            .await?;
+    }
+    Ok(())
+}
+
+/// Continues the latest session of `session_id` and `bicmid_id` if it was seen within
+/// `timeout_secs`, or else starts another one, and returns its ID.
+/// `hit` says what it counts.
+///
+/// `0` without a session ID and a BICMID.
+async fn get_or_start_session(
+    tx: &mut Transaction<'_, Postgres>,
+    session_id: &str,
+    bicmid_id: i64,
+    timeout_secs: u64,
+    hit: SessionHit,
+) -> Result<i64> {
+    if session_id.is_empty() && bicmid_id == 0 {
+        return Ok(0);
+    }
+    let (hits, page_views) = hit.increments();
+    let r = sqlx::query(concat!(
+        r#"UPDATE sessions SET hits = hits + $4, page_views = page_views + $5,"#,
+        r#" last_seen_at = to_char(now() AT TIME ZONE 'UTC', 'YYYY-MM-DD HH24:MI:SS')"#,
+        r#" WHERE id = (SELECT id FROM sessions WHERE bicmid_id = $1 AND value = $2"#,
+        r#" AND id <> 0 ORDER BY id DESC LIMIT 1 FOR UPDATE)"#,
+        r#" AND last_seen_at >= to_char(now() AT TIME ZONE 'UTC' - make_interval(secs => $3),"#,
+        r#" 'YYYY-MM-DD HH24:MI:SS')"#,
+        r#" RETURNING id"#
+    ))
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(timeout_secs as f64)
+    .bind(hits)
+    .bind(page_views)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let row = match r {
+        Some(row) => row,
+        None => {
+            sqlx::query(concat!(
+                r#"INSERT INTO sessions (value, bicmid_id, hits, page_views)"#,
+                r#" VALUES ($1, $2, $3, $4) RETURNING id"#
+            ))
+            .bind(session_id)
+            .bind(bicmid_id)
+            .bind(hits)
+            .bind(page_views)
+            .fetch_one(&mut **tx)
+            .await?
+        }
+    };
+    Ok(row.get(0))
+}
+
+/// Stores one page view into `page_views` in its own transaction, and counts it in its session.
+async fn store_pageview(
+    pool: &PgPool,
+    req: &PageViewRequest,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let ids = &mut LookupIds::new();
+    lock_lookups_shared(&mut tx).await?;
+    let path_id = get_or_store_path(&mut tx, ids, &req.path).await?;
+    let referrer_id = get_or_store_referrer(&mut tx, ids, &req.referrer).await?;
+    let bicmid_id = get_or_store_bicmid(&mut tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(&mut tx, ids, &req.user).await?;
+    let session_id = get_or_start_session(
+        &mut tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::PageView,
+    )
+    .await?;
+    //
+    sqlx::query(concat!(
+        r#"INSERT INTO page_views"#,
+        r#" (path_id, title, referrer_id, bicmid_id, session_id, user_id)"#,
+        r#" VALUES ($1, $2, $3, $4, $5, $6)"#
+    ))
+    .bind(path_id)
+    .bind(&req.title)
+    .bind(referrer_id)
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stores one custom event into `events` in its own transaction, extending its session.
+async fn store_event(pool: &PgPool, req: &EventRequest, session_timeout_secs: u64) -> Result<()> {
+    let properties = req.properties_json()?;
+    let mut tx = pool.begin().await?;
+    let ids = &mut LookupIds::new();
+    lock_lookups_shared(&mut tx).await?;
+    let name_id = get_or_store_event_name(&mut tx, ids, &req.name).await?;
+    let bicmid_id = get_or_store_bicmid(&mut tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(&mut tx, ids, &req.user).await?;
+    let session_id = get_or_start_session(
+        &mut tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::Event,
+    )
+    .await?;
+    //
+    sqlx::query(concat!(
+        r#"INSERT INTO events"#,
+        r#" (name_id, properties, bicmid_id, session_id, user_id)"#,
+        r#" VALUES ($1, $2, $3, $4, $5)"#
+    ))
+    .bind(name_id)
+    .bind(&properties)
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Ensures required tables exist in the PostgreSQL database by applying pending migrations.
+async fn create_tables(pool: &PgPool) -> Result<()> {
+    use super::migrate::{run_migrations, POSTGRES_MIGRATOR};
+    //
+    run_migrations(&POSTGRES_MIGRATOR, pool).await?;
+    //
+    // `JsInfo` special data for ID 0
+    {
+        let s = "";
+        let hash = create_jsinfo_hash(s);
+        sqlx::query(
+            r#"INSERT INTO jsinfos (id, hash, value) VALUES (0, $1, $2) ON CONFLICT DO NOTHING"#,
+        )
+        .bind(hash.as_str())
+        .bind(s)
+        .execute(pool)
+        .await?;
+    }
+    convert_legacy_jsinfos(pool).await?;
+    Ok(())
+}
+
+/// Converts the `jsinfos` rows stored as TOML by the older versions to JSON,
+/// and returns their number.
+///
+/// A row that becomes the same as an existing one is merged into it.
+///
+/// It runs once: the conversion is recorded in `data_conversions`, and is skipped from then on.
+async fn convert_legacy_jsinfos(pool: &PgPool) -> Result<u64> {
+    use super::jsinfo::{is_legacy_toml, json_from_legacy_toml, LEGACY_TOML_CONVERSION};
+    //
+    let mut tx = pool.begin().await?;
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO data_conversions (value) VALUES ($1)"#,
+        r#" ON CONFLICT DO NOTHING RETURNING id"#
+    ))
+    .bind(LEGACY_TOML_CONVERSION)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if r.is_none() {
+        // already done
+        return Ok(0);
+    }
+    let rows =
+        sqlx::query(r#"SELECT id, value FROM jsinfos WHERE id <> 0 AND value NOT LIKE '{%'"#)
+            .fetch_all(&mut *tx)
+            .await?;
+    if rows.is_empty() {
+        tx.commit().await?;
+        return Ok(0);
+    }
+    // the duplicates are deleted, as by the garbage collection
+    sqlx::query(r#"SELECT pg_advisory_xact_lock($1)"#)
+        .bind(LOOKUP_GC_LOCK)
+        .execute(&mut *tx)
+        .await?;
+    let mut count = 0;
+    for row in &rows {
+        let id: i64 = row.try_get(0)?;
+        let value: String = row.try_get(1)?;
+        if !is_legacy_toml(&value) {
+            continue;
+        }
+        let json = json_from_legacy_toml(&value);
+        let hash = create_jsinfo_hash(&json);
+        let existing = sqlx::query(r#"SELECT id FROM jsinfos WHERE hash = $1 AND value = $2"#)
+            .bind(&hash)
+            .bind(&json)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(existing) = existing {
+            let existing_id: i64 = existing.try_get(0)?;
+            sqlx::query(r#"UPDATE logs SET jsinfo_id = $1 WHERE jsinfo_id = $2"#)
+                .bind(existing_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(r#"DELETE FROM jsinfos WHERE id = $1"#)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query(r#"UPDATE jsinfos SET hash = $1, value = $2 WHERE id = $3"#)
+                .bind(&hash)
+                .bind(&json)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        count += 1;
+    }
+    tx.commit().await?;
+    dioxus::logger::tracing::info!("converted {count} jsinfos from TOML to JSON");
+    Ok(count)
+}
+
+/// Keeps the lookup values from the garbage collection until the end of the transaction.
+async fn lock_lookups_shared(tx: &mut Transaction<'_, Postgres>) -> Result<()> {
+    sqlx::query(r#"SELECT pg_advisory_xact_lock_shared($1)"#)
+        .bind(LOOKUP_GC_LOCK)
+        .execute(&mut **tx)
+        .await?;
+    Ok(())
+}
+
+/// Macro to generate functions that either store a new value or fetch the existing ID.
+/// The ID is remembered in `ids` for the rest of the transaction.
+///
+/// `ON CONFLICT DO NOTHING` returns no row when the value exists, so that it is selected then.
+macro_rules! simple_get_or_store {
+    ($func:ident, $tbl: expr) => {
+        async fn $func(
+            tx: &mut Transaction<'_, Postgres>,
+            ids: &mut LookupIds,
+            val: &str,
+        ) -> Result<i64> {
+            let key = ($tbl, val.to_string());
+            if let Some(id) = ids.get(&key) {
+                return Ok(*id);
+            }
+            let r = sqlx::query(concat!(
+                r#"INSERT INTO "#,
+                $tbl,
+                r#" (value) VALUES ($1) ON CONFLICT (value) DO NOTHING RETURNING id"#
+            ))
+            .bind(val)
+            .fetch_optional(&mut **tx)
+            .await?;
+            let row = match r {
+                Some(row) => row,
+                None => {
+                    sqlx::query(concat!(r#"SELECT id FROM "#, $tbl, r#" WHERE value = $1"#))
+                        .bind(val)
+                        .fetch_one(&mut **tx)
+                        .await?
+                }
+            };
+            let id = row.get(0);
+            ids.insert(key, id);
+            Ok(id)
+        }
+    };
+}
+
+/// Retrieves or stores the user agent.
+/// A new user agent is parsed into the structured columns at once.
+async fn get_or_store_user_agent(
+    tx: &mut Transaction<'_, Postgres>,
+    ids: &mut LookupIds,
+    val: &str,
+) -> Result<i64> {
+    let key = ("user_agents", val.to_string());
+    if let Some(id) = ids.get(&key) {
+        return Ok(*id);
+    }
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO user_agents (value) VALUES ($1)"#,
+        r#" ON CONFLICT (value) DO NOTHING RETURNING id"#
+    ))
+    .bind(val)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let id = match r {
+        Some(row) => {
+            let id = row.get(0);
+            store_parsed_user_agent(&mut **tx, id, &ParsedUserAgent::parse(val)).await?;
+            id
+        }
+        None => sqlx::query(r#"SELECT id FROM user_agents WHERE value = $1"#)
+            .bind(val)
+            .fetch_one(&mut **tx)
+            .await?
+            .get(0),
+    };
+    ids.insert(key, id);
+    Ok(id)
+}
+
+simple_get_or_store!(get_or_store_referrer, "referrers");
+
+simple_get_or_store!(get_or_store_ip_address, "ip_addresses");
+
+simple_get_or_store!(get_or_store_bicmid, "bicmids");
+
+simple_get_or_store!(get_or_store_user, "users");
+
+simple_get_or_store!(get_or_store_fingerprint, "fingerprints");
+
+simple_get_or_store!(get_or_store_path, "paths");
+
+simple_get_or_store!(get_or_store_event_name, "event_names");
+
+/// Retrieves or stores JS information in the database.
+/// Uses a hash to optimize lookup.
+async fn get_or_store_jsinfo(
+    tx: &mut Transaction<'_, Postgres>,
+    ids: &mut LookupIds,
+    info_s: &str,
+) -> Result<i64> {
+    let key = ("jsinfos", info_s.to_string());
+    if let Some(id) = ids.get(&key) {
+        return Ok(*id);
+    }
+    let hash = create_jsinfo_hash(info_s);
+    let hash_s = hash.as_str();
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO jsinfos (hash, value) VALUES ($1, $2)"#,
+        r#" ON CONFLICT (hash) DO NOTHING RETURNING id"#
+    ))
+    .bind(hash_s)
+    .bind(info_s)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let row = match r {
+        Some(row) => row,
+        None => {
+            sqlx::query(r#"SELECT id FROM jsinfos WHERE hash = $1 AND value = $2"#)
+                .bind(hash_s)
+                .bind(info_s)
+                .fetch_one(&mut **tx)
+                .await?
+        }
+    };
+    let id = row.get(0);
+    ids.insert(key, id);
+    Ok(id)
+}
+
+/// Creates a SHA-256 hash of the JS info string and encodes it in Base64.
+fn create_jsinfo_hash(s: &str) -> String {
+    use base64::Engine;
+
+    let hash_bytes = hmac_sha256::Hash::hash(s.as_bytes());
+    base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash_bytes)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::migrate::{undo_migrations, POSTGRES_MIGRATOR};
+    use super::super::test_postgres::PgCluster;
+    use super::*;
+
+    fn values(ip_address: &str, jsinfo: &str) -> VisitValues {
+        VisitValues {
+            ip_address: ip_address.to_string(),
+            ip_address_mode: "full",
+            fingerprint: String::new(),
+            jsinfo: jsinfo.to_string(),
+            consent: "",
+            bot_score: 0,
+        }
+    }
+
+    fn save_req(bicmid: &str, user: &str, ua: &str) -> SaveBroInfoRequest {
+        let mut req = SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            user: user.to_string(),
+            ..Default::default()
+        };
+        req.broinfo.basic.user_agent = ua.into();
+        req
+    }
+
+    #[tokio::test]
+    async fn test_postgres_store_00() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        store.health().await.unwrap();
+        assert!(store.db_path().await.unwrap().starts_with("postgres://"));
+        //
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        for (bicmid, user, ua) in [("b1", "", firefox), ("b2", "u1", firefox), ("b1", "u1", "")] {
+            store
+                .save_broinfo(&save_req(bicmid, user, ua), "192.0.2.1")
+                .await
+                .unwrap();
+        }
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.entries[0].bicmid, "b1");
+        assert_eq!(page.entries[0].user, "u1");
+        assert_eq!(page.entries[0].ip_address, "192.0.2.1");
+        assert_eq!(page.entries[0].create_at.len(), 19);
+        let q = LogQuery {
+            user: Some("u1".to_string()),
+            per_page: 1,
+            page: 1,
+            ..Default::default()
+        };
+        let page = store.query_logs(&q).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].bicmid, "b2");
+        //
+        let stats = store.query_log_stats(&LogQuery::default()).await.unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.browsers[0].name, "Firefox");
+        assert_eq!(stats.browsers[0].count, 2);
+        //
+        // the lookup values are deduplicated
+        let count = |tbl: &'static str| {
+            let pool = store.pool.clone();
+            async move {
+                sqlx::query(&format!("SELECT COUNT(*) FROM {tbl}"))
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+                    .get::<i64, _>(0)
+            }
+        };
+        assert_eq!(count("jsinfos").await, 2);
+        assert_eq!(count("user_agents").await, 2);
+        assert_eq!(count("bicmids").await, 3);
+        // the user agents are parsed when stored, once more on request
+        let sql = "SELECT COUNT(*) FROM user_agents WHERE browser_name = 'Firefox'";
+        let n: i64 = sqlx::query(sql)
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+        assert_eq!(store.reparse_user_agents(false).await.unwrap(), 0);
+        assert_eq!(store.reparse_user_agents(true).await.unwrap(), 1);
+        //
+        undo_migrations(&POSTGRES_MIGRATOR, &store.pool, 0)
+            .await
+            .unwrap();
+        assert!(store.query_logs(&LogQuery::default()).await.is_err());
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_saves_01() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        //
+        // every new value is saved by several tasks at once,
+        // and the last 100 requests are retries of the first 100
+        let handles: Vec<_> = (0..300)
+            .map(|i| {
+                let pool = store.pool.clone();
+                let k = i % 200;
+                tokio::spawn(async move {
+                    let mut req = save_req(
+                        &format!("b{}", k % 20),
+                        &format!("u{}", k % 7),
+                        &format!("Mozilla/5.0 ({})", k % 5),
+                    );
+                    req.request_id = format!("r{k}");
+                    let ip = format!("192.0.2.{}", k % 11);
+                    let jsinfo = format!("n = {}", k % 3);
+                    store_broinfo(&pool, &req, &values(&ip, &jsinfo), 1800).await
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+        //
+        let count = |sql: &'static str| {
+            let pool = store.pool.clone();
+            async move {
+                sqlx::query(sql)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+                    .get::<i64, _>(0)
+            }
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM logs").await, 200);
+        assert_eq!(
+            count("SELECT COUNT(DISTINCT request_id) FROM logs").await,
+            200
+        );
+        // each visit is counted once in its session
+        assert_eq!(
+            count("SELECT COALESCE(SUM(hits), 0)::BIGINT FROM sessions").await,
+            200
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM logs WHERE session_id = 0").await,
+            0
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM user_agents").await, 1 + 5);
+        assert_eq!(count("SELECT COUNT(*) FROM ip_addresses").await, 1 + 11);
+        assert_eq!(count("SELECT COUNT(*) FROM bicmids").await, 1 + 20);
+        assert_eq!(count("SELECT COUNT(*) FROM users").await, 1 + 7);
+        assert_eq!(count("SELECT COUNT(*) FROM jsinfos").await, 1 + 3);
+        store.pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn test_prune_02() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        for (bicmid, ip) in [
+            ("b1", "192.0.2.1"),
+            ("b2", "192.0.2.2"),
+            ("b3", "192.0.2.3"),
+        ] {
+            store
+                .save_broinfo(&save_req(bicmid, "", ""), ip)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE logs SET create_at = '2000-01-01 00:00:00' WHERE id = 1")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        //
+        let mut policy = RetentionConfig {
+            max_age_days: 30,
+            max_rows: 0,
+            interval_secs: 3600,
+            vacuum: VacuumMode::Incremental,
+        };
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.logs, 1);
+        // "b1", "192.0.2.1" and the session of "b1"
+        assert_eq!(report.lookups, 3);
+        //
+        policy.max_rows = 1;
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.logs, 1);
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].bicmid, "b3");
+        // the special rows of ID 0 are kept
+        let row = sqlx::query("SELECT COUNT(*) FROM bicmids WHERE id = 0")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<i64, _>(0), 1);
+    }
+
+    #[tokio::test]
+    async fn test_erase_subject_03() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        for (bicmid, user, ip) in [
+            ("b1", "u1", "192.0.2.1"),
+            ("b2", "u1", "192.0.2.2"),
+            ("b3", "", "192.0.2.3"),
+        ] {
+            store
+                .save_broinfo(&save_req(bicmid, user, ""), ip)
+                .await
+                .unwrap();
+        }
+        //
+        let subject = EraseSubject::Bicmid("b1".to_string());
+        let report = store.erase_subject(&subject).await.unwrap();
+        // "192.0.2.1" and the session; "u1" is still referred to by "b2"
+        let expected = EraseReport {
+            logs: 1,
+            page_views: 0,
+            events: 0,
+            subjects: 1,
+            lookups: 2,
+        };
+        assert_eq!(report, expected);
+        //
+        let subject = EraseSubject::User("u1".to_string());
+        let report = store.erase_subject(&subject).await.unwrap();
+        // "b2", "192.0.2.2" and the session of "b2"
+        let expected = EraseReport {
+            logs: 1,
+            page_views: 0,
+            events: 0,
+            subjects: 1,
+            lookups: 3,
+        };
+        assert_eq!(report, expected);
+        let report = store.erase_subject(&subject).await.unwrap();
+        assert_eq!(report, EraseReport::default());
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].bicmid, "b3");
+        // the empty values are never erased
+        let report = store
+            .erase_subject(&EraseSubject::User(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(report, EraseReport::default());
+    }
+
+    #[cfg(feature = "geoip")]
+    #[tokio::test]
+    async fn test_geoip_04() {
+        use super::super::geoip::test::fixture_reader;
+        //
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let mut store = PostgresStore::connect(&url).await.unwrap();
+        // saved before the GeoIP is configured
+        store
+            .save_broinfo(&save_req("b1", "", ""), "198.51.100.1")
+            .await
+            .unwrap();
+        store.geoip = Some(fixture_reader());
+        for (bicmid, ip) in [("b2", "192.0.2.1"), ("b3", "203.0.113.1"), ("b4", "")] {
+            store
+                .save_broinfo(&save_req(bicmid, "", ""), ip)
+                .await
+                .unwrap();
+        }
+        let geo = |ip: &'static str| {
+            let pool = store.pool.clone();
+            async move {
+                let row = sqlx::query(concat!(
+                    r#"SELECT country_code, city_name, autonomous_system_number,"#,
+                    r#" geo_resolved_at IS NOT NULL FROM ip_addresses WHERE value = $1"#
+                ))
+                .bind(ip)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+                let country: Option<String> = row.get(0);
+                let city: Option<String> = row.get(1);
+                let asn: Option<i64> = row.get(2);
+                let resolved: bool = row.get(3);
+                (country, city, asn, resolved)
+            }
+        };
+        let tokyo = (
+            Some("JP".to_string()),
+            Some("Tokyo".to_string()),
+            Some(64500),
+            true,
+        );
+        assert_eq!(geo("192.0.2.1").await, tokyo);
+        // not in the database, but resolved
+        assert_eq!(geo("203.0.113.1").await, (None, None, None, true));
+        assert_eq!(geo("198.51.100.1").await, (None, None, None, false));
+        assert_eq!(geo("").await, (None, None, None, false));
+        //
+        assert_eq!(store.backfill_geoip().await.unwrap(), 1);
+        let us = (Some("US".to_string()), None, None, true);
+        assert_eq!(geo("198.51.100.1").await, us);
+        assert_eq!(store.backfill_geoip().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_05() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let mut store = PostgresStore::connect(&url).await.unwrap();
+        store.fingerprinter = Some(Fingerprinter::new("secret").unwrap());
+        let req = |bicmid: &str, session_id: &str| SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            session_id: session_id.to_string(),
+            ..Default::default()
+        };
+        for (bicmid, session_id) in [("b1", "s1"), ("b1", "s1"), ("b2", "s2"), ("", "")] {
+            store
+                .save_broinfo(&req(bicmid, session_id), "")
+                .await
+                .unwrap();
+        }
+        // timed out
+        sqlx::query("UPDATE sessions SET last_seen_at = '2000-01-01 00:00:00' WHERE id = 1")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        store.save_broinfo(&req("b1", "s1"), "").await.unwrap();
+        let rows = sqlx::query("SELECT session_id FROM logs ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let session_ids: Vec<i64> = rows.iter().map(|r| r.get(0)).collect();
+        assert_eq!(session_ids, [1, 1, 2, 0, 3]);
+        let hits: i64 = sqlx::query("SELECT hits FROM sessions WHERE id = 1")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(hits, 2);
+        // the same fingerprint of "b1" and "b2"
+        let groups = store
+            .query_fingerprints(&FingerprintQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        let bicmids: Vec<(&str, i64)> = groups[0]
+            .bicmids
+            .iter()
+            .map(|b| (b.bicmid.as_str(), b.visits))
+            .collect();
+        assert_eq!(bicmids, [("b1", 3), ("b2", 1)]);
+    }
+
+    #[tokio::test]
+    async fn test_page_views_06() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        let view = |path: &str, referrer: &str| PageViewRequest {
+            path: path.to_string(),
+            title: format!("title of {path}"),
+            referrer: referrer.to_string(),
+            bicmid: "b1".to_string(),
+            session_id: "s1".to_string(),
+            ..Default::default()
+        };
+        for (path, referrer) in [("/", "https://example.com/"), ("/blog", "/")] {
+            store.save_pageview(&view(path, referrer)).await.unwrap();
+        }
+        let row = sqlx::query("SELECT hits, page_views FROM sessions WHERE id = 1")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!((row.get::<i64, _>(0), row.get::<i64, _>(1)), (0, 2));
+        let report = store
+            .erase_subject(&EraseSubject::Bicmid("b1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(report.page_views, 2);
+        // the paths, the referrers and the session
+        assert_eq!(report.lookups, 5);
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_07() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        let req = |request_id: &str| SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            request_id: request_id.to_string(),
+            ..Default::default()
+        };
+        for request_id in ["r1", "r1", "r2", "", ""] {
+            store.save_broinfo(&req(request_id), "").await.unwrap();
+        }
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 4);
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM logs WHERE request_id = 'r1'")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+    }
+
+    #[test]
+    fn test_open_on_another_runtime_08() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let runtime = || {
+            tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+        };
+        let url = runtime().block_on(cluster.create_database()).unwrap();
+        // opened on a runtime that ends right after, as `init_store()` does
+        let store = runtime().block_on(PostgresStore::connect(&url)).unwrap();
+        //
+        // and used on another one
+        runtime().block_on(async {
+            store.health().await.unwrap();
+            store
+                .save_broinfo(&save_req("b1", "", "Mozilla/5.0"), "192.0.2.1")
+                .await
+                .unwrap();
+            let page = store.query_logs(&LogQuery::default()).await.unwrap();
+            assert_eq!(page.total, 1);
+        });
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    #[tokio::test]
+    async fn test_user_agent_request_ids_09() {
+        let Some(cluster) = PgCluster::start() else {
+            eprintln!("skipped: PostgreSQL is not available");
+            return;
+        };
+        let url = cluster.create_database().await.unwrap();
+        let store = PostgresStore::connect(&url).await.unwrap();
+        let req = |ua: &str, request_id: &str| SaveUserAgentRequest {
+            ua: ua.into(),
+            request_id: request_id.to_string(),
+        };
+        for (ua, request_id) in [("ua1", "r1"), ("ua2", "r1"), ("ua3", "r2"), ("ua4", "")] {
+            store.save_user_agent(&req(ua, request_id)).await.unwrap();
+        }
+        let rows = sqlx::query("SELECT value FROM user_agents WHERE id <> 0 ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let uas: Vec<String> = rows.iter().map(|r| r.get(0)).collect();
+        assert_eq!(uas, ["ua1", "ua3", "ua4"]);
+    }
+}
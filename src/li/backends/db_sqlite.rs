@@ -2,57 +2,275 @@
 //! Handles database initialization, table creation, and data persistence.
 
 use anyhow::Result;
-use browserinfo::Browser;
-use dioxus::prelude::*;
+use async_trait::async_trait;
+use sqlx::Row;
+use sqlx::Transaction;
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use super::config::{RetentionConfig, SessionConfig, VacuumMode};
+use super::fingerprint::Fingerprinter;
+#[cfg(feature = "geoip")]
+use super::geoip::{GeoInfo, GeoIpReader};
+use super::privacy::IpAddressPrivacy;
+use super::retention::{PruneReport, DELETE_UNREFERENCED};
+use super::store::{BroInfoStore, SaveBroInfoItem, SessionHit, VisitValues};
+use super::user_agent::ParsedUserAgent;
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats, SaveBroInfoRequest};
+use super::{EventRequest, FingerprintGroup, FingerprintQuery, PageViewRequest};
 
 #[cfg(feature = "backend_user_agent")]
-use browserinfo::UserAgent;
+use super::SaveUserAgentRequest;
 
-#[cfg(feature = "server")]
-use std::path::PathBuf;
+/// The IDs of the lookup values already stored in a transaction, by table and value.
+type LookupIds = HashMap<(&'static str, String), i64>;
 
-#[cfg(feature = "server")]
-use super::get_ip_address_string;
+/// The store of the browser information in a SQLite database.
+pub struct SqliteStore {
+    pool: sqlx::SqlitePool,
+    db_path: String,
+    ip_privacy: IpAddressPrivacy,
+    fingerprinter: Option<Fingerprinter>,
+    session: SessionConfig,
+    #[cfg(feature = "geoip")]
+    geoip: Option<GeoIpReader>,
+}
 
-#[cfg(feature = "server")]
-use sqlx::Transaction;
+impl SqliteStore {
+    /// Opens the configured database file.
+    /// Also runs pending migrations if necessary.
+    pub async fn open() -> Result<Self> {
+        let db_path = get_db_path_();
+        let pool = connect_sqlx_pool(&db_path).await?;
+        // Create or upgrade tables to the latest schema
+        create_tables(&pool).await?;
+        let cfg = super::config::BackendConfig::global();
+        Ok(Self {
+            pool,
+            db_path: db_path.display().to_string(),
+            ip_privacy: IpAddressPrivacy::from_config(&cfg.privacy)?,
+            fingerprinter: Fingerprinter::from_config(&cfg.fingerprint)?,
+            session: cfg.session.clone(),
+            #[cfg(feature = "geoip")]
+            geoip: GeoIpReader::from_config(&cfg.geoip)?,
+        })
+    }
 
-#[cfg(feature = "server")]
-use sqlx::Row;
+    /// Opens the database file at `db_path`.
+    #[cfg(test)]
+    pub async fn open_file(db_path: &std::path::Path) -> Result<Self> {
+        let pool = connect_sqlx_pool(db_path).await?;
+        create_tables(&pool).await?;
+        Ok(Self {
+            pool,
+            db_path: db_path.display().to_string(),
+            ip_privacy: IpAddressPrivacy::default(),
+            fingerprinter: None,
+            session: SessionConfig::default(),
+            #[cfg(feature = "geoip")]
+            geoip: None,
+        })
+    }
 
-#[cfg(feature = "server")]
-use dioxus::fullstack::Lazy;
+    /// Opens a private in-memory database.
+    #[cfg(test)]
+    pub async fn open_in_memory() -> Result<Self> {
+        // a single connection, since each in-memory connection is its own database
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await?;
+        create_tables(&pool).await?;
+        Ok(Self {
+            pool,
+            db_path: ":memory:".to_string(),
+            ip_privacy: IpAddressPrivacy::default(),
+            fingerprinter: None,
+            session: SessionConfig::default(),
+            #[cfg(feature = "geoip")]
+            geoip: None,
+        })
+    }
+
+    /// Returns the values of the visit `req` from `ip_address` as they are stored.
+    fn visit_values(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<VisitValues> {
+        Ok(VisitValues {
+            ip_address: self.ip_privacy.apply(ip_address),
+            ip_address_mode: self.ip_privacy.mode().as_str(),
+            fingerprint: self
+                .fingerprinter
+                .as_ref()
+                .filter(|_| req.consent_level().allows_fingerprint())
+                .map(|f| f.compute(&req.broinfo.jsinfo))
+                .unwrap_or_default(),
+            jsinfo: jsinfo_db_string(req)?,
+            consent: req.consent.map(|c| c.as_str()).unwrap_or_default(),
+            bot_score: req.bot_score as i64,
+        })
+    }
 
-/// Global SQLite database pool, initialized lazily on the server.
-#[cfg(feature = "server")]
-static DB: Lazy<sqlx::SqlitePool> = Lazy::new(|| async move {
-    let pool = create_sqlx_pool().await?;
-    dioxus::Ok(pool)
-});
+    /// Resolves the GeoIP of the stored `ip_address`, if it is not resolved yet.
+    #[cfg(feature = "geoip")]
+    async fn resolve_geoip<'c, E>(&self, e: E, ip_address: &str) -> Result<()>
+    where
+        E: sqlx::SqliteExecutor<'c>,
+    {
+        let Some(reader) = &self.geoip else {
+            return Ok(());
+        };
+        if ip_address.is_empty() {
+            return Ok(());
+        }
+        let info = reader.lookup(ip_address)?.unwrap_or_default();
+        store_geo_info(e, ip_address, &info).await
+    }
+}
 
-/// Creates and initializes the SQLite connection pool.
-/// Also runs migrations (table creation) if necessary.
-#[cfg(feature = "server")]
-async fn create_sqlx_pool() -> Result<sqlx::sqlite::SqlitePool> {
+#[async_trait]
+impl BroInfoStore for SqliteStore {
+    fn kind(&self) -> &'static str {
+        "sqlite"
+    }
+
+    async fn db_path(&self) -> Result<String> {
+        Ok(self.db_path.clone())
+    }
+
+    async fn health(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Normalizes and stores JS info, User Agent, Referrer, IP Address, BICMID, and User ID.
+    async fn save_broinfo(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<()> {
+        let values = self.visit_values(req, ip_address)?;
+        store_broinfo(&self.pool, req, &values, self.session.timeout_secs).await?;
+        #[cfg(feature = "geoip")]
+        self.resolve_geoip(&self.pool, &values.ip_address).await?;
+        //
+        dioxus::logger::tracing::debug!("save_broinfo: {:?}", values.jsinfo);
+        Ok(())
+    }
+
+    /// Stores the visits of `batch` in one transaction.
+    async fn save_broinfo_batch(&self, batch: &[SaveBroInfoItem]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let mut ids = LookupIds::new();
+        for item in batch {
+            let values = self.visit_values(&item.req, &item.ip_address)?;
+            let timeout_secs = self.session.timeout_secs;
+            store_broinfo_tx(&mut tx, &mut ids, &item.req, &values, timeout_secs).await?;
+            #[cfg(feature = "geoip")]
+            self.resolve_geoip(&mut *tx, &values.ip_address).await?;
+        }
+        tx.commit().await?;
+        dioxus::logger::tracing::debug!("save_broinfo_batch: {} visits", batch.len());
+        Ok(())
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    async fn save_user_agent(&self, req: &SaveUserAgentRequest) -> Result<()> {
+        let ua_s = req.ua.get();
+        //
+        let mut tx = self.pool.begin().await?;
+        // a retried request, whose `request_id` is already stored, is not stored again
+        if !req.request_id.is_empty() {
+            let r = sqlx::query(concat!(
+                r#"INSERT INTO user_agent_requests (value) VALUES (?)"#,
+                r#" ON CONFLICT(value) DO NOTHING"#
+            ))
+            .bind(&req.request_id)
+            .execute(&mut *tx)
+            .await?;
+            if r.rows_affected() == 0 {
+                return Ok(());
+            }
+        }
+        //
+        let _user_agent_id = get_or_store_user_agent(&mut tx, &mut LookupIds::new(), ua_s).await?;
+        //
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn save_pageview(&self, req: &PageViewRequest) -> Result<()> {
+        store_pageview(&self.pool, req, self.session.timeout_secs).await
+    }
+
+    async fn save_event(&self, req: &EventRequest) -> Result<()> {
+        store_event(&self.pool, req, self.session.timeout_secs).await
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<LogPage> {
+        fetch_logs(&self.pool, query).await
+    }
+
+    async fn query_log_stats(&self, query: &LogQuery) -> Result<LogStats> {
+        fetch_log_stats(&self.pool, query).await
+    }
+
+    async fn erase_subject(&self, subject: &EraseSubject) -> Result<EraseReport> {
+        erase_subject(&self.pool, subject).await
+    }
+
+    async fn query_fingerprints(&self, query: &FingerprintQuery) -> Result<Vec<FingerprintGroup>> {
+        fetch_fingerprints(&self.pool, query).await
+    }
+
+    async fn prune(&self, policy: &RetentionConfig) -> Result<PruneReport> {
+        let report = delete_expired(&self.pool, policy).await?;
+        if report.logs + report.page_views + report.events > 0 {
+            vacuum(&self.pool, policy.vacuum).await?;
+        }
+        Ok(report)
+    }
+
+    async fn reparse_user_agents(&self, all: bool) -> Result<u64> {
+        reparse_user_agents(&self.pool, all).await
+    }
+
+    #[cfg(feature = "geoip")]
+    async fn backfill_geoip(&self) -> Result<u64> {
+        match &self.geoip {
+            Some(reader) => backfill_geoip(&self.pool, reader).await,
+            None => Ok(0),
+        }
+    }
+}
+
+/// Opens the SQLite connection pool without touching the schema.
+async fn connect_sqlx_pool(db_path: &std::path::Path) -> Result<sqlx::sqlite::SqlitePool> {
+    use sqlx::sqlite::SqliteAutoVacuum;
     use sqlx::sqlite::SqliteConnectOptions;
     use sqlx::sqlite::SqlitePoolOptions;
     use std::str::FromStr;
     //
-    let db_path = get_db_path_();
     let sq_uri = format!("sqlite://{}", db_path.display());
     // Open the database from the persisted "broinfo.sqlite3" file
-    let opts = SqliteConnectOptions::from_str(&sq_uri)?.create_if_missing(true);
+    // `auto_vacuum` takes effect on a new database, or on an old one after `VACUUM`
+    let opts = SqliteConnectOptions::from_str(&sq_uri)?
+        .create_if_missing(true)
+        .auto_vacuum(SqliteAutoVacuum::Incremental);
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
         .connect_with(opts)
         .await?;
-    // Create tables if it doesn't already exist
-    create_tables(&pool).await?;
     Ok(pool)
 }
 
+/// Reverts the database schema to the migration `target` version.
+///
+/// `target = 0` reverts all migrations, dropping every table.
+pub async fn migrate_undo(target: i64) -> Result<()> {
+    use super::migrate::{undo_migrations, SQLITE_MIGRATOR};
+    //
+    let pool = connect_sqlx_pool(&get_db_path_()).await?;
+    undo_migrations(&SQLITE_MIGRATOR, &pool, target).await?;
+    pool.close().await;
+    Ok(())
+}
+
 /// Resolves the database file path based on environment variables or defaults.
-#[cfg(feature = "server")]
 fn get_db_path_() -> PathBuf {
     let cfg = super::config::BackendConfig::global();
     let mut data_dir = data_base_dir();
@@ -60,7 +278,6 @@ fn get_db_path_() -> PathBuf {
     data_dir
 }
 /*
-#[cfg(feature = "server")]
 fn get_db_path_() -> PathBuf {
     let key1 = "BROWSERINFOCM_DB_PATH";
     if let Ok(s) = std::env::var(key1) {
@@ -86,7 +303,6 @@ fn get_db_path_() -> PathBuf {
 */
 
 /// Returns the base directory for data storage.
-#[cfg(feature = "server")]
 fn data_base_dir() -> PathBuf {
     let cfg = super::config::BackendConfig::global();
     let pb = PathBuf::from(&cfg.database.base_path);
@@ -94,7 +310,6 @@ fn data_base_dir() -> PathBuf {
     pb
 }
 /*
-#[cfg(feature = "server")]
 fn data_dir() -> PathBuf {
     let data_dir: PathBuf;
     #[cfg(not(feature = "backend_homedir"))]
@@ -112,7 +327,6 @@ fn data_dir() -> PathBuf {
 
 /// Returns the data directory within the user's home directory.
 #[cfg(feature = "backend_homedir")]
-#[cfg(feature = "server")]
 fn data_dir_on_desktop() -> PathBuf {
     let mut data_dir = match dirs::home_dir() {
         Some(home) => home,
@@ -127,136 +341,635 @@ fn data_dir_on_desktop() -> PathBuf {
     data_dir
 }
 
-/// Server function to get the current database path as a string.
-#[post("/api/v1/mikan1")]
-pub async fn get_db_path() -> Result<String> {
-    let db_path = get_db_path_();
-    let db_path_s = db_path.display().to_string();
-    dioxus::logger::tracing::debug!("db_path: {db_path_s:?}");
-    Ok(db_path_s)
-}
-
-/// Server function to get the client's IP address.
-#[post("/api/v1/ringo1", headers: dioxus::fullstack::HeaderMap)]
-pub async fn get_ip_address() -> Result<String> {
-    let ipaddr = get_ip_address_string(&headers);
-    dioxus::logger::tracing::debug!("ipaddr: {ipaddr:?}");
-    Ok(ipaddr)
+/// The `FROM` and `WHERE` clauses shared by the logs queries.
+///
+/// Binds: bicmid, user, since, until; each twice.
+macro_rules! logs_from_where {
+    () => {
+        concat!(
+            r#" FROM logs"#,
+            r#" INNER JOIN jsinfos ON jsinfos.id = logs.jsinfo_id"#,
+            r#" INNER JOIN user_agents ON user_agents.id = logs.user_agent_id"#,
+            r#" INNER JOIN referrers ON referrers.id = logs.referrer_id"#,
+            r#" INNER JOIN ip_addresses ON ip_addresses.id = logs.ip_address_id"#,
+            r#" INNER JOIN bicmids ON bicmids.id = logs.bicmid_id"#,
+            r#" INNER JOIN users ON users.id = logs.user_id"#,
+            r#" WHERE (? IS NULL OR bicmids.value = ?)"#,
+            r#" AND (? IS NULL OR users.value = ?)"#,
+            r#" AND (? IS NULL OR logs.create_at >= ?)"#,
+            r#" AND (? IS NULL OR logs.create_at < ?)"#,
+        )
+    };
 }
 
-/// Server function to save the user agent to the database.
-#[cfg(feature = "backend_user_agent")]
-#[post("/api/v1/useragent1")]
-pub async fn save_user_agent(req: super::SaveUserAgentRequest) -> Result<()> {
-    let ua_s = req.ua.get();
+/// Fetches one page of the stored visits matching `query`.
+async fn fetch_logs(pool: &sqlx::SqlitePool, query: &LogQuery) -> Result<LogPage> {
+    use super::LogEntry;
     //
-    #[cfg(feature = "backend_text")]
-    write_backend_text("user_agent.txt", ua_s)?;
+    let total: i64 = sqlx::query(concat!(r#"SELECT COUNT(*)"#, logs_from_where!()))
+        .bind(&query.bicmid)
+        .bind(&query.bicmid)
+        .bind(&query.user)
+        .bind(&query.user)
+        .bind(&query.since)
+        .bind(&query.since)
+        .bind(&query.until)
+        .bind(&query.until)
+        .fetch_one(pool)
+        .await?
+        .get(0);
     //
-    {
-        let mut tx = DB.begin().await?;
-        //
-        let _user_agent_id = get_or_store_user_agent(&mut tx, ua_s).await?;
-        //
-        tx.commit().await?;
-    }
+    let rows = sqlx::query(concat!(
+        r#"SELECT logs.id, logs.create_at, bicmids.value AS bicmid, users.value AS user,"#,
+        r#" user_agents.value AS user_agent, referrers.value AS referrer,"#,
+        r#" ip_addresses.value AS ip_address, logs.ip_address_mode, jsinfos.value AS jsinfo,"#,
+        r#" logs.bot_score"#,
+        logs_from_where!(),
+        r#" ORDER BY logs.id DESC LIMIT ? OFFSET ?"#
+    ))
+    .bind(&query.bicmid)
+    .bind(&query.bicmid)
+    .bind(&query.user)
+    .bind(&query.user)
+    .bind(&query.since)
+    .bind(&query.since)
+    .bind(&query.until)
+    .bind(&query.until)
+    .bind(query.limit() as i64)
+    .bind(query.offset() as i64)
+    .fetch_all(pool)
+    .await?;
     //
-    dioxus_logger::tracing::debug!("save_user_agent: {ua_s:?}");
-    //
-    #[cfg(feature = "backend_delay")]
-    let _ = sleep_x(2000).await;
+    let entries = rows
+        .iter()
+        .map(|row| {
+            Ok(LogEntry {
+                id: row.try_get("id")?,
+                create_at: row.try_get("create_at")?,
+                bicmid: row.try_get("bicmid")?,
+                user: row.try_get("user")?,
+                user_agent: row.try_get("user_agent")?,
+                referrer: row.try_get("referrer")?,
+                ip_address: row.try_get("ip_address")?,
+                ip_address_mode: row.try_get("ip_address_mode")?,
+                jsinfo: row.try_get("jsinfo")?,
+                bot_score: row.try_get("bot_score")?,
+            })
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(LogPage {
+        entries,
+        total,
+        page: query.page,
+        per_page: query.limit(),
+    })
+}
+
+/// Counts the stored visits matching `query` by browser and by operating system.
+///
+/// The pagination of `query` is ignored.
+async fn fetch_log_stats(pool: &sqlx::SqlitePool, query: &LogQuery) -> Result<LogStats> {
+    let rows = sqlx::query(concat!(
+        r#"SELECT user_agents.value, COUNT(*)"#,
+        logs_from_where!(),
+        r#" GROUP BY user_agents.value"#
+    ))
+    .bind(&query.bicmid)
+    .bind(&query.bicmid)
+    .bind(&query.user)
+    .bind(&query.user)
+    .bind(&query.since)
+    .bind(&query.since)
+    .bind(&query.until)
+    .bind(&query.until)
+    .fetch_all(pool)
+    .await?;
+    let counts = rows
+        .iter()
+        .map(|row| Ok((row.try_get::<String, _>(0)?, row.try_get::<i64, _>(1)?)))
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(LogStats::from_user_agent_counts(
+        counts.iter().map(|(ua, n)| (ua.as_str(), *n)),
+    ))
+}
+
+/// Fetches the fingerprints matching `query` and the BICMIDs that share them,
+/// the fingerprints of the most BICMIDs first.
+///
+/// The visits without a fingerprint or a BICMID are not linked.
+async fn fetch_fingerprints(
+    pool: &sqlx::SqlitePool,
+    query: &FingerprintQuery,
+) -> Result<Vec<FingerprintGroup>> {
+    use super::FingerprintBicmid;
     //
-    Ok(())
+    let rows = sqlx::query(concat!(
+        r#"SELECT fingerprints.value AS fingerprint, bicmids.value AS bicmid,"#,
+        r#" COUNT(*) AS visits, MIN(logs.create_at) AS first_seen,"#,
+        r#" MAX(logs.create_at) AS last_seen"#,
+        r#" FROM (SELECT fingerprint_id, COUNT(DISTINCT bicmid_id) AS n FROM logs"#,
+        r#" WHERE fingerprint_id <> 0 AND bicmid_id <> 0"#,
+        r#" AND (? IS NULL OR fingerprint_id IN"#,
+        r#" (SELECT id FROM fingerprints WHERE value = ?))"#,
+        r#" AND (? IS NULL OR fingerprint_id IN (SELECT linked.fingerprint_id FROM logs AS linked"#,
+        r#" INNER JOIN bicmids ON bicmids.id = linked.bicmid_id WHERE bicmids.value = ?))"#,
+        r#" GROUP BY fingerprint_id HAVING COUNT(DISTINCT bicmid_id) >= ?"#,
+        r#" ORDER BY n DESC, fingerprint_id LIMIT ?) AS shared"#,
+        r#" INNER JOIN logs ON logs.fingerprint_id = shared.fingerprint_id AND logs.bicmid_id <> 0"#,
+        r#" INNER JOIN fingerprints ON fingerprints.id = shared.fingerprint_id"#,
+        r#" INNER JOIN bicmids ON bicmids.id = logs.bicmid_id"#,
+        r#" GROUP BY shared.fingerprint_id, shared.n, fingerprints.value, bicmids.value"#,
+        r#" ORDER BY shared.n DESC, shared.fingerprint_id, last_seen DESC, bicmids.value"#
+    ))
+    .bind(&query.fingerprint)
+    .bind(&query.fingerprint)
+    .bind(&query.bicmid)
+    .bind(&query.bicmid)
+    .bind(query.min_bicmids() as i64)
+    .bind(query.limit() as i64)
+    .fetch_all(pool)
+    .await?;
+    let rows = rows
+        .iter()
+        .map(|row| {
+            let bicmid = FingerprintBicmid {
+                bicmid: row.try_get("bicmid")?,
+                visits: row.try_get("visits")?,
+                first_seen: row.try_get("first_seen")?,
+                last_seen: row.try_get("last_seen")?,
+            };
+            Ok((row.try_get("fingerprint")?, bicmid))
+        })
+        .collect::<Result<Vec<_>, sqlx::Error>>()?;
+    Ok(FingerprintGroup::from_rows(rows))
 }
 
-/// Server function to save full browser information to the database.
+/// Deletes the visits, the page views and the events of `subject`, its `bicmids` or `users` row,
+/// and the lookup values no longer referenced, in one transaction.
 ///
-/// Normalizes and stores JS info, User Agent, Referrer, IP Address, BICMID, and User ID.
-#[post("/api/v1/browserinfo1", headers: dioxus::fullstack::HeaderMap)]
-pub async fn save_broinfo(req: super::SaveBroInfoRequest) -> Result<Option<Browser>> {
-    let ip_address = get_ip_address_string(&headers);
-    let user_agent = req.broinfo.basic.user_agent.clone();
-    let referrer = req.broinfo.basic.referrer.clone();
+/// The special rows of ID 0, i.e. the empty values, are never deleted.
+async fn erase_subject(pool: &sqlx::SqlitePool, subject: &EraseSubject) -> Result<EraseReport> {
+    let (rows_sqls, subject_sql) = match subject {
+        EraseSubject::Bicmid(_) => (
+            [
+                concat!(
+                    r#"DELETE FROM logs WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = ? AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM page_views WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = ? AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM events WHERE bicmid_id IN"#,
+                    r#" (SELECT id FROM bicmids WHERE value = ? AND id <> 0)"#
+                ),
+            ],
+            r#"DELETE FROM bicmids WHERE value = ? AND id <> 0"#,
+        ),
+        EraseSubject::User(_) => (
+            [
+                concat!(
+                    r#"DELETE FROM logs WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = ? AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM page_views WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = ? AND id <> 0)"#
+                ),
+                concat!(
+                    r#"DELETE FROM events WHERE user_id IN"#,
+                    r#" (SELECT id FROM users WHERE value = ? AND id <> 0)"#
+                ),
+            ],
+            r#"DELETE FROM users WHERE value = ? AND id <> 0"#,
+        ),
+    };
+    let mut report = EraseReport::default();
+    let mut tx = pool.begin().await?;
+    let deleted = [&mut report.logs, &mut report.page_views, &mut report.events];
+    for (sql, deleted) in rows_sqls.into_iter().zip(deleted) {
+        let r = sqlx::query(sql)
+            .bind(subject.value())
+            .execute(&mut *tx)
+            .await?;
+        *deleted = r.rows_affected();
+    }
+    let r = sqlx::query(subject_sql)
+        .bind(subject.value())
+        .execute(&mut *tx)
+        .await?;
+    report.subjects = r.rows_affected();
+    for sql in DELETE_UNREFERENCED {
+        let r = sqlx::query(sql).execute(&mut *tx).await?;
+        report.lookups += r.rows_affected();
+    }
+    tx.commit().await?;
+    Ok(report)
+}
 
-    let jsinfo_s = toml::to_string(&req.broinfo.jsinfo).unwrap();
+/// Deletes the visits, the page views and the events beyond the limits of `policy`,
+/// then the lookup values no longer referenced.
+async fn delete_expired(pool: &sqlx::SqlitePool, policy: &RetentionConfig) -> Result<PruneReport> {
+    let mut report = PruneReport::default();
+    let mut tx = pool.begin().await?;
+    let tables = [
+        (
+            r#"DELETE FROM logs WHERE create_at < datetime('now', ?)"#,
+            concat!(
+                r#"DELETE FROM logs WHERE id <= "#,
+                r#"(SELECT id FROM logs ORDER BY id DESC LIMIT 1 OFFSET ?)"#
+            ),
+            &mut report.logs,
+        ),
+        (
+            r#"DELETE FROM page_views WHERE create_at < datetime('now', ?)"#,
+            concat!(
+                r#"DELETE FROM page_views WHERE id <= "#,
+                r#"(SELECT id FROM page_views ORDER BY id DESC LIMIT 1 OFFSET ?)"#
+            ),
+            &mut report.page_views,
+        ),
+        (
+            r#"DELETE FROM events WHERE create_at < datetime('now', ?)"#,
+            concat!(
+                r#"DELETE FROM events WHERE id <= "#,
+                r#"(SELECT id FROM events ORDER BY id DESC LIMIT 1 OFFSET ?)"#
+            ),
+            &mut report.events,
+        ),
+    ];
+    for (age_sql, rows_sql, deleted) in tables {
+        if policy.max_age_days > 0 {
+            let r = sqlx::query(age_sql)
+                .bind(format!("-{} days", policy.max_age_days))
+                .execute(&mut *tx)
+                .await?;
+            *deleted += r.rows_affected();
+        }
+        if policy.max_rows > 0 {
+            let r = sqlx::query(rows_sql)
+                .bind(policy.max_rows as i64)
+                .execute(&mut *tx)
+                .await?;
+            *deleted += r.rows_affected();
+        }
+    }
+    if report.logs + report.page_views + report.events > 0 {
+        for sql in DELETE_UNREFERENCED {
+            let r = sqlx::query(sql).execute(&mut *tx).await?;
+            report.lookups += r.rows_affected();
+        }
+    }
+    tx.commit().await?;
+    Ok(report)
+}
 
-    // Convert line breaks to <BR> and save based on DB display specifications.
-    // When extracting it, you can use .replace("<BR>", "\n") to parse it as TOML.
-    let jsinfo_ss = jsinfo_s.replace("\r\n", "<BR>").replace("\n", "<BR>");
+/// Returns the freed pages to the file system.
+async fn vacuum(pool: &sqlx::SqlitePool, mode: VacuumMode) -> Result<()> {
+    let sql = match mode {
+        VacuumMode::None => return Ok(()),
+        VacuumMode::Full => "VACUUM",
+        VacuumMode::Incremental => {
+            let auto_vacuum: i64 = sqlx::query("PRAGMA auto_vacuum")
+                .fetch_one(pool)
+                .await?
+                .get(0);
+            // 2: INCREMENTAL. An old database needs `VACUUM` once to switch to it.
+            if auto_vacuum == 2 {
+                "PRAGMA incremental_vacuum"
+            } else {
+                "VACUUM"
+            }
+        }
+    };
+    sqlx::query(sql).execute(pool).await?;
+    Ok(())
+}
 
-    #[cfg(feature = "backend_text")]
-    write_backend_text("jsinfo.txt", &jsinfo_s)?;
-    //
-    {
-        let mut tx: Transaction<'_, sqlx::Sqlite> = DB.begin().await?;
-        //
-        let user_agent_id = get_or_store_user_agent(&mut tx, user_agent.get()).await?;
-        let referrer_id = get_or_store_referrer(&mut tx, referrer.get()).await?;
-        let ip_address_id = get_or_store_ip_address(&mut tx, &ip_address).await?;
-        let bicmid_id = get_or_store_bicmid(&mut tx, &req.bicmid).await?;
-        let user_id = get_or_store_user(&mut tx, &req.user).await?;
-        let jsinfo_id = get_or_store_jsinfo(&mut tx, &jsinfo_ss).await?;
-        //
-        sqlx::query(concat!(
-            r#"INSERT INTO logs"#,
-            r#" (jsinfo_id, user_agent_id, referrer_id, ip_address_id, bicmid_id, user_id)"#,
-            r#" VALUES (?, ?, ?, ?, ?, ?)"#
+/// Stores `info` as the GeoIP of the IP address `value`, unless it is already resolved.
+#[cfg(feature = "geoip")]
+async fn store_geo_info<'c, E>(e: E, value: &str, info: &GeoInfo) -> Result<()>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    sqlx::query(concat!(
+        r#"UPDATE ip_addresses SET country_code = ?, region_name = ?, city_name = ?,"#,
+        r#" autonomous_system_number = ?, autonomous_system_organization = ?,"#,
+        r#" geo_resolved_at = CURRENT_TIMESTAMP"#,
+        r#" WHERE value = ? AND id <> 0 AND geo_resolved_at IS NULL"#
+    ))
+    .bind(&info.country_code)
+    .bind(&info.region_name)
+    .bind(&info.city_name)
+    .bind(info.autonomous_system_number.map(i64::from))
+    .bind(&info.autonomous_system_organization)
+    .bind(value)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+/// Resolves the GeoIP of every stored IP address not resolved yet, and returns their number.
+#[cfg(feature = "geoip")]
+async fn backfill_geoip(pool: &sqlx::SqlitePool, reader: &GeoIpReader) -> Result<u64> {
+    let mut count = 0;
+    loop {
+        let rows = sqlx::query(concat!(
+            r#"SELECT value FROM ip_addresses"#,
+            r#" WHERE id <> 0 AND geo_resolved_at IS NULL ORDER BY id LIMIT 1000"#
         ))
-        .bind(jsinfo_id)
-        .bind(user_agent_id)
-        .bind(referrer_id)
-        .bind(ip_address_id)
-        .bind(bicmid_id)
-        .bind(user_id)
-        .execute(&mut *tx)
+        .fetch_all(pool)
         .await?;
-        //
+        if rows.is_empty() {
+            return Ok(count);
+        }
+        let mut tx = pool.begin().await?;
+        for row in &rows {
+            let value: String = row.try_get(0)?;
+            let info = reader.lookup(&value)?.unwrap_or_default();
+            store_geo_info(&mut *tx, &value, &info).await?;
+        }
+        tx.commit().await?;
+        count += rows.len() as u64;
+    }
+}
+
+/// Stores `parsed` into the structured columns of the `user_agents` row `id`.
+async fn store_parsed_user_agent<'c, E>(e: E, id: i64, parsed: &ParsedUserAgent) -> Result<()>
+where
+    E: sqlx::SqliteExecutor<'c>,
+{
+    sqlx::query(concat!(
+        r#"UPDATE user_agents SET browser_name = ?, browser_version = ?,"#,
+        r#" os_name = ?, os_version = ?, device_model = ?, device_class = ?,"#,
+        r#" parsed_at = CURRENT_TIMESTAMP"#,
+        r#" WHERE id = ?"#
+    ))
+    .bind(&parsed.browser_name)
+    .bind(&parsed.browser_version)
+    .bind(&parsed.os_name)
+    .bind(&parsed.os_version)
+    .bind(&parsed.device_model)
+    .bind(parsed.device_class.as_str())
+    .bind(id)
+    .execute(e)
+    .await?;
+    Ok(())
+}
+
+/// Parses the stored user agents into the structured columns, and returns their number.
+/// Only the ones not parsed yet unless `all`.
+async fn reparse_user_agents(pool: &sqlx::SqlitePool, all: bool) -> Result<u64> {
+    let mut count = 0;
+    let mut last_id: i64 = 0;
+    loop {
+        let rows = sqlx::query(concat!(
+            r#"SELECT id, value FROM user_agents"#,
+            r#" WHERE id > ? AND (? OR parsed_at IS NULL) ORDER BY id LIMIT 1000"#
+        ))
+        .bind(last_id)
+        .bind(all)
+        .fetch_all(pool)
+        .await?;
+        if rows.is_empty() {
+            return Ok(count);
+        }
+        let mut tx = pool.begin().await?;
+        for row in &rows {
+            let id: i64 = row.try_get(0)?;
+            let value: String = row.try_get(1)?;
+            store_parsed_user_agent(&mut *tx, id, &ParsedUserAgent::parse(&value)).await?;
+            last_id = id;
+        }
         tx.commit().await?;
+        count += rows.len() as u64;
     }
+}
+
+/// Converts the JS info of `req` to the JSON stored in `jsinfos`.
+fn jsinfo_db_string(req: &SaveBroInfoRequest) -> Result<String> {
+    Ok(serde_json::to_string(&req.broinfo.jsinfo)?)
+}
+
+/// Stores one visit into `logs` in its own transaction.
+async fn store_broinfo(
+    pool: &sqlx::SqlitePool,
+    req: &SaveBroInfoRequest,
+    values: &VisitValues,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    store_broinfo_tx(
+        &mut tx,
+        &mut LookupIds::new(),
+        req,
+        values,
+        session_timeout_secs,
+    )
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Stores one visit into `logs`, normalizing every value into its lookup table,
+/// and counts it in its session.
+///
+/// The values found in `ids` are not looked up again.
+///
+/// A retried request, whose `request_id` is already stored, is deduplicated by `ui_logs_01`:
+/// the `logs` row is inserted first, without a `SELECT` before it, and the session is counted
+/// only if it was inserted.
+async fn store_broinfo_tx(
+    tx: &mut Transaction<'_, sqlx::Sqlite>,
+    ids: &mut LookupIds,
+    req: &SaveBroInfoRequest,
+    values: &VisitValues,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let user_agent = &req.broinfo.basic.user_agent;
+    let referrer = &req.broinfo.basic.referrer;
     //
-    dioxus::logger::tracing::debug!("save_broinfo: {jsinfo_ss:?}");
+    let user_agent_id = get_or_store_user_agent(tx, ids, user_agent.get()).await?;
+    let referrer_id = get_or_store_referrer(tx, ids, referrer.get()).await?;
+    let ip_address_id = get_or_store_ip_address(tx, ids, &values.ip_address).await?;
+    let bicmid_id = get_or_store_bicmid(tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(tx, ids, &req.user).await?;
+    let jsinfo_id = get_or_store_jsinfo(tx, ids, &values.jsinfo).await?;
+    let fingerprint_id = get_or_store_fingerprint(tx, ids, &values.fingerprint).await?;
     //
-    #[cfg(feature = "backend_delay")]
-    let _ = sleep_x(2000).await;
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO logs"#,
+        r#" (jsinfo_id, user_agent_id, referrer_id, ip_address_id, bicmid_id, user_id,"#,
+        r#" ip_address_mode, fingerprint_id, request_id, consent, bot_score)"#,
+        r#" VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)"#,
+        r#" ON CONFLICT(request_id) DO NOTHING RETURNING id"#
+    ))
+    .bind(jsinfo_id)
+    .bind(user_agent_id)
+    .bind(referrer_id)
+    .bind(ip_address_id)
+    .bind(bicmid_id)
+    .bind(user_id)
+    .bind(values.ip_address_mode)
+    .bind(fingerprint_id)
+    .bind(Some(req.request_id.as_str()).filter(|s| !s.is_empty()))
+    .bind(values.consent)
+    .bind(values.bot_score)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let Some(row) = r else {
+        // a retry of a stored request
+        return Ok(());
+    };
+    let log_id: i64 = row.get(0);
     //
-    if req.return_browser {
-        Ok(Some(req.broinfo.to_browser()?))
-    } else {
-        Ok(None)
+    let session_id = get_or_start_session(
+        tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::Visit,
+    )
+    .await?;
+    if session_id != 0 {
+        sqlx::query(r#"UPDATE logs SET session_id = ? WHERE id = ?"#)
+            .bind(session_id)
+            .bind(log_id)
+            .execute(&mut **tx)
+            .await?;
     }
+    Ok(())
 }
 
-/// Appends data to a text file. Used when `backend_text` feature is enabled.
-#[cfg(feature = "backend_text")]
-#[cfg(feature = "server")]
-fn write_backend_text(fnm: &str, data: &str) -> Result<()> {
-    use std::io::Write;
+/// Continues the latest session of `session_id` and `bicmid_id` if it was seen within
+/// `timeout_secs`, or else starts another one, and returns its ID.
+/// `hit` says what it counts.
+///
+/// `0` without a session ID and a BICMID.
+async fn get_or_start_session(
+    tx: &mut Transaction<'_, sqlx::Sqlite>,
+    session_id: &str,
+    bicmid_id: i64,
+    timeout_secs: u64,
+    hit: SessionHit,
+) -> Result<i64> {
+    if session_id.is_empty() && bicmid_id == 0 {
+        return Ok(0);
+    }
+    let (hits, page_views) = hit.increments();
+    let r = sqlx::query(concat!(
+        r#"UPDATE sessions SET hits = hits + ?, page_views = page_views + ?,"#,
+        r#" last_seen_at = CURRENT_TIMESTAMP"#,
+        r#" WHERE id = (SELECT id FROM sessions WHERE bicmid_id = ? AND value = ?"#,
+        r#" AND id <> 0 ORDER BY id DESC LIMIT 1)"#,
+        r#" AND last_seen_at >= datetime('now', ?)"#,
+        r#" RETURNING id"#
+    ))
+    .bind(hits)
+    .bind(page_views)
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(format!("-{timeout_secs} seconds"))
+    .fetch_optional(&mut **tx)
+    .await?;
+    let row =
+        match r {
+            Some(row) => row,
+            None => sqlx::query(concat!(
+                r#"INSERT INTO sessions (value, bicmid_id, hits, page_views) VALUES (?, ?, ?, ?)"#,
+                r#" RETURNING id"#
+            ))
+            .bind(session_id)
+            .bind(bicmid_id)
+            .bind(hits)
+            .bind(page_views)
+            .fetch_one(&mut **tx)
+            .await?,
+        };
+    Ok(row.get(0))
+}
+
+/// Stores one page view into `page_views` in its own transaction, and counts it in its session.
+async fn store_pageview(
+    pool: &sqlx::SqlitePool,
+    req: &PageViewRequest,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let mut tx = pool.begin().await?;
+    let ids = &mut LookupIds::new();
+    let path_id = get_or_store_path(&mut tx, ids, &req.path).await?;
+    let referrer_id = get_or_store_referrer(&mut tx, ids, &req.referrer).await?;
+    let bicmid_id = get_or_store_bicmid(&mut tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(&mut tx, ids, &req.user).await?;
+    let session_id = get_or_start_session(
+        &mut tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::PageView,
+    )
+    .await?;
     //
-    // Open file in append-only mode, creating it if it doesn't exist;
-    let mut file = std::fs::OpenOptions::new()
-        .write(true)
-        .append(true)
-        .create(true)
-        .open(fnm)?;
-    // And then write a newline to it with data
-    let _ = file.write_fmt(format_args!("{data}\n"));
+    sqlx::query(concat!(
+        r#"INSERT INTO page_views"#,
+        r#" (path_id, title, referrer_id, bicmid_id, session_id, user_id)"#,
+        r#" VALUES (?, ?, ?, ?, ?, ?)"#
+    ))
+    .bind(path_id)
+    .bind(&req.title)
+    .bind(referrer_id)
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
     Ok(())
 }
 
-/// Sleeps for a specified duration. Used for testing delays when `backend_delay` is enabled.
-#[cfg(feature = "backend_delay")]
-#[cfg(feature = "server")]
-async fn sleep_x(millis: u64) -> Result<()> {
-    use std::time::Duration;
-    async_sleep_aki::async_sleep(Duration::from_millis(millis)).await;
+/// Stores one custom event into `events` in its own transaction, extending its session.
+async fn store_event(
+    pool: &sqlx::SqlitePool,
+    req: &EventRequest,
+    session_timeout_secs: u64,
+) -> Result<()> {
+    let properties = req.properties_json()?;
+    let mut tx = pool.begin().await?;
+    let ids = &mut LookupIds::new();
+    let name_id = get_or_store_event_name(&mut tx, ids, &req.name).await?;
+    let bicmid_id = get_or_store_bicmid(&mut tx, ids, &req.bicmid).await?;
+    let user_id = get_or_store_user(&mut tx, ids, &req.user).await?;
+    let session_id = get_or_start_session(
+        &mut tx,
+        &req.session_id,
+        bicmid_id,
+        session_timeout_secs,
+        SessionHit::Event,
+    )
+    .await?;
+    //
+    sqlx::query(concat!(
+        r#"INSERT INTO events"#,
+        r#" (name_id, properties, bicmid_id, session_id, user_id)"#,
+        r#" VALUES (?, ?, ?, ?, ?)"#
+    ))
+    .bind(name_id)
+    .bind(&properties)
+    .bind(bicmid_id)
+    .bind(session_id)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await?;
     Ok(())
 }
 
-/// Ensures required tables exist in the SQLite database.
-#[cfg(feature = "server")]
+/// Ensures required tables exist in the SQLite database by applying pending migrations.
 async fn create_tables(pool: &sqlx::sqlite::SqlitePool) -> Result<()> {
-    const SQL: &str = include_str!("../../../migrations/20260107001015_create-tables.up.sql");
-    sqlx::raw_sql(SQL).execute(pool).await?;
+    use super::migrate::{run_migrations, SQLITE_MIGRATOR};
+    //
+    run_migrations(&SQLITE_MIGRATOR, pool).await?;
     //
     // `JsInfo` special data for ID 0
     {
@@ -275,80 +988,205 @@ async fn create_tables(pool: &sqlx::sqlite::SqlitePool) -> Result<()> {
             .execute(pool)
             .await?;
     }
+    convert_legacy_jsinfos(pool).await?;
     Ok(())
 }
 
-/// Macro to generate functions that either fetch an existing ID or store a new value and return its ID.
-#[cfg(feature = "server")]
+/// Converts the `jsinfos` rows stored as TOML by the older versions to JSON,
+/// and returns their number.
+///
+/// A row that becomes the same as an existing one is merged into it.
+///
+/// It runs once: the conversion is recorded in `data_conversions`, and is skipped from then on.
+async fn convert_legacy_jsinfos(pool: &sqlx::SqlitePool) -> Result<u64> {
+    use super::jsinfo::{is_legacy_toml, json_from_legacy_toml, LEGACY_TOML_CONVERSION};
+    //
+    let mut tx = pool.begin().await?;
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO data_conversions (value) VALUES (?)"#,
+        r#" ON CONFLICT DO NOTHING RETURNING id"#
+    ))
+    .bind(LEGACY_TOML_CONVERSION)
+    .fetch_optional(&mut *tx)
+    .await?;
+    if r.is_none() {
+        // already done
+        return Ok(0);
+    }
+    let rows =
+        sqlx::query(r#"SELECT id, value FROM jsinfos WHERE id <> 0 AND value NOT LIKE '{%'"#)
+            .fetch_all(&mut *tx)
+            .await?;
+    if rows.is_empty() {
+        tx.commit().await?;
+        return Ok(0);
+    }
+    let mut count = 0;
+    for row in &rows {
+        let id: i64 = row.try_get(0)?;
+        let value: String = row.try_get(1)?;
+        if !is_legacy_toml(&value) {
+            continue;
+        }
+        let json = json_from_legacy_toml(&value);
+        let hash = create_jsinfo_hash(&json);
+        let existing = sqlx::query(r#"SELECT id FROM jsinfos WHERE hash = ? AND value = ?"#)
+            .bind(&hash)
+            .bind(&json)
+            .fetch_optional(&mut *tx)
+            .await?;
+        if let Some(existing) = existing {
+            let existing_id: i64 = existing.try_get(0)?;
+            sqlx::query(r#"UPDATE logs SET jsinfo_id = ? WHERE jsinfo_id = ?"#)
+                .bind(existing_id)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query(r#"DELETE FROM jsinfos WHERE id = ?"#)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        } else {
+            sqlx::query(r#"UPDATE jsinfos SET hash = ?, value = ? WHERE id = ?"#)
+                .bind(&hash)
+                .bind(&json)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+        }
+        count += 1;
+    }
+    tx.commit().await?;
+    dioxus::logger::tracing::info!("converted {count} jsinfos from TOML to JSON");
+    Ok(count)
+}
+
+/// Macro to generate functions that either store a new value or fetch the existing ID.
+/// The ID is remembered in `ids` for the rest of the transaction.
+///
+/// The `INSERT` comes first, so that concurrent saves of a new value do not race between
+/// a `SELECT` and an `INSERT`. `ON CONFLICT DO NOTHING` returns no row when the value exists,
+/// so that it is selected then.
 macro_rules! simple_get_or_store {
     ($func:ident, $tbl: expr) => {
-        async fn $func(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, val: &str) -> Result<i64> {
-            match sqlx::query(concat!(r#"SELECT id FROM "#, $tbl, r#" WHERE value = ?"#))
-                .bind(val)
-                .fetch_one(&mut **tx)
-                .await
-            {
-                Ok(row) => Ok(row.get(0)),
-                Err(sqlx::Error::RowNotFound) => {
-                    let r = sqlx::query(concat!(r#"INSERT INTO "#, $tbl, r#" (value) VALUES (?)"#))
+        async fn $func(
+            tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+            ids: &mut LookupIds,
+            val: &str,
+        ) -> Result<i64> {
+            let key = ($tbl, val.to_string());
+            if let Some(id) = ids.get(&key) {
+                return Ok(*id);
+            }
+            let r = sqlx::query(concat!(
+                r#"INSERT INTO "#,
+                $tbl,
+                r#" (value) VALUES (?) ON CONFLICT DO NOTHING RETURNING id"#
+            ))
+            .bind(val)
+            .fetch_optional(&mut **tx)
+            .await?;
+            let row = match r {
+                Some(row) => row,
+                None => {
+                    sqlx::query(concat!(r#"SELECT id FROM "#, $tbl, r#" WHERE value = ?"#))
                         .bind(val)
-                        .execute(&mut **tx)
-                        .await?;
-                    Ok(r.last_insert_rowid())
+                        .fetch_one(&mut **tx)
+                        .await?
                 }
-                Err(e) => Err(e.into()),
-            }
+            };
+            let id = row.get(0);
+            ids.insert(key, id);
+            Ok(id)
         }
     };
 }
 
-#[cfg(feature = "server")]
-simple_get_or_store!(get_or_store_user_agent, "user_agents");
+/// Retrieves or stores the user agent.
+/// A new user agent is parsed into the structured columns at once.
+async fn get_or_store_user_agent(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ids: &mut LookupIds,
+    val: &str,
+) -> Result<i64> {
+    let key = ("user_agents", val.to_string());
+    if let Some(id) = ids.get(&key) {
+        return Ok(*id);
+    }
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO user_agents (value) VALUES (?)"#,
+        r#" ON CONFLICT DO NOTHING RETURNING id"#
+    ))
+    .bind(val)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let id = match r {
+        Some(row) => {
+            let id = row.get(0);
+            store_parsed_user_agent(&mut **tx, id, &ParsedUserAgent::parse(val)).await?;
+            id
+        }
+        None => sqlx::query(r#"SELECT id FROM user_agents WHERE value = ?"#)
+            .bind(val)
+            .fetch_one(&mut **tx)
+            .await?
+            .get(0),
+    };
+    ids.insert(key, id);
+    Ok(id)
+}
 
-#[cfg(feature = "server")]
 simple_get_or_store!(get_or_store_referrer, "referrers");
 
-#[cfg(feature = "server")]
 simple_get_or_store!(get_or_store_ip_address, "ip_addresses");
 
-#[cfg(feature = "server")]
 simple_get_or_store!(get_or_store_bicmid, "bicmids");
 
-#[cfg(feature = "server")]
 simple_get_or_store!(get_or_store_user, "users");
 
+simple_get_or_store!(get_or_store_fingerprint, "fingerprints");
+
+simple_get_or_store!(get_or_store_path, "paths");
+
+simple_get_or_store!(get_or_store_event_name, "event_names");
+
 /// Retrieves or stores JS information in the database.
 /// Uses a hash to optimize lookup.
-#[cfg(feature = "server")]
 async fn get_or_store_jsinfo(
     tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    ids: &mut LookupIds,
     info_s: &str,
 ) -> Result<i64> {
+    let key = ("jsinfos", info_s.to_string());
+    if let Some(id) = ids.get(&key) {
+        return Ok(*id);
+    }
     let hash = create_jsinfo_hash(info_s);
     let hash_s = hash.as_str();
-    let mut jsinfo_id = -1;
-    let r = sqlx::query(r#"SELECT id FROM jsinfos WHERE hash = ? AND value = ?"#)
-        .bind(hash_s)
-        .bind(info_s)
-        .fetch_one(&mut **tx)
-        .await;
-    if let Ok(row) = r {
-        jsinfo_id = row.get(0);
-    } else if let Err(sqlx::Error::RowNotFound) = r {
-        let r = sqlx::query(r#"INSERT INTO jsinfos (hash, value) VALUES (?, ?)"#)
-            .bind(hash_s)
-            .bind(info_s)
-            .execute(&mut **tx)
-            .await?;
-        jsinfo_id = r.last_insert_rowid();
-    } else if let Err(e) = r {
-        return Err(e.into());
-    }
-    Ok(jsinfo_id)
+    let r = sqlx::query(concat!(
+        r#"INSERT INTO jsinfos (hash, value) VALUES (?, ?)"#,
+        r#" ON CONFLICT DO NOTHING RETURNING id"#
+    ))
+    .bind(hash_s)
+    .bind(info_s)
+    .fetch_optional(&mut **tx)
+    .await?;
+    let row = match r {
+        Some(row) => row,
+        None => {
+            sqlx::query(r#"SELECT id FROM jsinfos WHERE hash = ? AND value = ?"#)
+                .bind(hash_s)
+                .bind(info_s)
+                .fetch_one(&mut **tx)
+                .await?
+        }
+    };
+    let id = row.get(0);
+    ids.insert(key, id);
+    Ok(id)
 }
 
 /// Creates a SHA-256 hash of the JS info string and encodes it in Base64.
-#[cfg(feature = "server")]
 fn create_jsinfo_hash(s: &str) -> String {
     use base64::Engine;
 
@@ -356,11 +1194,35 @@ fn create_jsinfo_hash(s: &str) -> String {
     base64::engine::general_purpose::STANDARD_NO_PAD.encode(hash_bytes)
 }
 
-#[cfg(feature = "server")]
 #[cfg(test)]
 mod test {
     use super::*;
 
+    async fn memory_pool() -> sqlx::SqlitePool {
+        SqliteStore::open_in_memory().await.unwrap().pool
+    }
+
+    fn values(ip_address: &str, jsinfo: &str) -> VisitValues {
+        VisitValues {
+            ip_address: ip_address.to_string(),
+            ip_address_mode: "full",
+            fingerprint: String::new(),
+            jsinfo: jsinfo.to_string(),
+            consent: "",
+            bot_score: 0,
+        }
+    }
+
+    fn save_req(bicmid: &str, user: &str, ua: &str) -> SaveBroInfoRequest {
+        let mut req = SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            user: user.to_string(),
+            ..Default::default()
+        };
+        req.broinfo.basic.user_agent = ua.into();
+        req
+    }
+
     #[test]
     fn test_create_jsinfo_hash_00() {
         let s0 = "";
@@ -376,4 +1238,780 @@ mod test {
         assert_eq!(s.len(), 43);
         assert_eq!(s, "OWQpS2ZGE3mNGkd+uK0CEYtI0MVzjEJ2EyAvLtEjtfE");
     }
+
+    #[tokio::test]
+    async fn test_fetch_logs_02() {
+        let pool = memory_pool().await;
+        for (bicmid, user) in [("b1", ""), ("b2", "u1"), ("b1", "u1")] {
+            let req = save_req(bicmid, user, "Mozilla/5.0");
+            store_broinfo(&pool, &req, &values("192.0.2.1", "a = 1"), 1800)
+                .await
+                .unwrap();
+        }
+        //
+        let page = fetch_logs(&pool, &LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 3);
+        assert_eq!(page.entries.len(), 3);
+        assert_eq!(page.entries[0].bicmid, "b1");
+        assert_eq!(page.entries[0].user, "u1");
+        assert_eq!(page.entries[0].ip_address, "192.0.2.1");
+        assert_eq!(page.entries[0].user_agent, "Mozilla/5.0");
+        assert_eq!(page.entries[0].jsinfo, "a = 1");
+        //
+        let q = LogQuery {
+            bicmid: Some("b1".to_string()),
+            ..Default::default()
+        };
+        let page = fetch_logs(&pool, &q).await.unwrap();
+        assert_eq!(page.total, 2);
+        //
+        let q = LogQuery {
+            user: Some("u1".to_string()),
+            per_page: 1,
+            page: 1,
+            ..Default::default()
+        };
+        let page = fetch_logs(&pool, &q).await.unwrap();
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries.len(), 1);
+        assert_eq!(page.entries[0].bicmid, "b2");
+        //
+        let q = LogQuery {
+            since: Some("2000-01-01 00:00:00".to_string()),
+            until: Some("2000-01-02 00:00:00".to_string()),
+            ..Default::default()
+        };
+        let page = fetch_logs(&pool, &q).await.unwrap();
+        assert_eq!(page.total, 0);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_log_stats_03() {
+        let pool = memory_pool().await;
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        for (bicmid, ua) in [("b1", firefox), ("b2", firefox), ("b3", "")] {
+            let req = save_req(bicmid, "", ua);
+            store_broinfo(&pool, &req, &values("", ""), 1800)
+                .await
+                .unwrap();
+        }
+        let stats = fetch_log_stats(&pool, &LogQuery::default()).await.unwrap();
+        assert_eq!(stats.total, 3);
+        assert_eq!(stats.browsers[0].name, "Firefox");
+        assert_eq!(stats.browsers[0].count, 2);
+        assert_eq!(stats.oses[0].name, "Linux");
+        assert_eq!(stats.oses[0].count, 2);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_parallel_saves_04() {
+        let db_path = std::env::temp_dir().join(format!(
+            "browserinfocm-test-{}-parallel.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&db_path);
+        let pool = connect_sqlx_pool(&db_path).await.unwrap();
+        create_tables(&pool).await.unwrap();
+        //
+        // every new value is saved by several tasks at once,
+        // and the last 100 requests are retries of the first 100
+        let handles: Vec<_> = (0..300)
+            .map(|i| {
+                let pool = pool.clone();
+                let k = i % 200;
+                tokio::spawn(async move {
+                    let mut req = save_req(
+                        &format!("b{}", k % 20),
+                        &format!("u{}", k % 7),
+                        &format!("Mozilla/5.0 ({})", k % 5),
+                    );
+                    req.request_id = format!("r{k}");
+                    let ip = format!("192.0.2.{}", k % 11);
+                    let jsinfo = format!("n = {}", k % 3);
+                    store_broinfo(&pool, &req, &values(&ip, &jsinfo), 1800).await
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+        //
+        let count = |sql: &'static str| {
+            let pool = pool.clone();
+            async move {
+                sqlx::query(sql)
+                    .fetch_one(&pool)
+                    .await
+                    .unwrap()
+                    .get::<i64, _>(0)
+            }
+        };
+        assert_eq!(count("SELECT COUNT(*) FROM logs").await, 200);
+        assert_eq!(
+            count("SELECT COUNT(DISTINCT request_id) FROM logs").await,
+            200
+        );
+        // one session of each BICMID, counting each visit once
+        assert_eq!(
+            count("SELECT COUNT(*) FROM sessions WHERE id <> 0").await,
+            20
+        );
+        assert_eq!(
+            count("SELECT COALESCE(SUM(hits), 0) FROM sessions").await,
+            200
+        );
+        assert_eq!(
+            count("SELECT COUNT(*) FROM logs WHERE session_id = 0").await,
+            0
+        );
+        assert_eq!(count("SELECT COUNT(*) FROM user_agents").await, 1 + 5);
+        assert_eq!(count("SELECT COUNT(*) FROM ip_addresses").await, 1 + 11);
+        assert_eq!(count("SELECT COUNT(*) FROM bicmids").await, 1 + 20);
+        assert_eq!(count("SELECT COUNT(*) FROM users").await, 1 + 7);
+        assert_eq!(count("SELECT COUNT(*) FROM jsinfos").await, 1 + 3);
+        //
+        pool.close().await;
+        let _ = std::fs::remove_file(&db_path);
+    }
+
+    #[tokio::test]
+    async fn test_prune_05() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        for (bicmid, ip) in [
+            ("b1", "192.0.2.1"),
+            ("b2", "192.0.2.2"),
+            ("b3", "192.0.2.3"),
+        ] {
+            store
+                .save_broinfo(&save_req(bicmid, "", ""), ip)
+                .await
+                .unwrap();
+        }
+        sqlx::query("UPDATE logs SET create_at = '2000-01-01 00:00:00' WHERE id = 1")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        //
+        let mut policy = RetentionConfig {
+            max_age_days: 30,
+            max_rows: 0,
+            interval_secs: 3600,
+            vacuum: VacuumMode::Incremental,
+        };
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.logs, 1);
+        // "b1", "192.0.2.1" and the session of "b1"
+        assert_eq!(report.lookups, 3);
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report, PruneReport::default());
+        //
+        policy.max_rows = 1;
+        policy.vacuum = VacuumMode::Full;
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.logs, 1);
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].bicmid, "b3");
+        // the special rows of ID 0 are kept
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM bicmids WHERE id = 0")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+    }
+
+    #[tokio::test]
+    async fn test_erase_subject_06() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        for (bicmid, user, ip) in [
+            ("b1", "u1", "192.0.2.1"),
+            ("b2", "u1", "192.0.2.2"),
+            ("b3", "", "192.0.2.3"),
+        ] {
+            store
+                .save_broinfo(&save_req(bicmid, user, ""), ip)
+                .await
+                .unwrap();
+        }
+        //
+        let subject = EraseSubject::Bicmid("b1".to_string());
+        let report = store.erase_subject(&subject).await.unwrap();
+        // "192.0.2.1" and the session; "u1" is still referred to by "b2"
+        let expected = EraseReport {
+            logs: 1,
+            page_views: 0,
+            events: 0,
+            subjects: 1,
+            lookups: 2,
+        };
+        assert_eq!(report, expected);
+        //
+        let subject = EraseSubject::User("u1".to_string());
+        let report = store.erase_subject(&subject).await.unwrap();
+        // "b2", "192.0.2.2" and the session of "b2"
+        let expected = EraseReport {
+            logs: 1,
+            page_views: 0,
+            events: 0,
+            subjects: 1,
+            lookups: 3,
+        };
+        assert_eq!(report, expected);
+        let report = store.erase_subject(&subject).await.unwrap();
+        assert_eq!(report, EraseReport::default());
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 1);
+        assert_eq!(page.entries[0].bicmid, "b3");
+        // the empty values are never erased
+        let report = store
+            .erase_subject(&EraseSubject::User(String::new()))
+            .await
+            .unwrap();
+        assert_eq!(report, EraseReport::default());
+    }
+
+    #[tokio::test]
+    async fn test_ip_address_mode_07() {
+        use super::super::config::IpAddressMode;
+        //
+        let mut store = SqliteStore::open_in_memory().await.unwrap();
+        store
+            .save_broinfo(&save_req("b1", "", ""), "192.0.2.1")
+            .await
+            .unwrap();
+        store.ip_privacy = IpAddressPrivacy::new(IpAddressMode::Truncate, "").unwrap();
+        store
+            .save_broinfo(&save_req("b2", "", ""), "192.0.2.123")
+            .await
+            .unwrap();
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        let mut entries = page.entries;
+        entries.sort_by(|a, b| a.bicmid.cmp(&b.bicmid));
+        assert_eq!(entries[0].ip_address, "192.0.2.1");
+        assert_eq!(entries[0].ip_address_mode, "full");
+        assert_eq!(entries[1].ip_address, "192.0.2.0");
+        assert_eq!(entries[1].ip_address_mode, "truncate");
+    }
+
+    #[cfg(feature = "geoip")]
+    #[tokio::test]
+    async fn test_geoip_08() {
+        use super::super::geoip::test::fixture_reader;
+        //
+        let mut store = SqliteStore::open_in_memory().await.unwrap();
+        // saved before the GeoIP is configured
+        store
+            .save_broinfo(&save_req("b1", "", ""), "198.51.100.1")
+            .await
+            .unwrap();
+        store.geoip = Some(fixture_reader());
+        for (bicmid, ip) in [("b2", "192.0.2.1"), ("b3", "203.0.113.1"), ("b4", "")] {
+            store
+                .save_broinfo(&save_req(bicmid, "", ""), ip)
+                .await
+                .unwrap();
+        }
+        let geo = |ip: &'static str| {
+            let pool = store.pool.clone();
+            async move {
+                let row = sqlx::query(concat!(
+                    r#"SELECT country_code, city_name, autonomous_system_number,"#,
+                    r#" geo_resolved_at IS NOT NULL FROM ip_addresses WHERE value = ?"#
+                ))
+                .bind(ip)
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+                let country: Option<String> = row.get(0);
+                let city: Option<String> = row.get(1);
+                let asn: Option<i64> = row.get(2);
+                let resolved: bool = row.get(3);
+                (country, city, asn, resolved)
+            }
+        };
+        let tokyo = (
+            Some("JP".to_string()),
+            Some("Tokyo".to_string()),
+            Some(64500),
+            true,
+        );
+        assert_eq!(geo("192.0.2.1").await, tokyo);
+        // not in the database, but resolved
+        assert_eq!(geo("203.0.113.1").await, (None, None, None, true));
+        assert_eq!(geo("198.51.100.1").await, (None, None, None, false));
+        assert_eq!(geo("").await, (None, None, None, false));
+        //
+        assert_eq!(store.backfill_geoip().await.unwrap(), 1);
+        let us = (Some("US".to_string()), None, None, true);
+        assert_eq!(geo("198.51.100.1").await, us);
+        assert_eq!(store.backfill_geoip().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_parsed_user_agent_09() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        for (bicmid, ua) in [("b1", firefox), ("b2", firefox), ("b3", "curl/8.0")] {
+            store
+                .save_broinfo(&save_req(bicmid, "", ua), "192.0.2.1")
+                .await
+                .unwrap();
+        }
+        let sql = concat!(
+            r#"SELECT browser_name, os_name, device_class FROM user_agents"#,
+            r#" WHERE value = ?"#
+        );
+        let row = sqlx::query(sql)
+            .bind(firefox)
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        let parsed: (String, String, String) = (row.get(0), row.get(1), row.get(2));
+        assert_eq!(parsed, ("Firefox".into(), "Linux".into(), "desktop".into()));
+        // the visits by browser in plain SQL
+        let rows = sqlx::query(concat!(
+            r#"SELECT user_agents.browser_name, COUNT(*) FROM logs"#,
+            r#" JOIN user_agents ON user_agents.id = logs.user_agent_id"#,
+            r#" GROUP BY user_agents.browser_name ORDER BY 2 DESC"#
+        ))
+        .fetch_all(&store.pool)
+        .await
+        .unwrap();
+        let counts: Vec<(String, i64)> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+        assert_eq!(
+            counts,
+            [("Firefox".to_string(), 2), ("curl".to_string(), 1)]
+        );
+        // stored before the columns
+        sqlx::query("UPDATE user_agents SET browser_name = NULL, parsed_at = NULL WHERE id <> 0")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(store.reparse_user_agents(false).await.unwrap(), 2);
+        assert_eq!(store.reparse_user_agents(false).await.unwrap(), 0);
+        assert_eq!(store.reparse_user_agents(true).await.unwrap(), 2);
+        let row = sqlx::query(sql)
+            .bind("curl/8.0")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(row.get::<String, _>(0), "curl");
+    }
+
+    #[tokio::test]
+    async fn test_convert_legacy_jsinfos_10() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let mut req = save_req("b1", "", "");
+        req.broinfo.jsinfo.timezone = "Asia/Tokyo".to_string();
+        req.broinfo.jsinfo.screen_width = Some(1920);
+        store.save_broinfo(&req, "192.0.2.1").await.unwrap();
+        // the same JS information, and another one, as stored by the older versions
+        let legacy = |tz: &str| {
+            let mut info = req.broinfo.jsinfo.clone();
+            info.timezone = tz.to_string();
+            toml::to_string(&info).unwrap().replace('\n', "<BR>")
+        };
+        for (id, value) in [(10, legacy("Asia/Tokyo")), (11, legacy("Europe/Berlin"))] {
+            sqlx::query("INSERT INTO jsinfos (id, hash, value) VALUES (?, ?, ?)")
+                .bind(id)
+                .bind(create_jsinfo_hash(&value))
+                .bind(&value)
+                .execute(&store.pool)
+                .await
+                .unwrap();
+            sqlx::query(concat!(
+                r#"INSERT INTO logs"#,
+                r#" (jsinfo_id, user_agent_id, referrer_id, ip_address_id, bicmid_id, user_id)"#,
+                r#" VALUES (?, 0, 0, 0, 0, 0)"#
+            ))
+            .bind(id)
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        }
+        // as if the database were opened by this version the first time
+        sqlx::query("DELETE FROM data_conversions")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!(convert_legacy_jsinfos(&store.pool).await.unwrap(), 2);
+        // once only
+        assert_eq!(convert_legacy_jsinfos(&store.pool).await.unwrap(), 0);
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM data_conversions")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+        let rows = sqlx::query(concat!(
+            r#"SELECT json_extract(jsinfos.value, '$.timezone'), COUNT(*) FROM logs"#,
+            r#" JOIN jsinfos ON jsinfos.id = logs.jsinfo_id"#,
+            r#" GROUP BY jsinfos.id ORDER BY 1"#
+        ))
+        .fetch_all(&store.pool)
+        .await
+        .unwrap();
+        let counts: Vec<(String, i64)> = rows.iter().map(|r| (r.get(0), r.get(1))).collect();
+        let expected = [
+            ("Asia/Tokyo".to_string(), 2),
+            ("Europe/Berlin".to_string(), 1),
+        ];
+        assert_eq!(counts, expected);
+        //
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        let jsinfo = page.entries[0].parse_jsinfo().unwrap();
+        assert_eq!(jsinfo.screen_width, Some(1920));
+    }
+
+    #[tokio::test]
+    async fn test_fingerprints_11() {
+        let mut store = SqliteStore::open_in_memory().await.unwrap();
+        let req = |bicmid: &str, tz: &str| {
+            let mut req = save_req(bicmid, "", "");
+            req.broinfo.jsinfo.timezone = tz.to_string();
+            req
+        };
+        // not enabled
+        store
+            .save_broinfo(&req("b0", "Asia/Tokyo"), "")
+            .await
+            .unwrap();
+        store.fingerprinter = Some(Fingerprinter::new("secret").unwrap());
+        for (bicmid, tz) in [
+            ("b1", "Asia/Tokyo"),
+            ("b1", "Asia/Tokyo"),
+            ("b2", "Asia/Tokyo"),
+            ("b3", "Europe/Berlin"),
+            ("", "Europe/Berlin"),
+        ] {
+            store.save_broinfo(&req(bicmid, tz), "").await.unwrap();
+        }
+        let tokyo = store
+            .visit_values(&req("", "Asia/Tokyo"), "")
+            .unwrap()
+            .fingerprint;
+        //
+        let groups = store
+            .query_fingerprints(&FingerprintQuery::default())
+            .await
+            .unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].fingerprint, tokyo);
+        let bicmids: Vec<(&str, i64)> = groups[0]
+            .bicmids
+            .iter()
+            .map(|b| (b.bicmid.as_str(), b.visits))
+            .collect();
+        assert_eq!(bicmids, [("b1", 2), ("b2", 1)]);
+        // by BICMID, with the ones not shared
+        let query = FingerprintQuery {
+            bicmid: Some("b3".to_string()),
+            ..Default::default()
+        };
+        let groups = store.query_fingerprints(&query).await.unwrap();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].bicmids.len(), 1);
+        let query = FingerprintQuery {
+            bicmid: Some("b0".to_string()),
+            ..Default::default()
+        };
+        assert!(store.query_fingerprints(&query).await.unwrap().is_empty());
+        let query = FingerprintQuery {
+            fingerprint: Some(tokyo),
+            ..Default::default()
+        };
+        assert_eq!(store.query_fingerprints(&query).await.unwrap().len(), 1);
+        // the fingerprint of an erased BICMID is collected
+        store
+            .erase_subject(&EraseSubject::Bicmid("b3".to_string()))
+            .await
+            .unwrap();
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM fingerprints")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1 + 2);
+    }
+
+    #[tokio::test]
+    async fn test_sessions_12() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let req = |bicmid: &str, session_id: &str| SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            session_id: session_id.to_string(),
+            ..Default::default()
+        };
+        for (bicmid, session_id) in [("b1", "s1"), ("b1", "s1"), ("b1", "s2"), ("", "")] {
+            store
+                .save_broinfo(&req(bicmid, session_id), "")
+                .await
+                .unwrap();
+        }
+        // timed out
+        sqlx::query("UPDATE sessions SET last_seen_at = '2000-01-01 00:00:00' WHERE id = 1")
+            .execute(&store.pool)
+            .await
+            .unwrap();
+        store.save_broinfo(&req("b1", "s1"), "").await.unwrap();
+        let rows = sqlx::query("SELECT session_id FROM logs ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let session_ids: Vec<i64> = rows.iter().map(|r| r.get(0)).collect();
+        assert_eq!(session_ids, [1, 1, 2, 0, 3]);
+        // sessions per day, and pages per session
+        let row = sqlx::query(concat!(
+            r#"SELECT date(started_at), COUNT(*), AVG(hits) FROM sessions"#,
+            r#" WHERE id <> 0 GROUP BY date(started_at)"#
+        ))
+        .fetch_one(&store.pool)
+        .await
+        .unwrap();
+        assert_eq!(row.get::<i64, _>(1), 3);
+        assert!((row.get::<f64, _>(2) - 4.0 / 3.0).abs() < 1e-9);
+        // erased with the BICMID
+        store
+            .erase_subject(&EraseSubject::Bicmid("b1".to_string()))
+            .await
+            .unwrap();
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM sessions")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+    }
+
+    #[tokio::test]
+    async fn test_page_views_13() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let visit = SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            session_id: "s1".to_string(),
+            ..Default::default()
+        };
+        store.save_broinfo(&visit, "").await.unwrap();
+        let view = |path: &str, referrer: &str| PageViewRequest {
+            path: path.to_string(),
+            title: format!("title of {path}"),
+            referrer: referrer.to_string(),
+            bicmid: "b1".to_string(),
+            session_id: "s1".to_string(),
+            user: "u1".to_string(),
+        };
+        for (path, referrer) in [
+            ("/", "https://example.com/"),
+            ("/blog", "/"),
+            ("/", "/blog"),
+        ] {
+            store.save_pageview(&view(path, referrer)).await.unwrap();
+        }
+        let rows = sqlx::query(concat!(
+            r#"SELECT paths.value, page_views.title, referrers.value, page_views.session_id"#,
+            r#" FROM page_views"#,
+            r#" JOIN paths ON paths.id = page_views.path_id"#,
+            r#" JOIN referrers ON referrers.id = page_views.referrer_id"#,
+            r#" ORDER BY page_views.id"#
+        ))
+        .fetch_all(&store.pool)
+        .await
+        .unwrap();
+        let views: Vec<(String, String, String, i64)> = rows
+            .iter()
+            .map(|r| (r.get(0), r.get(1), r.get(2), r.get(3)))
+            .collect();
+        assert_eq!(views.len(), 3);
+        assert_eq!(views[1].0, "/blog");
+        assert_eq!(views[1].1, "title of /blog");
+        assert_eq!(views[1].2, "/");
+        assert!(views.iter().all(|v| v.3 == 1));
+        // counted besides the visit
+        let row = sqlx::query("SELECT hits, page_views FROM sessions WHERE id = 1")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!((row.get::<i64, _>(0), row.get::<i64, _>(1)), (1, 3));
+        // pruned like the visits
+        let policy = RetentionConfig {
+            max_age_days: 0,
+            max_rows: 1,
+            interval_secs: 3600,
+            vacuum: VacuumMode::None,
+        };
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.page_views, 2);
+        // the path "/blog", and the referrers "https://example.com/" and "/"
+        assert_eq!(report.lookups, 3);
+        // erased with the user
+        let report = store
+            .erase_subject(&EraseSubject::User("u1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(report.page_views, 1);
+        assert_eq!(report.subjects, 1);
+    }
+
+    #[tokio::test]
+    async fn test_events_14() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let event = |name: &str, properties: serde_json::Value| EventRequest {
+            name: name.to_string(),
+            properties,
+            bicmid: "b1".to_string(),
+            session_id: "s1".to_string(),
+            user: "u1".to_string(),
+        };
+        let props = serde_json::json!({"plan": "pro"});
+        store
+            .save_event(&event("checkout_started", props.clone()))
+            .await
+            .unwrap();
+        store
+            .save_event(&event("checkout_started", serde_json::Value::Null))
+            .await
+            .unwrap();
+        store
+            .save_event(&event("video_played", props))
+            .await
+            .unwrap();
+        let rows = sqlx::query(concat!(
+            r#"SELECT event_names.value, events.properties, events.session_id FROM events"#,
+            r#" JOIN event_names ON event_names.id = events.name_id ORDER BY events.id"#
+        ))
+        .fetch_all(&store.pool)
+        .await
+        .unwrap();
+        let events: Vec<(String, String, i64)> = rows
+            .iter()
+            .map(|r| (r.get(0), r.get(1), r.get(2)))
+            .collect();
+        assert_eq!(events[0].0, "checkout_started");
+        assert_eq!(events[0].1, r#"{"plan":"pro"}"#);
+        assert_eq!(events[1].1, "{}");
+        assert!(events.iter().all(|e| e.2 == 1));
+        // the events are not counted in the session
+        let row = sqlx::query("SELECT hits, page_views FROM sessions WHERE id = 1")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap();
+        assert_eq!((row.get::<i64, _>(0), row.get::<i64, _>(1)), (0, 0));
+        // pruned like the visits
+        let policy = RetentionConfig {
+            max_age_days: 0,
+            max_rows: 1,
+            interval_secs: 3600,
+            vacuum: VacuumMode::None,
+        };
+        let report = store.prune(&policy).await.unwrap();
+        assert_eq!(report.events, 2);
+        // "checkout_started"
+        assert_eq!(report.lookups, 1);
+        // erased with the user
+        let report = store
+            .erase_subject(&EraseSubject::User("u1".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(report.events, 1);
+        // "video_played", "b1" and the session
+        assert_eq!(report.lookups, 3);
+    }
+
+    #[tokio::test]
+    async fn test_request_ids_15() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let req = |request_id: &str| SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            session_id: "s1".to_string(),
+            request_id: request_id.to_string(),
+            ..Default::default()
+        };
+        store.save_broinfo(&req("r1"), "").await.unwrap();
+        // a retry
+        store.save_broinfo(&req("r1"), "").await.unwrap();
+        // twice in a batch
+        let item = |request_id: &str| SaveBroInfoItem {
+            req: req(request_id),
+            ip_address: String::new(),
+        };
+        store
+            .save_broinfo_batch(&[item("r2"), item("r1"), item("r2")])
+            .await
+            .unwrap();
+        // without a request ID
+        store.save_broinfo(&req(""), "").await.unwrap();
+        store.save_broinfo(&req(""), "").await.unwrap();
+        let rows = sqlx::query("SELECT request_id FROM logs ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let request_ids: Vec<Option<String>> = rows.iter().map(|r| r.get(0)).collect();
+        let expected = [Some("r1"), Some("r2"), None, None].map(|s| s.map(String::from));
+        assert_eq!(request_ids, expected);
+        // the retries are not counted in the session
+        let hits: i64 = sqlx::query("SELECT hits FROM sessions WHERE id = 1")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(hits, 4);
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    #[tokio::test]
+    async fn test_prune_saved_user_agent_16() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let req = SaveUserAgentRequest {
+            ua: "Mozilla/5.0 (only the user agent)".into(),
+            request_id: String::new(),
+        };
+        store.save_user_agent(&req).await.unwrap();
+        //
+        let policy = RetentionConfig {
+            max_age_days: 30,
+            max_rows: 0,
+            interval_secs: 3600,
+            vacuum: VacuumMode::Incremental,
+        };
+        store.prune(&policy).await.unwrap();
+        // the user agent is kept, though no visit refers to it
+        let n: i64 = sqlx::query("SELECT COUNT(*) FROM user_agents WHERE id <> 0")
+            .fetch_one(&store.pool)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(n, 1);
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    #[tokio::test]
+    async fn test_user_agent_request_ids_17() {
+        let store = SqliteStore::open_in_memory().await.unwrap();
+        let req = |ua: &str, request_id: &str| SaveUserAgentRequest {
+            ua: ua.into(),
+            request_id: request_id.to_string(),
+        };
+        store.save_user_agent(&req("ua1", "r1")).await.unwrap();
+        // a retry with another user agent is not stored
+        store.save_user_agent(&req("ua2", "r1")).await.unwrap();
+        store.save_user_agent(&req("ua3", "r2")).await.unwrap();
+        store.save_user_agent(&req("ua4", "")).await.unwrap();
+        store.save_user_agent(&req("ua4", "")).await.unwrap();
+        //
+        let rows = sqlx::query("SELECT value FROM user_agents WHERE id <> 0 ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let uas: Vec<String> = rows.iter().map(|r| r.get(0)).collect();
+        assert_eq!(uas, ["ua1", "ua3", "ua4"]);
+        let rows = sqlx::query("SELECT value FROM user_agent_requests ORDER BY id")
+            .fetch_all(&store.pool)
+            .await
+            .unwrap();
+        let request_ids: Vec<String> = rows.iter().map(|r| r.get(0)).collect();
+        assert_eq!(request_ids, ["r1", "r2"]);
+    }
 }
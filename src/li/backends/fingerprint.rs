@@ -0,0 +1,129 @@
+//! The browser fingerprint, computed on the server from the stable fields of the JS information.
+//! It survives the loss of the BICMID, e.g. cleared storage or a private window, and is stored
+//! in `fingerprints`. The fields are hashed with `fingerprint.secret`, so that the fingerprints
+//! of different servers can not be joined.
+
+use anyhow::{bail, Result};
+use browserinfo::JsInfo;
+
+use super::config::FingerprintConfig;
+
+/// The computation of the browser fingerprint.
+#[derive(Debug, Clone)]
+pub struct Fingerprinter {
+    secret: String,
+}
+
+impl Fingerprinter {
+    pub fn new(secret: &str) -> Result<Self> {
+        if secret.is_empty() {
+            bail!("`fingerprint.secret` must be set for `fingerprint.enabled = true`");
+        }
+        Ok(Self {
+            secret: secret.to_string(),
+        })
+    }
+
+    /// Returns `None` if the fingerprint is not enabled.
+    pub fn from_config(cfg: &FingerprintConfig) -> Result<Option<Self>> {
+        if !cfg.enabled {
+            return Ok(None);
+        }
+        Self::new(&cfg.secret).map(Some)
+    }
+
+    /// Creates the HMAC-SHA256 of the stable fields of `jsinfo` keyed with the secret,
+    /// and encodes it in Base64.
+    ///
+    /// The fields are the platform, the screen, the time zone, the language and the hardware
+    /// concurrency and memory. `JsInfo` has no GPU information.
+    pub fn compute(&self, jsinfo: &JsInfo) -> String {
+        use base64::Engine;
+
+        let mac =
+            hmac_sha256::HMAC::mac(canonical_fields(jsinfo).as_bytes(), self.secret.as_bytes());
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac)
+    }
+}
+
+/// Returns the stable fields as one string, a field per line. A missing field is empty.
+fn canonical_fields(jsinfo: &JsInfo) -> String {
+    fn opt<T: ToString>(v: &Option<T>) -> String {
+        v.as_ref().map(|v| v.to_string()).unwrap_or_default()
+    }
+    let fields = [
+        ("oscpu", jsinfo.oscpu.clone()),
+        ("platform", jsinfo.platform.clone()),
+        ("cpu_cores", opt(&jsinfo.cpu_cores)),
+        ("device_memory", opt(&jsinfo.device_memory)),
+        ("user_language", jsinfo.user_language.clone()),
+        ("screen_width", opt(&jsinfo.screen_width)),
+        ("screen_height", opt(&jsinfo.screen_height)),
+        ("screen_color_depth", opt(&jsinfo.screen_color_depth)),
+        ("device_pixel_ratio", opt(&jsinfo.device_pixel_ratio)),
+        ("timezone", jsinfo.timezone.clone()),
+    ];
+    let mut s = String::from("browserinfocm-fingerprint-1\n");
+    for (k, v) in fields {
+        s.push_str(k);
+        s.push('=');
+        s.push_str(&v.replace('\n', " "));
+        s.push('\n');
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn jsinfo() -> JsInfo {
+        JsInfo {
+            platform: "Linux x86_64".to_string(),
+            cpu_cores: Some(8),
+            user_language: "ja".to_string(),
+            screen_width: Some(1920),
+            screen_height: Some(1080),
+            device_pixel_ratio: Some(1.5),
+            timezone: "Asia/Tokyo".to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_compute_00() {
+        assert!(Fingerprinter::new("").is_err());
+        let fp = Fingerprinter::new("secret").unwrap();
+        let h = fp.compute(&jsinfo());
+        assert_eq!(h.len(), 43);
+        assert_eq!(h, fp.compute(&jsinfo()));
+        // the volatile fields are ignored
+        let mut info = jsinfo();
+        info.is_dark_mode = true;
+        info.cookie_enabled = true;
+        assert_eq!(h, fp.compute(&info));
+        // the stable fields are not
+        let mut info = jsinfo();
+        info.timezone = "UTC".to_string();
+        assert_ne!(h, fp.compute(&info));
+        let mut info = jsinfo();
+        info.screen_width = None;
+        assert_ne!(h, fp.compute(&info));
+        let another = Fingerprinter::new("another").unwrap();
+        assert_ne!(h, another.compute(&jsinfo()));
+    }
+
+    #[test]
+    fn test_from_config_01() {
+        let cfg = FingerprintConfig {
+            enabled: false,
+            secret: String::new(),
+        };
+        assert!(Fingerprinter::from_config(&cfg).unwrap().is_none());
+        let cfg = FingerprintConfig {
+            enabled: true,
+            secret: String::new(),
+        };
+        assert!(Fingerprinter::from_config(&cfg).is_err());
+    }
+}
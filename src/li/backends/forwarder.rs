@@ -1,113 +1,304 @@
 //! Forwarder backend implementation.
-//! Relays browser information requests to another server specified by `NEXT_URL`.
+//! Relays browser information requests to the next server, configured by `forwarder.next_url`
+//! or the `NEXT_URL` environment variable, with its API key and signed with its secret.
 
-use anyhow::Result;
-use browserinfo::{BroInfo, Browser};
-use dioxus::prelude::*;
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use dioxus::fullstack::StatusCode;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use super::auth::{sign_api_request, unix_now};
+use super::auth::{API_KEY_HEADER, API_SIGNATURE_HEADER, API_TIMESTAMP_HEADER};
+use super::config::BackendConfig;
+use super::rejected;
+use super::store::BroInfoStore;
+use super::SaveBroInfoResponse;
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats, SaveBroInfoRequest};
+use super::{EventRequest, FingerprintGroup, FingerprintQuery, PageViewRequest};
 
 #[cfg(feature = "backend_user_agent")]
-use browserinfo::UserAgent;
+use super::SaveUserAgentRequest;
 
-#[cfg(feature = "server")]
-use std::sync::LazyLock;
+/// The store that forwards the browser information to the next server.
+pub struct ForwarderStore {
+    /// The base URL of the next server, without a trailing `/`.
+    next_url: String,
+    /// The admin token of the next server.
+    admin_token: String,
+    /// The API key of the next server, empty for none.
+    api_key: String,
+    /// The signing secret of the next server, empty for none.
+    api_secret: String,
+    /// Shared HTTP client for forwarding requests.
+    client: reqwest::Client,
+}
 
-#[cfg(feature = "server")]
-use serde::{Deserialize, Serialize};
+/// Request body of the `get_db_path` server function.
+#[derive(Serialize)]
+struct DbPathBody<'a> {
+    admin_token: &'a str,
+}
 
-#[cfg(feature = "server")]
-use std::time::Duration;
+/// Request body of the `save_broinfo` server function.
+#[derive(Serialize)]
+struct SaveBroInfoBody<'a> {
+    req: &'a SaveBroInfoRequest,
+}
+
+/// Request body of the `save_user_agent` server function.
+#[cfg(feature = "backend_user_agent")]
+#[derive(Serialize)]
+struct SaveUserAgentBody<'a> {
+    req: &'a SaveUserAgentRequest,
+}
+
+/// Request body of the `save_pageview` server function.
+#[derive(Serialize)]
+struct SavePageViewBody<'a> {
+    req: &'a PageViewRequest,
+}
+
+/// Request body of the `save_event` server function.
+#[derive(Serialize)]
+struct SaveEventBody<'a> {
+    req: &'a EventRequest,
+}
 
-#[cfg(feature = "server")]
-use reqwest;
-
-/// The target URL for forwarding requests, loaded from the `NEXT_URL` environment variable.
-#[cfg(feature = "server")]
-pub static NEXT_URL: LazyLock<Result<String, String>> = LazyLock::new(|| {
-    // NEXT_URL: "http://aki-desktop.local:8080/"
-    std::env::var("NEXT_URL")
-        .map(|val| val.trim_end_matches('/').to_string())
-        .map_err(|_| {
-            "Environment variable 'NEXT_URL' is not set. Please set it to the target backend URL."
-                .to_string()
+/// Request body of the `query_logs` and `query_log_stats` server functions.
+#[derive(Serialize)]
+struct QueryLogsBody<'a> {
+    admin_token: &'a str,
+    query: &'a LogQuery,
+}
+
+/// Request body of the `erase_subject` server function.
+#[derive(Serialize)]
+struct EraseSubjectBody<'a> {
+    admin_token: &'a str,
+    subject: &'a EraseSubject,
+}
+
+/// Request body of the `query_fingerprints` server function.
+#[derive(Serialize)]
+struct QueryFingerprintsBody<'a> {
+    admin_token: &'a str,
+    query: &'a FingerprintQuery,
+}
+
+impl ForwarderStore {
+    /// Creates the store from the config.
+    pub fn new(cfg: &BackendConfig) -> Result<Self> {
+        let next_url = if cfg.forwarder.next_url.is_empty() {
+            // NEXT_URL: "http://aki-desktop.local:8080/"
+            std::env::var("NEXT_URL").map_err(|_| {
+                anyhow!("Neither `forwarder.next_url` nor the environment variable 'NEXT_URL' is set. Please set it to the target backend URL.")
+            })?
+        } else {
+            cfg.forwarder.next_url.clone()
+        };
+        let client = reqwest::Client::builder()
+            .connect_timeout(Duration::from_millis(1000))
+            .build()?;
+        Ok(Self {
+            next_url: next_url.trim_end_matches('/').to_string(),
+            admin_token: cfg.forwarder.admin_token.clone(),
+            api_key: cfg.forwarder.api_key.clone(),
+            api_secret: cfg.forwarder.api_secret.clone(),
+            client,
         })
-});
-
-/// Shared HTTP client for forwarding requests.
-#[cfg(feature = "server")]
-pub static CLIENT: LazyLock<reqwest::Client> = LazyLock::new(|| {
-    reqwest::Client::builder()
-        .connect_timeout(Duration::from_millis(1000))
-        .build()
-        .expect("Failed to create reqwest client")
-});
-
-/// Forwards the database path request to the next backend.
-#[post("/api/v1/mikan1")]
-pub async fn get_db_path() -> Result<String> {
-    let base_url = NEXT_URL.as_ref().map_err(|e| anyhow::anyhow!(e.clone()))?;
-    let url_s = format!("{}/api/v1/mikan1", base_url);
-
-    let resp = CLIENT
-        .post(&url_s)
-        .header("x-request-client", "dioxus")
-        .timeout(Duration::from_millis(5000))
-        .send()
-        .await?
-        .json::<String>()
-        .await?;
-    Ok(resp)
+    }
+
+    /// Builds a request to the server function at `path` of the next server.
+    fn post(&self, path: &str) -> reqwest::RequestBuilder {
+        let url_s = format!("{}{}", self.next_url, path);
+        self.client
+            .post(&url_s)
+            .header("x-request-client", "dioxus")
+            .timeout(Duration::from_millis(5000))
+    }
+
+    /// Builds a request with the JSON `body` to the server function at `path` of the next server,
+    /// with the API key and the signature of the bytes sent if they are configured.
+    fn post_json<B: Serialize>(&self, path: &str, body: &B) -> Result<reqwest::RequestBuilder> {
+        let body = serde_json::to_vec(body)?;
+        let mut rb = self
+            .post(path)
+            .header(reqwest::header::CONTENT_TYPE, "application/json");
+        if !self.api_key.is_empty() {
+            rb = rb.header(API_KEY_HEADER, &self.api_key);
+        }
+        if !self.api_secret.is_empty() {
+            let timestamp = unix_now();
+            let signature = sign_api_request(&self.api_secret, timestamp, path, &body);
+            rb = rb
+                .header(API_TIMESTAMP_HEADER, timestamp.to_string())
+                .header(API_SIGNATURE_HEADER, signature);
+        }
+        Ok(rb.body(body))
+    }
+
+    /// Sends the request, and decodes the JSON response.
+    async fn send<T: DeserializeOwned>(rb: reqwest::RequestBuilder) -> Result<T> {
+        let resp = rb.send().await?;
+        let status = resp.status();
+        if !status.is_success() {
+            let body = resp.text().await.unwrap_or_default();
+            return Err(upstream_error(status.as_u16(), &body));
+        }
+        Ok(resp.json::<T>().await?)
+    }
 }
 
-/// Forwards the IP address request to the next backend.
-#[post("/api/v1/ringo1")]
-pub async fn get_ipaddr() -> Result<String> {
-    let base_url = NEXT_URL.as_ref().map_err(|e| anyhow::anyhow!(e.clone()))?;
-    let url_s = format!("{}/api/v1/ringo1", base_url);
-
-    let resp = CLIENT
-        .post(&url_s)
-        .header("x-request-client", "dioxus")
-        .timeout(Duration::from_millis(5000))
-        .send()
-        .await?
-        .json::<String>()
-        .await?;
-    Ok(resp)
+/// The error payload of a server function.
+#[derive(Deserialize)]
+struct ErrorBody {
+    #[serde(default)]
+    message: String,
+    code: u16,
 }
 
-/// Forwards the user agent save request to the next backend.
-#[cfg(feature = "backend_user_agent")]
-#[post("/api/v1/useragent1")]
-pub async fn save_user_agent(req: super::SaveUserAgentRequest) -> Result<()> {
-    let base_url = NEXT_URL.as_ref().map_err(|e| anyhow::anyhow!(e.clone()))?;
-    let url_s = format!("{}/api/v1/useragent1", base_url);
-
-    let _resp = CLIENT
-        .post(&url_s)
-        .header("x-request-client", "dioxus")
-        .timeout(Duration::from_millis(5000))
-        .json(&req)
-        .send()
-        .await?;
-    //dioxus_logger::tracing::info!("save_user_agent next: {_res:?}");
-    Ok(())
+/// Converts the error response of the next server into the error returned to the client.
+///
+/// A rejection, a 4xx code of the error payload or else a 4xx status, is passed through,
+/// so that the client does not retry it; anything else is a 500.
+fn upstream_error(status: u16, body: &str) -> anyhow::Error {
+    let (code, message) = match serde_json::from_str::<ErrorBody>(body) {
+        Ok(e) => (e.code, e.message),
+        Err(_) => (status, body.to_string()),
+    };
+    match StatusCode::from_u16(code) {
+        Ok(code) if code.is_client_error() => rejected(code, message),
+        _ => anyhow!("the next server failed with {status}: {message}"),
+    }
 }
 
-/// Forwards the full browser info save request to the next backend.
-#[post("/api/v1/browserinfo1")]
-pub async fn save_broinfo(req: super::SaveBroInfoRequest) -> Result<Option<Browser>> {
-    let base_url = NEXT_URL.as_ref().map_err(|e| anyhow::anyhow!(e.clone()))?;
-    let url_s = format!("{}/api/v1/browserinfo1", base_url);
-
-    let resp = CLIENT
-        .post(&url_s)
-        .header("x-request-client", "dioxus")
-        .timeout(Duration::from_millis(5000))
-        .json(&req)
-        .send()
-        .await?
-        .json::<Option<Browser>>()
-        .await?;
-    //dioxus_logger::tracing::info!("save_broinfo next: {_res:?}");
-    Ok(resp)
+#[async_trait]
+impl BroInfoStore for ForwarderStore {
+    fn kind(&self) -> &'static str {
+        "forwarder"
+    }
+
+    /// Forwards the database path request to the next backend.
+    async fn db_path(&self) -> Result<String> {
+        let rb = self.post_json(
+            "/api/v1/mikan1",
+            &DbPathBody {
+                admin_token: &self.admin_token,
+            },
+        )?;
+        Self::send(rb).await
+    }
+
+    /// Checks the health of the next backend.
+    async fn health(&self) -> Result<()> {
+        let _kind: String = Self::send(self.post("/api/v1/health1")).await?;
+        Ok(())
+    }
+
+    /// Forwards the full browser info save request to the next backend.
+    ///
+    /// The client's IP address is passed on in the `x-forwarded-for` header,
+    /// and the request ID in the request, so that the next backend deduplicates the retries too.
+    async fn save_broinfo(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<()> {
+        let rb = self
+            .post_json("/api/v1/browserinfo1", &SaveBroInfoBody { req })?
+            .header("x-forwarded-for", ip_address);
+        let _resp: SaveBroInfoResponse = Self::send(rb).await?;
+        Ok(())
+    }
+
+    /// Forwards the user agent save request to the next backend.
+    #[cfg(feature = "backend_user_agent")]
+    async fn save_user_agent(&self, req: &SaveUserAgentRequest) -> Result<()> {
+        let rb = self.post_json("/api/v1/useragent1", &SaveUserAgentBody { req })?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the page view save request to the next backend.
+    async fn save_pageview(&self, req: &PageViewRequest) -> Result<()> {
+        let rb = self.post_json("/api/v1/pageview1", &SavePageViewBody { req })?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the custom event save request to the next backend.
+    async fn save_event(&self, req: &EventRequest) -> Result<()> {
+        let rb = self.post_json("/api/v1/event1", &SaveEventBody { req })?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the logs query to the next backend.
+    async fn query_logs(&self, query: &LogQuery) -> Result<LogPage> {
+        let rb = self.post_json(
+            "/api/v1/logs1",
+            &QueryLogsBody {
+                admin_token: &self.admin_token,
+                query,
+            },
+        )?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the logs statistics query to the next backend.
+    async fn query_log_stats(&self, query: &LogQuery) -> Result<LogStats> {
+        let rb = self.post_json(
+            "/api/v1/logstats1",
+            &QueryLogsBody {
+                admin_token: &self.admin_token,
+                query,
+            },
+        )?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the erasure to the next backend.
+    async fn erase_subject(&self, subject: &EraseSubject) -> Result<EraseReport> {
+        let rb = self.post_json(
+            "/api/v1/erase1",
+            &EraseSubjectBody {
+                admin_token: &self.admin_token,
+                subject,
+            },
+        )?;
+        Self::send(rb).await
+    }
+
+    /// Forwards the fingerprint query to the next backend.
+    async fn query_fingerprints(&self, query: &FingerprintQuery) -> Result<Vec<FingerprintGroup>> {
+        let rb = self.post_json(
+            "/api/v1/fingerprints1",
+            &QueryFingerprintsBody {
+                admin_token: &self.admin_token,
+                query,
+            },
+        )?;
+        Self::send(rb).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn status(e: &anyhow::Error) -> Option<StatusCode> {
+        e.downcast_ref::<dioxus::fullstack::HttpError>()
+            .map(|e| e.status)
+    }
+
+    #[test]
+    fn test_upstream_error_00() {
+        // the server functions answer 500 with the status in the error payload
+        let e = upstream_error(500, r#"{"message":"too many requests","code":429}"#);
+        assert_eq!(status(&e), Some(StatusCode::TOO_MANY_REQUESTS));
+        assert!(e.to_string().ends_with(": too many requests"));
+        let e = upstream_error(413, r#"{"message":"the request is too large","code":413}"#);
+        assert_eq!(status(&e), Some(StatusCode::PAYLOAD_TOO_LARGE));
+        let e = upstream_error(400, "Bad Request");
+        assert_eq!(status(&e), Some(StatusCode::BAD_REQUEST));
+        // a failure of the next server is not a rejection
+        let e = upstream_error(500, r#"{"message":"database is locked","code":500}"#);
+        assert_eq!(status(&e), None);
+        assert_eq!(status(&upstream_error(502, "Bad Gateway")), None);
+    }
 }
@@ -0,0 +1,141 @@
+//! Offline GeoIP enrichment of the stored IP addresses.
+//! Resolves `ip_addresses.value` against local MaxMind DB files (`.mmdb`): the GeoIP2 or GeoLite2
+//! City database for the country, region and city, and the ASN database for the autonomous system.
+
+use anyhow::{Context, Result};
+use maxminddb::{geoip2, MaxMindDBError, Reader};
+use std::net::IpAddr;
+
+use super::config::GeoIpConfig;
+
+/// The GeoIP of an IP address. Every field is `None` if it is not in the database.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct GeoInfo {
+    /// The ISO 3166-1 alpha-2 code of the country. ex.) `JP`
+    pub country_code: Option<String>,
+    /// The English name of the first subdivision. ex.) `Tokyo`
+    pub region_name: Option<String>,
+    /// The English name of the city.
+    pub city_name: Option<String>,
+    pub autonomous_system_number: Option<u32>,
+    pub autonomous_system_organization: Option<String>,
+}
+
+/// The readers of the City and ASN databases.
+pub struct GeoIpReader {
+    city: Option<Reader<Vec<u8>>>,
+    asn: Option<Reader<Vec<u8>>>,
+}
+
+impl std::fmt::Debug for GeoIpReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GeoIpReader")
+            .field("city", &self.city.is_some())
+            .field("asn", &self.asn.is_some())
+            .finish()
+    }
+}
+
+impl GeoIpReader {
+    /// Opens the configured databases. Returns `None` if neither is configured.
+    pub fn from_config(cfg: &GeoIpConfig) -> Result<Option<Self>> {
+        if cfg.city_db.is_empty() && cfg.asn_db.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self {
+            city: open_db(&cfg.city_db)?,
+            asn: open_db(&cfg.asn_db)?,
+        }))
+    }
+
+    /// Resolves `ip_address`. Returns `None` if it is not an IP address, ex.) hashed.
+    pub fn lookup(&self, ip_address: &str) -> Result<Option<GeoInfo>> {
+        let Ok(ip) = ip_address.parse::<IpAddr>() else {
+            return Ok(None);
+        };
+        let mut info = GeoInfo::default();
+        if let Some(reader) = &self.city {
+            if let Some(city) = not_found_as_none(reader.lookup::<geoip2::City>(ip))? {
+                info.country_code = city.country.and_then(|c| c.iso_code).map(|s| s.to_string());
+                info.region_name = city
+                    .subdivisions
+                    .and_then(|v| v.into_iter().next())
+                    .and_then(|s| s.names)
+                    .and_then(|names| names.get("en").map(|s| s.to_string()));
+                info.city_name = city
+                    .city
+                    .and_then(|c| c.names)
+                    .and_then(|names| names.get("en").map(|s| s.to_string()));
+            }
+        }
+        if let Some(reader) = &self.asn {
+            if let Some(asn) = not_found_as_none(reader.lookup::<geoip2::Asn>(ip))? {
+                info.autonomous_system_number = asn.autonomous_system_number;
+                info.autonomous_system_organization =
+                    asn.autonomous_system_organization.map(|s| s.to_string());
+            }
+        }
+        Ok(Some(info))
+    }
+}
+
+fn open_db(path: &str) -> Result<Option<Reader<Vec<u8>>>> {
+    if path.is_empty() {
+        return Ok(None);
+    }
+    let reader = Reader::open_readfile(path).with_context(|| format!("geoip: {path}"))?;
+    Ok(Some(reader))
+}
+
+fn not_found_as_none<T>(r: std::result::Result<T, MaxMindDBError>) -> Result<Option<T>> {
+    match r {
+        Ok(v) => Ok(Some(v)),
+        Err(MaxMindDBError::AddressNotFoundError(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod test {
+    use super::*;
+
+    /// The fixture written by `tests/fixtures/make-geoip-test-mmdb.py`.
+    pub fn fixture_reader() -> GeoIpReader {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/tests/fixtures/geoip-test.mmdb"
+        );
+        let cfg = GeoIpConfig {
+            city_db: path.to_string(),
+            asn_db: path.to_string(),
+        };
+        GeoIpReader::from_config(&cfg).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_lookup_00() {
+        let reader = fixture_reader();
+        let info = reader.lookup("192.0.2.7").unwrap().unwrap();
+        let expected = GeoInfo {
+            country_code: Some("JP".to_string()),
+            region_name: Some("Tokyo".to_string()),
+            city_name: Some("Tokyo".to_string()),
+            autonomous_system_number: Some(64500),
+            autonomous_system_organization: Some("Example Net JP".to_string()),
+        };
+        assert_eq!(info, expected);
+        let info = reader.lookup("2001:db8:1::1").unwrap().unwrap();
+        assert_eq!(info.country_code.as_deref(), Some("DE"));
+        assert_eq!(info.autonomous_system_number, Some(64501));
+        let info = reader.lookup("198.51.100.1").unwrap().unwrap();
+        assert_eq!(info.country_code.as_deref(), Some("US"));
+        assert_eq!(info.city_name, None);
+        //
+        assert_eq!(
+            reader.lookup("203.0.113.1").unwrap(),
+            Some(GeoInfo::default())
+        );
+        assert_eq!(reader.lookup("").unwrap(), None);
+        assert_eq!(reader.lookup("Zm9vYmFy").unwrap(), None);
+    }
+}
@@ -0,0 +1,47 @@
+//! Conversion of the JS information stored by the older versions.
+//! They stored it as TOML with `<BR>` line breaks; it is stored as JSON now.
+
+use browserinfo::JsInfo;
+
+/// The name of the conversion in `data_conversions`, recorded once it is done.
+pub const LEGACY_TOML_CONVERSION: &str = "jsinfos-toml-to-json";
+
+/// Returns `true` if `value` of `jsinfos` is in the old TOML format.
+pub fn is_legacy_toml(value: &str) -> bool {
+    !value.is_empty() && !value.starts_with('{')
+}
+
+/// Converts `value` of `jsinfos` from the old TOML format to JSON,
+/// the same as a new save of the same JS information.
+///
+/// A value that is not the TOML of `JsInfo` is kept as the string `{"toml": value}`.
+pub fn json_from_legacy_toml(value: &str) -> String {
+    let toml_s = value.replace("<BR>", "\n");
+    toml::from_str::<JsInfo>(&toml_s)
+        .ok()
+        .and_then(|info| serde_json::to_string(&info).ok())
+        .unwrap_or_else(|| serde_json::json!({ "toml": toml_s }).to_string())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_json_from_legacy_toml_00() {
+        let info = JsInfo {
+            platform: "Linux x86_64".to_string(),
+            screen_width: Some(1920),
+            timezone: "Asia/Tokyo".to_string(),
+            ..Default::default()
+        };
+        let legacy = toml::to_string(&info).unwrap().replace('\n', "<BR>");
+        assert!(is_legacy_toml(&legacy));
+        let json = json_from_legacy_toml(&legacy);
+        assert!(!is_legacy_toml(&json));
+        assert_eq!(json, serde_json::to_string(&info).unwrap());
+        //
+        let json = json_from_legacy_toml("not = [toml");
+        assert_eq!(json, r#"{"toml":"not = [toml"}"#);
+    }
+}
@@ -0,0 +1,465 @@
+//! Abuse protection of the save endpoints.
+//! Token buckets per client IP address and per BICMID, limits of the payload size and of the
+//! field lengths, and a sanity check of the JS information, configured by `[limits]`.
+//! The accepted and the rejected requests are counted in `SaveMetrics`.
+
+use anyhow::Result;
+use browserinfo::JsInfo;
+use dioxus::fullstack::axum::middleware::Next;
+use dioxus::fullstack::body::{to_bytes, Body};
+use dioxus::fullstack::extract::Request;
+use dioxus::fullstack::response::Response;
+use dioxus::fullstack::{Method, StatusCode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+
+use super::config::{BackendConfig, LimitsConfig};
+use super::{rejected, rejected_response};
+use super::{EventRequest, PageViewRequest, SaveBroInfoRequest, SaveMetrics};
+
+/// The number of the buckets kept. Beyond it, the buckets that are full again are forgotten,
+/// but never one that still lacks tokens; a new key is refused until one is full again.
+const BUCKETS_MAX: usize = 10_000;
+
+/// The paths of the save endpoints, whose payload size is limited.
+const SAVE_PATHS: &[&str] = &[
+    "/api/v1/browserinfo1",
+    "/api/v1/useragent1",
+    "/api/v1/pageview1",
+    "/api/v1/event1",
+];
+
+/// Why a save request is rejected.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Rejection {
+    RateLimitedIp,
+    RateLimitedBicmid,
+    PayloadTooLarge,
+    FieldTooLong,
+    MalformedJsinfo,
+}
+
+impl Rejection {
+    /// Returns the HTTP status of the rejection.
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::RateLimitedIp | Self::RateLimitedBicmid => StatusCode::TOO_MANY_REQUESTS,
+            Self::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::FieldTooLong | Self::MalformedJsinfo => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            Self::RateLimitedIp => "too many requests from the IP address",
+            Self::RateLimitedBicmid => "too many requests from the BICMID",
+            Self::PayloadTooLarge => "the request is too large",
+            Self::FieldTooLong => "a field of the request is too long",
+            Self::MalformedJsinfo => "the JS information is malformed",
+        };
+        f.write_str(s)
+    }
+}
+
+/// One token bucket.
+#[derive(Debug, Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    at: Instant,
+}
+
+/// The token buckets of one kind of key.
+#[derive(Debug)]
+struct TokenBuckets {
+    burst: f64,
+    per_sec: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+impl TokenBuckets {
+    /// Creates the buckets of `burst` tokens, refilled by `per_minute` tokens a minute.
+    /// A `burst` of `0` limits nothing.
+    fn new(burst: u32, per_minute: u32) -> Self {
+        Self {
+            burst: burst as f64,
+            per_sec: per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns the tokens of `b` refilled until `now`.
+    fn refilled(&self, b: &Bucket, now: Instant) -> f64 {
+        let secs = now.saturating_duration_since(b.at).as_secs_f64();
+        (b.tokens + secs * self.per_sec).min(self.burst)
+    }
+
+    /// Takes a token of `key` at `now`, and returns `false` if there is none.
+    /// An empty key is not limited.
+    fn take(&self, key: &str, now: Instant) -> bool {
+        if self.burst <= 0.0 || key.is_empty() {
+            return true;
+        }
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= BUCKETS_MAX && !buckets.contains_key(key) {
+            buckets.retain(|_, b| self.refilled(b, now) < self.burst);
+            if buckets.len() >= BUCKETS_MAX {
+                return false;
+            }
+        }
+        let b = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.burst,
+            at: now,
+        });
+        b.tokens = self.refilled(b, now);
+        b.at = now;
+        if b.tokens < 1.0 {
+            return false;
+        }
+        b.tokens -= 1.0;
+        true
+    }
+}
+
+/// The counters of `SaveMetrics`.
+#[derive(Debug, Default)]
+struct Counters {
+    accepted: AtomicU64,
+    rate_limited_ip: AtomicU64,
+    rate_limited_bicmid: AtomicU64,
+    payload_too_large: AtomicU64,
+    field_too_long: AtomicU64,
+    malformed_jsinfo: AtomicU64,
+}
+
+/// The abuse protection of the save endpoints.
+#[derive(Debug)]
+pub struct SaveLimiter {
+    ip: TokenBuckets,
+    bicmid: TokenBuckets,
+    max_payload_bytes: usize,
+    max_field_len: usize,
+    counters: Counters,
+}
+
+impl SaveLimiter {
+    pub fn new(cfg: &LimitsConfig) -> Self {
+        Self {
+            ip: TokenBuckets::new(cfg.ip_burst, cfg.ip_per_minute),
+            bicmid: TokenBuckets::new(cfg.bicmid_burst, cfg.bicmid_per_minute),
+            max_payload_bytes: cfg.max_payload_bytes,
+            max_field_len: cfg.max_field_len,
+            counters: Counters::default(),
+        }
+    }
+
+    /// Checks a `save_broinfo` request from `ip_address` at `now`.
+    pub fn check_broinfo(
+        &self,
+        req: &SaveBroInfoRequest,
+        ip_address: &str,
+        now: Instant,
+    ) -> Result<()> {
+        let basic = &req.broinfo.basic;
+        let jsinfo = &req.broinfo.jsinfo;
+        let fields = [
+            basic.user_agent.get(),
+            basic.referrer.get(),
+            &req.bicmid,
+            &req.user,
+            &req.session_id,
+            &req.request_id,
+            &jsinfo.oscpu,
+            &jsinfo.platform,
+            &jsinfo.user_language,
+            &jsinfo.timezone,
+        ];
+        let r = self
+            .take(ip_address, &req.bicmid, now)
+            .and_then(|_| self.check_fields(&fields))
+            .and_then(|_| check_jsinfo(jsinfo));
+        self.count(r)
+    }
+
+    /// Checks a `save_pageview` request from `ip_address` at `now`.
+    pub fn check_pageview(
+        &self,
+        req: &PageViewRequest,
+        ip_address: &str,
+        now: Instant,
+    ) -> Result<()> {
+        let fields = [
+            &req.path,
+            &req.title,
+            &req.referrer,
+            &req.bicmid,
+            &req.session_id,
+            &req.user,
+        ];
+        let r = self
+            .take(ip_address, &req.bicmid, now)
+            .and_then(|_| self.check_fields(&fields.map(String::as_str)));
+        self.count(r)
+    }
+
+    /// Checks a `save_event` request from `ip_address` at `now`.
+    pub fn check_event(&self, req: &EventRequest, ip_address: &str, now: Instant) -> Result<()> {
+        let fields = [&req.name, &req.bicmid, &req.session_id, &req.user];
+        let r = self
+            .take(ip_address, &req.bicmid, now)
+            .and_then(|_| self.check_fields(&fields.map(String::as_str)));
+        self.count(r)
+    }
+
+    /// Returns the numbers of the checked requests.
+    pub fn metrics(&self) -> SaveMetrics {
+        let c = &self.counters;
+        SaveMetrics {
+            accepted: c.accepted.load(Ordering::Relaxed),
+            rate_limited_ip: c.rate_limited_ip.load(Ordering::Relaxed),
+            rate_limited_bicmid: c.rate_limited_bicmid.load(Ordering::Relaxed),
+            payload_too_large: c.payload_too_large.load(Ordering::Relaxed),
+            field_too_long: c.field_too_long.load(Ordering::Relaxed),
+            malformed_jsinfo: c.malformed_jsinfo.load(Ordering::Relaxed),
+        }
+    }
+
+    fn take(&self, ip_address: &str, bicmid: &str, now: Instant) -> Result<(), Rejection> {
+        if !self.ip.take(ip_address, now) {
+            return Err(Rejection::RateLimitedIp);
+        }
+        if !self.bicmid.take(bicmid, now) {
+            return Err(Rejection::RateLimitedBicmid);
+        }
+        Ok(())
+    }
+
+    /// Checks the lengths of the string `fields`. `0` limits nothing.
+    fn check_fields(&self, fields: &[&str]) -> Result<(), Rejection> {
+        if self.max_field_len > 0 && fields.iter().any(|s| s.len() > self.max_field_len) {
+            return Err(Rejection::FieldTooLong);
+        }
+        Ok(())
+    }
+
+    /// Counts the result of a check, and converts it into the error returned to the client.
+    fn count(&self, r: Result<(), Rejection>) -> Result<()> {
+        let c = &self.counters;
+        let counter = match r {
+            Ok(()) => &c.accepted,
+            Err(Rejection::RateLimitedIp) => &c.rate_limited_ip,
+            Err(Rejection::RateLimitedBicmid) => &c.rate_limited_bicmid,
+            Err(Rejection::PayloadTooLarge) => &c.payload_too_large,
+            Err(Rejection::FieldTooLong) => &c.field_too_long,
+            Err(Rejection::MalformedJsinfo) => &c.malformed_jsinfo,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        r.map_err(|e| rejected(e.status(), e))
+    }
+}
+
+/// Rejects the JS information that no browser sends: a number out of its range,
+/// or a control character in a string.
+fn check_jsinfo(info: &JsInfo) -> Result<(), Rejection> {
+    let in_range = |v: Option<i32>, max: i32| v.is_none_or(|n| (0..=max).contains(&n));
+    let ok = in_range(info.screen_width, 100_000)
+        && in_range(info.screen_height, 100_000)
+        && in_range(info.screen_color_depth, 64)
+        && in_range(info.cpu_cores, 4096)
+        && in_range(info.device_memory, 65_536)
+        && info
+            .device_pixel_ratio
+            .is_none_or(|r| r.is_finite() && r > 0.0 && r <= 16.0)
+        && [
+            &info.oscpu,
+            &info.platform,
+            &info.user_language,
+            &info.timezone,
+        ]
+        .iter()
+        .all(|s| !s.chars().any(char::is_control));
+    if ok {
+        Ok(())
+    } else {
+        Err(Rejection::MalformedJsinfo)
+    }
+}
+
+/// Reads the body of `req` into memory, and rejects it if it is over `max` bytes.
+/// `0` limits nothing.
+async fn read_body(req: Request, max: usize) -> Result<Request, Rejection> {
+    let limit = if max == 0 { usize::MAX } else { max };
+    let declared = req
+        .headers()
+        .get(dioxus::fullstack::http::header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|s| s.parse::<usize>().ok());
+    if declared.is_some_and(|n| n > limit) {
+        return Err(Rejection::PayloadTooLarge);
+    }
+    let (parts, body) = req.into_parts();
+    let bytes = to_bytes(body, limit)
+        .await
+        .map_err(|_| Rejection::PayloadTooLarge)?;
+    Ok(Request::from_parts(parts, Body::from(bytes)))
+}
+
+/// The middleware that rejects a save request whose raw body is over `limits.max_payload_bytes`,
+/// before it is deserialized.
+pub async fn limit_payload(req: Request, next: Next) -> Response {
+    let path = req.uri().path();
+    if req.method() != Method::POST || !SAVE_PATHS.iter().any(|p| path.ends_with(p)) {
+        return next.run(req).await;
+    }
+    let limiter = limiter();
+    match read_body(req, limiter.max_payload_bytes).await {
+        Ok(req) => next.run(req).await,
+        Err(r) => {
+            limiter.count(Err(r)).ok();
+            rejected_response(r.status(), r)
+        }
+    }
+}
+
+static LIMITER: OnceLock<SaveLimiter> = OnceLock::new();
+
+/// Returns the global limiter of the `[limits]` config.
+pub fn limiter() -> &'static SaveLimiter {
+    LIMITER.get_or_init(|| SaveLimiter::new(&BackendConfig::global().limits))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn config() -> LimitsConfig {
+        LimitsConfig {
+            ip_burst: 3,
+            ip_per_minute: 60,
+            bicmid_burst: 2,
+            bicmid_per_minute: 60,
+            max_payload_bytes: 4096,
+            max_field_len: 64,
+        }
+    }
+
+    #[test]
+    fn test_token_buckets_00() {
+        let buckets = TokenBuckets::new(2, 60);
+        let t0 = Instant::now();
+        assert!(buckets.take("a", t0));
+        assert!(buckets.take("a", t0));
+        assert!(!buckets.take("a", t0));
+        assert!(buckets.take("b", t0));
+        assert!(buckets.take("", t0));
+        // one token a second
+        assert!(buckets.take("a", t0 + Duration::from_secs(1)));
+        assert!(!buckets.take("a", t0 + Duration::from_secs(1)));
+        //
+        let unlimited = TokenBuckets::new(0, 0);
+        assert!((0..10).all(|_| unlimited.take("a", t0)));
+    }
+
+    #[test]
+    fn test_token_buckets_full_04() {
+        let buckets = TokenBuckets::new(1, 60);
+        let t0 = Instant::now();
+        for i in 0..BUCKETS_MAX {
+            assert!(buckets.take(&format!("k{i}"), t0));
+        }
+        // all of them lack a token, and none is forgotten
+        assert!(!buckets.take("new", t0));
+        assert!(!buckets.take("k0", t0));
+        // the full ones are forgotten
+        let t1 = t0 + Duration::from_secs(1);
+        assert!(buckets.take("new", t1));
+        assert!(!buckets.take("new", t1));
+        assert_eq!(buckets.buckets.lock().unwrap().len(), 1);
+    }
+
+    fn status(e: &anyhow::Error) -> StatusCode {
+        e.downcast_ref::<dioxus::fullstack::HttpError>()
+            .unwrap()
+            .status
+    }
+
+    #[test]
+    fn test_check_broinfo_01() {
+        let limiter = SaveLimiter::new(&config());
+        let t0 = Instant::now();
+        let req = |bicmid: &str| SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            ..Default::default()
+        };
+        assert!(limiter.check_broinfo(&req("b1"), "192.0.2.1", t0).is_ok());
+        assert!(limiter.check_broinfo(&req("b1"), "192.0.2.1", t0).is_ok());
+        assert!(limiter.check_broinfo(&req("b1"), "192.0.2.1", t0).is_err());
+        let e = limiter
+            .check_broinfo(&req("b2"), "192.0.2.1", t0)
+            .unwrap_err();
+        assert_eq!(status(&e), StatusCode::TOO_MANY_REQUESTS);
+        //
+        let t1 = t0 + Duration::from_secs(60);
+        let mut long = req("b3");
+        long.user = "u".repeat(65);
+        let e = limiter.check_broinfo(&long, "192.0.2.2", t1).unwrap_err();
+        assert_eq!(status(&e), StatusCode::BAD_REQUEST);
+        let mut large = req("b4");
+        large.broinfo.basic.referrer = "r".repeat(60).as_str().into();
+        large.broinfo.jsinfo.timezone = "t".repeat(60);
+        assert!(limiter.check_broinfo(&large, "192.0.2.3", t1).is_ok());
+        let mut malformed = req("b5");
+        malformed.broinfo.jsinfo.screen_width = Some(-1);
+        assert!(limiter.check_broinfo(&malformed, "192.0.2.4", t1).is_err());
+        malformed.broinfo.jsinfo.screen_width = Some(1920);
+        malformed.broinfo.jsinfo.device_pixel_ratio = Some(f64::NAN);
+        assert!(limiter.check_broinfo(&malformed, "192.0.2.4", t1).is_err());
+        //
+        let metrics = limiter.metrics();
+        assert_eq!(metrics.accepted, 3);
+        assert_eq!(metrics.rate_limited_bicmid, 1);
+        assert_eq!(metrics.rate_limited_ip, 1);
+        assert_eq!(metrics.field_too_long, 1);
+        assert_eq!(metrics.malformed_jsinfo, 2);
+        assert_eq!(metrics.payload_too_large, 0);
+    }
+
+    #[test]
+    fn test_check_event_02() {
+        let limiter = SaveLimiter::new(&config());
+        let t0 = Instant::now();
+        let mut req = EventRequest {
+            name: "checkout_started".to_string(),
+            ..Default::default()
+        };
+        assert!(limiter.check_event(&req, "", t0).is_ok());
+        req.name = "n".repeat(65);
+        assert!(limiter.check_event(&req, "", t0).is_err());
+        assert_eq!(limiter.metrics().field_too_long, 1);
+    }
+
+    #[tokio::test]
+    async fn test_read_body_03() {
+        let req = |body: String, len: Option<usize>| {
+            let mut b = dioxus::fullstack::http::Request::builder().uri("/api/v1/event1");
+            if let Some(len) = len {
+                b = b.header("content-length", len);
+            }
+            b.body(Body::from(body)).unwrap()
+        };
+        let req_ok = read_body(req("x".repeat(256), None), 256).await.unwrap();
+        let bytes = to_bytes(req_ok.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(bytes.len(), 256);
+        // the raw body, not its re-serialization, is measured
+        let r = read_body(req("x".repeat(257), None), 256).await;
+        assert_eq!(r.err(), Some(Rejection::PayloadTooLarge));
+        // a too large Content-Length is rejected without reading the body
+        let r = read_body(req(String::new(), Some(257)), 256).await;
+        assert_eq!(r.err(), Some(Rejection::PayloadTooLarge));
+        assert!(read_body(req("x".repeat(257), None), 0).await.is_ok());
+    }
+}
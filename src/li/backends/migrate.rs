@@ -0,0 +1,117 @@
+//! Versioned schema migrations for the database backends.
+//! The `migrations/*.up.sql` scripts, or `migrations/postgres/*.up.sql` for PostgreSQL,
+//! are embedded at compile time, applied in version order and recorded in the `_sqlx_migrations` table;
+//! the matching `*.down.sql` scripts are used for rollback.
+
+use anyhow::{anyhow, Result};
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use sqlx::Acquire;
+use std::ops::Deref;
+
+/// Migrations for the SQLite backend.
+pub static SQLITE_MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Migrations for the PostgreSQL backend.
+#[cfg(feature = "database_postgres")]
+pub static POSTGRES_MIGRATOR: Migrator = sqlx::migrate!("./migrations/postgres");
+
+/// Applies every pending migration.
+///
+/// Refuses to continue when the database has a migration applied that this binary does not know,
+/// i.e. the database schema is newer than the binary.
+pub async fn run_migrations<'a, A>(migrator: &Migrator, conn: A) -> Result<()>
+where
+    A: Acquire<'a>,
+    <A::Connection as Deref>::Target: Migrate,
+{
+    migrator
+        .run(conn)
+        .await
+        .map_err(|e| migrate_error(migrator, e))
+}
+
+/// Reverts every applied migration newer than `target`.
+///
+/// `target = 0` reverts all of them.
+pub async fn undo_migrations<'a, A>(migrator: &Migrator, conn: A, target: i64) -> Result<()>
+where
+    A: Acquire<'a>,
+    <A::Connection as Deref>::Target: Migrate,
+{
+    migrator
+        .undo(conn, target)
+        .await
+        .map_err(|e| migrate_error(migrator, e))
+}
+
+/// Returns the latest migration version known to this binary.
+pub fn latest_version(migrator: &Migrator) -> i64 {
+    migrator.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+fn migrate_error(migrator: &Migrator, e: MigrateError) -> anyhow::Error {
+    match e {
+        MigrateError::VersionMissing(v) => anyhow!(
+            "the database schema (migration {v}) is newer than this binary (latest migration {}); refusing to start",
+            latest_version(migrator)
+        ),
+        e => anyhow!(e).context("Failed to migrate the database schema"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use sqlx::Row;
+
+    async fn memory_pool() -> sqlx::SqlitePool {
+        // a single connection, since each in-memory connection is its own database
+        SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap()
+    }
+
+    async fn table_count(pool: &sqlx::SqlitePool, name: &str) -> i64 {
+        sqlx::query(r#"SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?"#)
+            .bind(name)
+            .fetch_one(pool)
+            .await
+            .unwrap()
+            .get(0)
+    }
+
+    #[tokio::test]
+    async fn test_run_and_undo_00() {
+        let pool = memory_pool().await;
+        run_migrations(&SQLITE_MIGRATOR, &pool).await.unwrap();
+        assert_eq!(table_count(&pool, "logs").await, 1);
+        // running again is a no-op
+        run_migrations(&SQLITE_MIGRATOR, &pool).await.unwrap();
+        //
+        undo_migrations(&SQLITE_MIGRATOR, &pool, 0).await.unwrap();
+        assert_eq!(table_count(&pool, "logs").await, 0);
+        assert_eq!(table_count(&pool, "jsinfos").await, 0);
+    }
+
+    #[tokio::test]
+    async fn test_newer_database_01() {
+        let pool = memory_pool().await;
+        run_migrations(&SQLITE_MIGRATOR, &pool).await.unwrap();
+        let newer = latest_version(&SQLITE_MIGRATOR) + 1;
+        sqlx::query(concat!(
+            r#"INSERT INTO _sqlx_migrations"#,
+            r#" (version, description, success, checksum, execution_time)"#,
+            r#" VALUES (?, 'from the future', TRUE, X'00', 0)"#
+        ))
+        .bind(newer)
+        .execute(&pool)
+        .await
+        .unwrap();
+        let r = run_migrations(&SQLITE_MIGRATOR, &pool).await;
+        let msg = r.unwrap_err().to_string();
+        assert!(msg.contains("refusing to start"), "{msg}");
+    }
+}
@@ -1,41 +1,195 @@
 //! Backend module for handling data persistence.
-//! Switches between local SQLite storage and remote forwarding based on feature flags.
-#[cfg(not(feature = "backend_next"))]
-mod db_sqlite;
-#[cfg(not(feature = "backend_next"))]
-pub use db_sqlite::*;
+//! The server functions in `api` save and query through a `BroInfoStore`: local SQLite storage,
+//! PostgreSQL with the `database_postgres` feature, or remote forwarding with the `backend_next` feature,
+//! selected at runtime by `backend.kind`.
+mod api;
+pub use api::*;
 
-#[cfg(not(feature = "backend_next"))]
 #[cfg(feature = "server")]
 mod config;
 
+#[cfg(feature = "server")]
+mod migrate;
+
+#[cfg(feature = "server")]
+mod auth;
+
+#[cfg(feature = "server")]
+mod store;
+
+#[cfg(feature = "server")]
+mod write_queue;
+
+#[cfg(feature = "server")]
+mod retention;
+
+#[cfg(feature = "server")]
+mod limits;
+
+#[cfg(feature = "server")]
+mod bot;
+
+#[cfg(feature = "server")]
+mod privacy;
+
+#[cfg(feature = "server")]
+mod client_ip;
+
+#[cfg(feature = "server")]
+mod user_agent;
+
+#[cfg(feature = "server")]
+mod jsinfo;
+
+#[cfg(feature = "server")]
+mod fingerprint;
+
+#[cfg(feature = "geoip")]
+#[cfg(feature = "server")]
+mod geoip;
+
+#[cfg(feature = "server")]
+mod db_sqlite;
+
+#[cfg(feature = "database_postgres")]
+#[cfg(feature = "server")]
+mod db_postgres;
+
+#[cfg(feature = "database_postgres")]
+#[cfg(feature = "server")]
+#[cfg(test)]
+mod test_postgres;
+
 #[cfg(feature = "backend_next")]
+#[cfg(feature = "server")]
 mod forwarder;
-#[cfg(feature = "backend_next")]
-pub use forwarder::*;
 
-pub use super::SaveBroInfoRequest;
+#[allow(unused_imports)]
+pub use super::SaveMetrics;
 #[cfg(feature = "backend_user_agent")]
 pub use super::SaveUserAgentRequest;
+#[allow(unused_imports)]
+pub use super::{normalize_event_name, CollectionPolicy, ConsentLevel, FieldAction};
+#[allow(unused_imports)]
+pub use super::{EraseReport, EraseSubject, EventRequest, LogEntry, LogPage, LogQuery, LogStats};
+#[allow(unused_imports)]
+pub use super::{FingerprintBicmid, FingerprintGroup, FingerprintQuery, PageViewRequest};
+pub use super::{SaveBroInfoRequest, SaveBroInfoResponse};
 
 #[cfg(feature = "server")]
 pub fn backend_init() -> anyhow::Result<()> {
-    config::BackendConfig::init()
+    config::BackendConfig::init()?;
+    config::BackendConfig::global().collection.policy()?;
+    store::init_store()?;
+    retention::start_retention_task()
+}
+
+/// Adds the middlewares of the backend to `router`, ex.) `dioxus::server::router(App)`:
+/// the limit of the raw body size of the save requests, `limits.max_payload_bytes`,
+/// and the capture of the raw body whose signature `[api]` verifies.
+#[cfg(feature = "server")]
+pub fn backend_router(router: dioxus::fullstack::axum::Router) -> dioxus::fullstack::axum::Router {
+    use dioxus::fullstack::axum::middleware::from_fn;
+    // the last layer runs first
+    router
+        .layer(from_fn(auth::capture_body))
+        .layer(from_fn(limits::limit_payload))
+}
+
+/// Prunes the stored visits once with the `[retention]` policy, regardless of its interval.
+#[cfg(feature = "server")]
+pub async fn backend_prune() -> anyhow::Result<()> {
+    retention::prune_once().await?;
+    Ok(())
+}
+
+/// Parses the stored user agents into the structured columns of `user_agents` again,
+/// e.g. after `browserinfo` improves its parser, and returns their number.
+/// Only the ones not parsed yet, i.e. stored before the columns, unless `all`.
+#[cfg(feature = "server")]
+pub async fn backend_reparse_user_agents(all: bool) -> anyhow::Result<u64> {
+    store::store().reparse_user_agents(all).await
+}
+
+/// Resolves the GeoIP of the stored IP addresses not resolved yet with the `[geoip]` databases,
+/// and returns their number.
+#[cfg(feature = "geoip")]
+#[cfg(feature = "server")]
+pub async fn backend_geoip_backfill() -> anyhow::Result<u64> {
+    store::store().backfill_geoip().await
+}
+
+/// Writes out the visits buffered by the write queue.
+///
+/// Call it when the server shuts down, if `write_queue.enabled` is set.
+#[cfg(feature = "server")]
+pub async fn backend_shutdown() -> anyhow::Result<()> {
+    store::shutdown_store().await
+}
+
+/// Reverts the database schema of the configured backend down to the migration `target` version.
+#[cfg(feature = "server")]
+pub async fn backend_migrate_undo(target: i64) -> anyhow::Result<()> {
+    #[cfg(feature = "database_postgres")]
+    {
+        let cfg = config::BackendConfig::global();
+        if cfg.backend.kind == config::BackendKind::Postgres {
+            return db_postgres::migrate_undo(cfg, target).await;
+        }
+    }
+    db_sqlite::migrate_undo(target).await
 }
 
-/// Extracts the client's IP address from HTTP headers.
+/// Extracts the client's IP address of a request from the socket `peer`.
 ///
-/// Specifically looks for the `x-forwarded-for` header to handle requests behind a proxy.
+/// The `Forwarded`, `X-Forwarded-For` and `X-Real-IP` headers are believed only as far as
+/// they were appended by `proxy.trusted_proxies`. `peer` is known if the server is served
+/// with `into_make_service_with_connect_info::<SocketAddr>()`; otherwise it is assumed to be
+/// a trusted proxy. Returns an empty string if no address is found.
 #[allow(unused)]
 #[cfg(feature = "server")]
-pub fn get_ip_address_string(headers: &dioxus::fullstack::HeaderMap) -> String {
-    use std::net::IpAddr;
-    headers
-        .get("x-forwarded-for")
-        .and_then(|v| v.to_str().ok())
-        .and_then(|s| s.split(',').next())
-        .map(|s| s.trim())
-        .filter(|s| s.parse::<IpAddr>().is_ok())
-        .unwrap_or("")
-        .to_string()
+pub fn get_ip_address_string(
+    headers: &dioxus::fullstack::HeaderMap,
+    peer: Option<std::net::SocketAddr>,
+) -> String {
+    let cfg = config::BackendConfig::global();
+    client_ip::client_ip(headers, peer, &cfg.proxy.trusted_proxies)
+        .map(|ip| ip.to_string())
+        .unwrap_or_default()
+}
+
+/// Returns the socket peer address in the request `extensions`, if the server is served
+/// with the connect info.
+#[cfg(feature = "server")]
+fn peer_addr(extensions: &dioxus::fullstack::http::Extensions) -> Option<std::net::SocketAddr> {
+    use dioxus::fullstack::extract::ConnectInfo;
+    extensions
+        .get::<ConnectInfo<std::net::SocketAddr>>()
+        .map(|ci| ci.0)
+}
+
+/// Returns the error of a request rejected with the 4xx `status`.
+///
+/// The client sees the status, so that it does not retry a request that fails the same way again.
+#[cfg(feature = "server")]
+fn rejected(
+    status: dioxus::fullstack::StatusCode,
+    message: impl std::fmt::Display,
+) -> anyhow::Error {
+    anyhow::Error::new(dioxus::fullstack::HttpError::new(
+        status,
+        message.to_string(),
+    ))
+}
+
+/// Returns the response of a request rejected by a middleware with the 4xx `status`,
+/// with the error payload of a server function.
+#[cfg(feature = "server")]
+fn rejected_response(
+    status: dioxus::fullstack::StatusCode,
+    message: impl std::fmt::Display,
+) -> dioxus::fullstack::response::Response {
+    use dioxus::fullstack::response::IntoResponse;
+    let body = serde_json::json!({"message": message.to_string(), "code": status.as_u16()});
+    (status, dioxus::fullstack::Json(body)).into_response()
 }
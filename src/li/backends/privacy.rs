@@ -0,0 +1,107 @@
+//! Anonymization of the client's IP address before it is stored.
+//! The mode is recorded in `logs.ip_address_mode`, so that the stored addresses of mixed modes
+//! stay interpretable.
+
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+
+use super::config::{IpAddressMode, PrivacyConfig};
+
+/// The anonymization of the client's IP address.
+#[derive(Debug, Clone, Default)]
+pub struct IpAddressPrivacy {
+    mode: IpAddressMode,
+    secret: String,
+}
+
+impl IpAddressPrivacy {
+    pub fn new(mode: IpAddressMode, secret: &str) -> Result<Self> {
+        if mode == IpAddressMode::Hash && secret.is_empty() {
+            bail!("`privacy.ip_address_secret` must be set for `ip_address_mode = \"hash\"`");
+        }
+        Ok(Self {
+            mode,
+            secret: secret.to_string(),
+        })
+    }
+
+    pub fn from_config(cfg: &PrivacyConfig) -> Result<Self> {
+        Self::new(cfg.ip_address_mode, &cfg.ip_address_secret)
+    }
+
+    /// Returns the mode recorded with the stored address.
+    pub fn mode(&self) -> IpAddressMode {
+        self.mode
+    }
+
+    /// Returns the address to store. An empty or invalid address is stored as empty.
+    pub fn apply(&self, ip_address: &str) -> String {
+        let Ok(ip) = ip_address.parse::<IpAddr>() else {
+            return String::new();
+        };
+        match self.mode {
+            IpAddressMode::Full => ip.to_string(),
+            IpAddressMode::Truncate => truncate_ip(ip).to_string(),
+            IpAddressMode::Hash => hash_ip(ip, &self.secret),
+            IpAddressMode::Drop => String::new(),
+        }
+    }
+}
+
+/// Clears the host part of `ip`: IPv4 /24, IPv6 /48.
+fn truncate_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            IpAddr::from([a, b, c, 0])
+        }
+        IpAddr::V6(v6) => {
+            let mut octets = v6.octets();
+            octets[6..].fill(0);
+            IpAddr::from(octets)
+        }
+    }
+}
+
+/// Creates the HMAC-SHA256 of `ip` keyed with `secret`, and encodes it in Base64.
+fn hash_ip(ip: IpAddr, secret: &str) -> String {
+    use base64::Engine;
+
+    let mac = hmac_sha256::HMAC::mac(ip.to_string().as_bytes(), secret.as_bytes());
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(mac)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_truncate_00() {
+        let p = IpAddressPrivacy::new(IpAddressMode::Truncate, "").unwrap();
+        assert_eq!(p.apply("192.0.2.123"), "192.0.2.0");
+        assert_eq!(p.apply("2001:db8:1234:5678::1"), "2001:db8:1234::");
+        assert_eq!(p.apply(""), "");
+        assert_eq!(p.apply("not an address"), "");
+    }
+
+    #[test]
+    fn test_hash_01() {
+        assert!(IpAddressPrivacy::new(IpAddressMode::Hash, "").is_err());
+        let p = IpAddressPrivacy::new(IpAddressMode::Hash, "secret").unwrap();
+        let h = p.apply("192.0.2.1");
+        assert_eq!(h.len(), 43);
+        assert_eq!(h, p.apply("192.0.2.1"));
+        assert_ne!(h, p.apply("192.0.2.2"));
+        let p2 = IpAddressPrivacy::new(IpAddressMode::Hash, "another").unwrap();
+        assert_ne!(h, p2.apply("192.0.2.1"));
+    }
+
+    #[test]
+    fn test_full_and_drop_02() {
+        let p = IpAddressPrivacy::default();
+        assert_eq!(p.mode(), IpAddressMode::Full);
+        assert_eq!(p.apply("2001:db8::1"), "2001:db8::1");
+        let p = IpAddressPrivacy::new(IpAddressMode::Drop, "").unwrap();
+        assert_eq!(p.apply("192.0.2.1"), "");
+    }
+}
@@ -0,0 +1,105 @@
+//! Retention of the stored visits.
+//! Periodically deletes the visits, the page views and the events beyond
+//! `retention.max_age_days` or `retention.max_rows`, then the lookup values no longer referenced by any of them,
+//! and shrinks the database.
+
+use anyhow::Result;
+use std::time::Duration;
+
+use super::config::BackendConfig;
+use super::store::store;
+
+/// The numbers of rows deleted by a pruning.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PruneReport {
+    /// The deleted `logs` rows.
+    pub logs: u64,
+    /// The deleted `page_views` rows.
+    pub page_views: u64,
+    /// The deleted `events` rows.
+    pub events: u64,
+    /// The deleted rows of the lookup tables.
+    pub lookups: u64,
+}
+
+/// Deletes the rows of the lookup table `$tbl` that no `$from.$col` refers to.
+/// The special rows of ID 0 are kept.
+macro_rules! delete_unreferenced {
+    ($tbl: expr, $col: expr, $($from: expr),+) => {
+        concat!(
+            r#"DELETE FROM "#,
+            $tbl,
+            r#" WHERE id <> 0"#,
+            $(
+                r#" AND NOT EXISTS (SELECT 1 FROM "#,
+                $from,
+                r#" WHERE "#,
+                $from,
+                r#"."#,
+                $col,
+                r#" = "#,
+                $tbl,
+                r#".id)"#,
+            )+
+        )
+    };
+}
+
+/// The garbage collection of the lookup tables, shared by the database backends.
+///
+/// With the `backend_user_agent` feature, the `user_agents` rows are kept, since
+/// `save_user_agent()` stores them without any `logs` row referring to them.
+pub const DELETE_UNREFERENCED: &[&str] = &[
+    delete_unreferenced!("jsinfos", "jsinfo_id", "logs"),
+    #[cfg(not(feature = "backend_user_agent"))]
+    delete_unreferenced!("user_agents", "user_agent_id", "logs"),
+    delete_unreferenced!("referrers", "referrer_id", "logs", "page_views"),
+    delete_unreferenced!("ip_addresses", "ip_address_id", "logs"),
+    delete_unreferenced!("bicmids", "bicmid_id", "logs", "page_views", "events"),
+    delete_unreferenced!("users", "user_id", "logs", "page_views", "events"),
+    delete_unreferenced!("fingerprints", "fingerprint_id", "logs"),
+    delete_unreferenced!("sessions", "session_id", "logs", "page_views", "events"),
+    delete_unreferenced!("paths", "path_id", "page_views"),
+    delete_unreferenced!("event_names", "name_id", "events"),
+];
+
+/// Prunes the global store once, with the configured policy.
+pub async fn prune_once() -> Result<PruneReport> {
+    let cfg = BackendConfig::global();
+    let report = store().prune(&cfg.retention).await?;
+    dioxus::logger::tracing::info!(
+        "retention: deleted {} visits, {} page views, {} events and {} lookup values",
+        report.logs,
+        report.page_views,
+        report.events,
+        report.lookups
+    );
+    Ok(report)
+}
+
+/// Starts the periodic pruning, if any limit is configured.
+///
+/// It runs on its own thread, so that it does not depend on the server's runtime.
+pub fn start_retention_task() -> Result<()> {
+    let cfg = BackendConfig::global();
+    if !cfg.retention.is_enabled() {
+        return Ok(());
+    }
+    let interval = Duration::from_secs(cfg.retention.interval_secs.max(1));
+    let rt = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    std::thread::Builder::new()
+        .name("browserinfocm-retention".to_string())
+        .spawn(move || {
+            rt.block_on(async move {
+                loop {
+                    if let Err(e) = prune_once().await {
+                        dioxus::logger::tracing::error!("retention: {e:#}");
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            })
+        })?;
+    Ok(())
+}
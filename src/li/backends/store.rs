@@ -0,0 +1,272 @@
+//! Storage backend abstraction.
+//! The server functions save and query the browser information through a `BroInfoStore`,
+//! which is selected at runtime from `BackendConfig`.
+
+use anyhow::Result;
+use async_trait::async_trait;
+use dioxus::fullstack::Lazy;
+use std::sync::Arc;
+
+use super::config::{BackendConfig, BackendKind, RetentionConfig};
+use super::retention::PruneReport;
+use super::write_queue::QueuedStore;
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats, SaveBroInfoRequest};
+use super::{EventRequest, FingerprintGroup, FingerprintQuery, PageViewRequest};
+
+#[cfg(feature = "backend_user_agent")]
+use super::SaveUserAgentRequest;
+
+/// A storage backend of the browser information.
+///
+/// A store is created by `init_store()` on a runtime of its own, which ends right after it,
+/// not on the runtime that serves the requests. So creating a store must not leave anything
+/// bound to the runtime that creates it: it must not spawn a task, and must not keep a
+/// connection that is driven by that runtime. The connections are opened lazily on first use,
+/// as `PostgresStore` does, or run on threads of their own, as the ones of SQLite do,
+/// and a background task is spawned by the first request, as `QueuedStore` does.
+#[async_trait]
+pub trait BroInfoStore: Send + Sync {
+    /// Returns the name of the backend, e.g. `"sqlite"`.
+    fn kind(&self) -> &'static str;
+
+    /// Returns the path or location of the database.
+    async fn db_path(&self) -> Result<String>;
+
+    /// Checks that the backend is able to serve requests.
+    async fn health(&self) -> Result<()>;
+
+    /// Saves full browser information of one visit from `ip_address`.
+    async fn save_broinfo(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<()>;
+
+    /// Saves the visits of `batch` together, in one transaction if the backend has them.
+    ///
+    /// The default saves them one by one.
+    async fn save_broinfo_batch(&self, batch: &[SaveBroInfoItem]) -> Result<()> {
+        for item in batch {
+            self.save_broinfo(&item.req, &item.ip_address).await?;
+        }
+        Ok(())
+    }
+
+    /// Saves the user agent only.
+    #[cfg(feature = "backend_user_agent")]
+    async fn save_user_agent(&self, req: &SaveUserAgentRequest) -> Result<()>;
+
+    /// Saves one page view, counting it in the session of the BICMID.
+    async fn save_pageview(&self, req: &PageViewRequest) -> Result<()>;
+
+    /// Saves one custom event, whose name is already normalized.
+    async fn save_event(&self, req: &EventRequest) -> Result<()>;
+
+    /// Queries the stored visits, newest first.
+    async fn query_logs(&self, query: &LogQuery) -> Result<LogPage>;
+
+    /// Counts the stored visits by browser and by operating system.
+    async fn query_log_stats(&self, query: &LogQuery) -> Result<LogStats>;
+
+    /// Deletes the visits of `subject`, its `bicmids` or `users` row,
+    /// and the lookup values no longer referenced.
+    async fn erase_subject(&self, subject: &EraseSubject) -> Result<EraseReport>;
+
+    /// Lists the fingerprints matching `query` and the BICMIDs that share them.
+    async fn query_fingerprints(&self, query: &FingerprintQuery) -> Result<Vec<FingerprintGroup>>;
+
+    /// Deletes the visits beyond the limits of `policy`, then the lookup values
+    /// no longer referenced, and shrinks the database.
+    ///
+    /// The default deletes nothing.
+    async fn prune(&self, policy: &RetentionConfig) -> Result<PruneReport> {
+        let _ = policy;
+        Ok(PruneReport::default())
+    }
+
+    /// Parses the stored user agents into the structured columns of `user_agents`,
+    /// and returns their number. Only the ones not parsed yet unless `all`.
+    ///
+    /// The default parses nothing.
+    async fn reparse_user_agents(&self, all: bool) -> Result<u64> {
+        let _ = all;
+        Ok(0)
+    }
+
+    /// Resolves the GeoIP of the stored IP addresses not resolved yet, and returns their number.
+    ///
+    /// The default resolves nothing.
+    #[cfg(feature = "geoip")]
+    async fn backfill_geoip(&self) -> Result<u64> {
+        Ok(0)
+    }
+
+    /// Writes out everything buffered. Called once when the server shuts down.
+    async fn shutdown(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// One visit to be saved by `BroInfoStore::save_broinfo_batch()`.
+#[derive(Debug, Clone)]
+pub struct SaveBroInfoItem {
+    pub req: SaveBroInfoRequest,
+    pub ip_address: String,
+}
+
+/// What a save counts in its session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionHit {
+    /// A visit, counted in `hits`.
+    Visit,
+    /// A page view, counted in `page_views`.
+    PageView,
+    /// A custom event, only extending the session.
+    Event,
+}
+
+impl SessionHit {
+    /// Returns the increments of `hits` and `page_views`.
+    pub fn increments(&self) -> (i64, i64) {
+        match self {
+            Self::Visit => (1, 0),
+            Self::PageView => (0, 1),
+            Self::Event => (0, 0),
+        }
+    }
+}
+
+/// The values of one visit as they are stored: anonymized, fingerprinted and serialized.
+#[derive(Debug, Clone)]
+pub struct VisitValues {
+    /// The client's IP address, already anonymized as `ip_address_mode` says.
+    pub ip_address: String,
+    pub ip_address_mode: &'static str,
+    /// The browser fingerprint, empty if it is not enabled.
+    pub fingerprint: String,
+    /// The JSON of the JS information.
+    pub jsinfo: String,
+    /// The name of the consent, empty from a client without it.
+    pub consent: &'static str,
+    /// The bot score of the visit.
+    pub bot_score: i64,
+}
+
+/// The global store, set by `init_store()`, or else created on first use from `BackendConfig`.
+static STORE: Lazy<Arc<dyn BroInfoStore>> = Lazy::new(|| async move {
+    let store = create_store().await?;
+    dioxus::Ok(store)
+});
+
+/// Creates the store of the configured kind.
+async fn create_store() -> Result<Arc<dyn BroInfoStore>> {
+    let cfg = BackendConfig::global();
+    let store: Arc<dyn BroInfoStore> = match cfg.backend.kind {
+        BackendKind::Sqlite => Arc::new(super::db_sqlite::SqliteStore::open().await?),
+        #[cfg(feature = "database_postgres")]
+        BackendKind::Postgres => Arc::new(super::db_postgres::PostgresStore::open(cfg).await?),
+        #[cfg(not(feature = "database_postgres"))]
+        BackendKind::Postgres => {
+            anyhow::bail!("the `postgres` backend requires the `database_postgres` feature")
+        }
+        #[cfg(feature = "backend_next")]
+        BackendKind::Forwarder => Arc::new(super::forwarder::ForwarderStore::new(cfg)?),
+        #[cfg(not(feature = "backend_next"))]
+        BackendKind::Forwarder => {
+            anyhow::bail!("the `forwarder` backend requires the `backend_next` feature")
+        }
+    };
+    dioxus::logger::tracing::info!("backend store: {}", store.kind());
+    if cfg.write_queue.enabled {
+        dioxus::logger::tracing::info!("write queue: {:?}", cfg.write_queue);
+        return Ok(Arc::new(QueuedStore::new(store, &cfg.write_queue)));
+    }
+    Ok(store)
+}
+
+/// Opens the configured store at startup, so that a misconfiguration, or a database
+/// newer than this binary, stops the server before it serves requests.
+pub fn init_store() -> Result<()> {
+    let store = block_on_init(create_store())
+        .map_err(|e| anyhow::anyhow!("Failed to open the backend store: {e:#}"))?;
+    STORE
+        .set(store)
+        .map_err(|e| anyhow::anyhow!("Failed to set the backend store: {e}"))
+}
+
+/// Runs `fut` to completion on a runtime of its own, in a thread of its own,
+/// so that it can be called inside or outside of a tokio runtime.
+/// The runtime ends with `fut`, see `BroInfoStore` for what it requires of the stores.
+///
+/// Unlike `Lazy::initialize()`, which panics, the error of `fut` is returned.
+fn block_on_init<T, F>(fut: F) -> Result<T>
+where
+    T: Send + 'static,
+    F: std::future::Future<Output = Result<T>> + Send + 'static,
+{
+    std::thread::spawn(move || {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(fut)
+    })
+    .join()
+    .map_err(|_| anyhow::anyhow!("the initialization thread panicked"))?
+}
+
+/// Returns the global store.
+pub fn store() -> &'static dyn BroInfoStore {
+    &**STORE
+}
+
+/// Writes out everything buffered by the global store.
+pub async fn shutdown_store() -> Result<()> {
+    store().shutdown().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::db_sqlite::SqliteStore;
+    use super::super::migrate::{latest_version, run_migrations, SQLITE_MIGRATOR};
+    use super::*;
+
+    #[test]
+    fn test_block_on_init_00() {
+        let r = block_on_init(async { Ok(1) });
+        assert_eq!(r.unwrap(), 1);
+        let r: Result<()> = block_on_init(async { anyhow::bail!("broken") });
+        assert_eq!(r.unwrap_err().to_string(), "broken");
+    }
+
+    #[test]
+    fn test_block_on_init_newer_database_01() {
+        let db_path = std::env::temp_dir().join(format!(
+            "browserinfocm-test-{}-init.sqlite3",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&db_path);
+        //
+        // the database is migrated by a newer binary
+        let path = db_path.clone();
+        block_on_init(async move {
+            let pool = sqlx::sqlite::SqlitePoolOptions::new()
+                .connect(&format!("sqlite://{}?mode=rwc", path.display()))
+                .await?;
+            run_migrations(&SQLITE_MIGRATOR, &pool).await?;
+            sqlx::query(concat!(
+                r#"INSERT INTO _sqlx_migrations"#,
+                r#" (version, description, success, checksum, execution_time)"#,
+                r#" VALUES (?, 'from the future', TRUE, X'00', 0)"#
+            ))
+            .bind(latest_version(&SQLITE_MIGRATOR) + 1)
+            .execute(&pool)
+            .await?;
+            pool.close().await;
+            Ok(())
+        })
+        .unwrap();
+        //
+        // opening it fails instead of panicking
+        let path = db_path.clone();
+        let r = block_on_init(async move { SqliteStore::open_file(&path).await });
+        let msg = r.err().unwrap().to_string();
+        assert!(msg.contains("refusing to start"), "{msg}");
+        let _ = std::fs::remove_file(&db_path);
+    }
+}
@@ -0,0 +1,195 @@
+//! A throwaway PostgreSQL server for the tests of the `database_postgres` feature.
+//! Uses `BROWSERINFOCM_TEST_PG_URL` if it is set, otherwise starts a private cluster
+//! with `initdb` and `pg_ctl` in a temporary directory, which is removed on drop.
+
+use anyhow::{bail, Result};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// A running PostgreSQL server.
+pub struct PgCluster {
+    /// The URL of the maintenance database.
+    url: String,
+    /// The private cluster, stopped on drop. `None` for `BROWSERINFOCM_TEST_PG_URL`.
+    _local: Option<LocalCluster>,
+}
+
+struct LocalCluster {
+    dir: PathBuf,
+    bin_dir: PathBuf,
+    /// `initdb` refuses to run as root, so the cluster is run as the `postgres` user then.
+    run_as: Option<String>,
+}
+
+impl PgCluster {
+    /// Starts the server, or returns `None` if PostgreSQL is not available.
+    pub fn start() -> Option<Self> {
+        if let Ok(url) = std::env::var("BROWSERINFOCM_TEST_PG_URL") {
+            return Some(Self { url, _local: None });
+        }
+        let bin_dir = find_bin_dir()?;
+        match LocalCluster::start(bin_dir) {
+            Ok((local, port)) => Some(Self {
+                url: format!("postgres://postgres@127.0.0.1:{port}/postgres"),
+                _local: Some(local),
+            }),
+            Err(e) => {
+                eprintln!("failed to start PostgreSQL: {e:#}");
+                None
+            }
+        }
+    }
+
+    /// Creates a new empty database, and returns its URL.
+    pub async fn create_database(&self) -> Result<String> {
+        use sqlx::Connection;
+        //
+        let name = format!(
+            "browserinfocm_test_{}_{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        );
+        let mut conn = sqlx::PgConnection::connect(&self.url).await?;
+        sqlx::query(&format!("CREATE DATABASE {name}"))
+            .execute(&mut conn)
+            .await?;
+        conn.close().await?;
+        //
+        let base = self
+            .url
+            .rsplit_once('/')
+            .map(|(b, _)| b)
+            .unwrap_or(&self.url);
+        Ok(format!("{base}/{name}"))
+    }
+}
+
+impl LocalCluster {
+    fn start(bin_dir: PathBuf) -> Result<(Self, u16)> {
+        let dir = std::env::temp_dir().join(format!(
+            "browserinfocm-pg-{}-{}",
+            std::process::id(),
+            SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&dir)?;
+        let run_as = if is_root() {
+            let status = Command::new("chown").arg("postgres").arg(&dir).status()?;
+            if !status.success() {
+                bail!("chown postgres {}: {status}", dir.display());
+            }
+            Some("postgres".to_string())
+        } else {
+            None
+        };
+        let cluster = Self {
+            dir,
+            bin_dir,
+            run_as,
+        };
+        let port = free_port()?;
+        let data = cluster.dir.join("data");
+        cluster.run(
+            "initdb",
+            &[
+                "-D".as_ref(),
+                data.as_os_str(),
+                "-U".as_ref(),
+                "postgres".as_ref(),
+                "--auth=trust".as_ref(),
+                "--encoding=UTF8".as_ref(),
+                "--no-sync".as_ref(),
+            ],
+        )?;
+        let opts = format!(
+            "-p {port} -k {} -c listen_addresses=127.0.0.1 -c fsync=off",
+            cluster.dir.display()
+        );
+        cluster.run(
+            "pg_ctl",
+            &[
+                "-D".as_ref(),
+                data.as_os_str(),
+                "-l".as_ref(),
+                cluster.dir.join("postgres.log").as_os_str(),
+                "-o".as_ref(),
+                opts.as_ref(),
+                "-w".as_ref(),
+                "start".as_ref(),
+            ],
+        )?;
+        Ok((cluster, port))
+    }
+
+    fn run(&self, prog: &str, args: &[&std::ffi::OsStr]) -> Result<()> {
+        let prog = self.bin_dir.join(prog);
+        let mut cmd = match &self.run_as {
+            Some(user) => {
+                let mut cmd = Command::new("runuser");
+                cmd.args(["-u", user, "--"]).arg(&prog);
+                cmd
+            }
+            None => Command::new(&prog),
+        };
+        let status = cmd
+            .args(args)
+            .current_dir(&self.dir)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()?;
+        if !status.success() {
+            bail!("{}: {status}", prog.display());
+        }
+        Ok(())
+    }
+}
+
+impl Drop for LocalCluster {
+    fn drop(&mut self) {
+        let data = self.dir.join("data");
+        let _ = self.run(
+            "pg_ctl",
+            &[
+                "-D".as_ref(),
+                data.as_os_str(),
+                "-m".as_ref(),
+                "immediate".as_ref(),
+                "-w".as_ref(),
+                "stop".as_ref(),
+            ],
+        );
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Finds the directory of `initdb` and `pg_ctl`, in `PATH` or the Debian location.
+fn find_bin_dir() -> Option<PathBuf> {
+    let has_initdb = |d: &Path| d.join("initdb").is_file() && d.join("pg_ctl").is_file();
+    let path = std::env::var_os("PATH").unwrap_or_default();
+    if let Some(d) = std::env::split_paths(&path).find(|d| has_initdb(d)) {
+        return Some(d);
+    }
+    let mut dirs: Vec<PathBuf> = std::fs::read_dir("/usr/lib/postgresql")
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path().join("bin"))
+        .filter(|d| has_initdb(d))
+        .collect();
+    dirs.sort();
+    dirs.pop()
+}
+
+fn is_root() -> bool {
+    Command::new("id")
+        .arg("-u")
+        .output()
+        .map(|o| o.stdout.trim_ascii() == b"0")
+        .unwrap_or(false)
+}
+
+fn free_port() -> Result<u16> {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
@@ -0,0 +1,105 @@
+//! Parsing of the stored user agents into the structured columns of `user_agents`.
+//! The browser, OS and device model come from `browserinfo`; the device class is guessed here.
+
+use browserinfo::BroInfo;
+
+/// The parsed user agent, as stored in `user_agents`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct ParsedUserAgent {
+    pub browser_name: String,
+    pub browser_version: String,
+    pub os_name: String,
+    pub os_version: String,
+    pub device_model: String,
+    pub device_class: DeviceClass,
+}
+
+/// The class of the device of a user agent.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceClass {
+    Desktop,
+    Mobile,
+    Tablet,
+    /// A crawler or another automated client.
+    Bot,
+    #[default]
+    Unknown,
+}
+
+impl DeviceClass {
+    /// Returns the name stored in `user_agents.device_class`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Desktop => "desktop",
+            Self::Mobile => "mobile",
+            Self::Tablet => "tablet",
+            Self::Bot => "bot",
+            Self::Unknown => "unknown",
+        }
+    }
+}
+
+impl ParsedUserAgent {
+    /// Parses `ua`. A user agent that can not be parsed gives empty names.
+    pub fn parse(ua: &str) -> Self {
+        let mut broinfo = BroInfo::default();
+        broinfo.basic.user_agent = ua.into();
+        let browser = broinfo.to_browser().unwrap_or_default();
+        let os = browser.os.unwrap_or_default();
+        let device_class = device_class(ua, &os.name, &browser.device);
+        Self {
+            browser_name: browser.name,
+            browser_version: browser.version,
+            os_name: os.name,
+            os_version: os.version,
+            device_model: browser.device,
+            device_class,
+        }
+    }
+}
+
+fn device_class(ua: &str, os_name: &str, device_model: &str) -> DeviceClass {
+    let ua = ua.to_ascii_lowercase();
+    let has = |pats: &[&str]| pats.iter().any(|p| ua.contains(p));
+    if device_model == "Spider" || has(&["bot", "crawl", "spider", "slurp", "headless"]) {
+        DeviceClass::Bot
+    } else if has(&["ipad", "tablet"]) || (ua.contains("android") && !ua.contains("mobile")) {
+        DeviceClass::Tablet
+    } else if has(&["mobi", "iphone", "ipod", "android", "windows phone"]) {
+        DeviceClass::Mobile
+    } else if !os_name.is_empty() && os_name != "Other" {
+        DeviceClass::Desktop
+    } else {
+        DeviceClass::Unknown
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_00() {
+        let ua = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let p = ParsedUserAgent::parse(ua);
+        assert_eq!(p.browser_name, "Firefox");
+        assert_eq!(p.browser_version, "128.0");
+        assert_eq!(p.os_name, "Linux");
+        assert_eq!(p.device_class, DeviceClass::Desktop);
+        //
+        let ua = concat!(
+            "Mozilla/5.0 (iPhone; CPU iPhone OS 17_5 like Mac OS X) AppleWebKit/605.1.15",
+            " (KHTML, like Gecko) Version/17.5 Mobile/15E148 Safari/604.1"
+        );
+        let p = ParsedUserAgent::parse(ua);
+        assert_eq!(p.os_name, "iOS");
+        assert_eq!(p.device_class, DeviceClass::Mobile);
+        //
+        let ua = "Mozilla/5.0 (compatible; Googlebot/2.1; +http://www.google.com/bot.html)";
+        assert_eq!(ParsedUserAgent::parse(ua).device_class, DeviceClass::Bot);
+        assert_eq!(
+            ParsedUserAgent::parse("").device_class,
+            DeviceClass::Unknown
+        );
+    }
+}
@@ -0,0 +1,395 @@
+//! Optional in-process write queue of `save_broinfo`.
+//! A bounded channel feeds a background task, which saves the queued visits in one transaction
+//! every `write_queue.max_delay_ms` or `write_queue.max_batch` visits, whichever comes first.
+
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+
+use super::config::{RetentionConfig, WriteQueueConfig};
+use super::retention::PruneReport;
+use super::store::{BroInfoStore, SaveBroInfoItem};
+use super::{EraseReport, EraseSubject, LogPage, LogQuery, LogStats, SaveBroInfoRequest};
+use super::{EventRequest, FingerprintGroup, FingerprintQuery, PageViewRequest};
+
+#[cfg(feature = "backend_user_agent")]
+use super::SaveUserAgentRequest;
+
+enum QueueMsg {
+    /// A visit to save, and where to report its result.
+    Save(Box<SaveBroInfoItem>, oneshot::Sender<Result<()>>),
+    /// Saves everything queued.
+    Flush(oneshot::Sender<()>),
+    /// Saves everything queued, then stops the task.
+    Shutdown(oneshot::Sender<()>),
+}
+
+/// The senders waiting for the queued visits to be saved.
+#[derive(Default)]
+struct Acks {
+    flushes: Vec<oneshot::Sender<()>>,
+    shutdowns: Vec<oneshot::Sender<()>>,
+}
+
+impl Acks {
+    fn is_empty(&self) -> bool {
+        self.flushes.is_empty() && self.shutdowns.is_empty()
+    }
+}
+
+/// The store that queues `save_broinfo` in front of another store.
+///
+/// A save returns after its batch is committed, so that its errors are still reported.
+/// It waits while the queue is full.
+pub struct QueuedStore {
+    inner: Arc<dyn BroInfoStore>,
+    sender: mpsc::Sender<QueueMsg>,
+    /// Taken by the background task, which is spawned by the first save,
+    /// so that it runs on the server's runtime.
+    receiver: Mutex<Option<mpsc::Receiver<QueueMsg>>>,
+    max_batch: usize,
+    max_delay: Duration,
+    /// Set by `shutdown()`; the later saves go to the inner store directly.
+    closed: AtomicBool,
+}
+
+impl QueuedStore {
+    pub fn new(inner: Arc<dyn BroInfoStore>, cfg: &WriteQueueConfig) -> Self {
+        let (sender, receiver) = mpsc::channel(cfg.capacity.max(1));
+        Self {
+            inner,
+            sender,
+            receiver: Mutex::new(Some(receiver)),
+            max_batch: cfg.max_batch.max(1),
+            max_delay: Duration::from_millis(cfg.max_delay_ms),
+            closed: AtomicBool::new(false),
+        }
+    }
+
+    /// Spawns the background task, if it is not yet.
+    fn ensure_worker(&self) {
+        let receiver = self.receiver.lock().unwrap().take();
+        if let Some(receiver) = receiver {
+            let inner = self.inner.clone();
+            tokio::spawn(run_worker(inner, receiver, self.max_batch, self.max_delay));
+        }
+    }
+
+    /// Waits until the visits queued so far are saved.
+    async fn flush(&self) {
+        // if the task was never spawned, nothing is queued
+        let spawned = self.receiver.lock().unwrap().is_none();
+        if spawned {
+            let (ack, done) = oneshot::channel();
+            if self.sender.send(QueueMsg::Flush(ack)).await.is_ok() {
+                let _ = done.await;
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl BroInfoStore for QueuedStore {
+    fn kind(&self) -> &'static str {
+        self.inner.kind()
+    }
+
+    async fn db_path(&self) -> Result<String> {
+        self.inner.db_path().await
+    }
+
+    async fn health(&self) -> Result<()> {
+        self.inner.health().await
+    }
+
+    async fn save_broinfo(&self, req: &SaveBroInfoRequest, ip_address: &str) -> Result<()> {
+        if self.closed.load(Ordering::Acquire) {
+            return self.inner.save_broinfo(req, ip_address).await;
+        }
+        self.ensure_worker();
+        let item = Box::new(SaveBroInfoItem {
+            req: req.clone(),
+            ip_address: ip_address.to_string(),
+        });
+        let (reply, result) = oneshot::channel();
+        if let Err(mpsc::error::SendError(QueueMsg::Save(item, _))) =
+            self.sender.send(QueueMsg::Save(item, reply)).await
+        {
+            // the task has stopped after `shutdown()`
+            return self.inner.save_broinfo(&item.req, &item.ip_address).await;
+        }
+        result
+            .await
+            .map_err(|_| anyhow!("the write queue stopped before saving the visit"))?
+    }
+
+    async fn save_broinfo_batch(&self, batch: &[SaveBroInfoItem]) -> Result<()> {
+        self.inner.save_broinfo_batch(batch).await
+    }
+
+    #[cfg(feature = "backend_user_agent")]
+    async fn save_user_agent(&self, req: &SaveUserAgentRequest) -> Result<()> {
+        self.inner.save_user_agent(req).await
+    }
+
+    async fn save_pageview(&self, req: &PageViewRequest) -> Result<()> {
+        self.inner.save_pageview(req).await
+    }
+
+    async fn save_event(&self, req: &EventRequest) -> Result<()> {
+        self.inner.save_event(req).await
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> Result<LogPage> {
+        self.inner.query_logs(query).await
+    }
+
+    async fn query_log_stats(&self, query: &LogQuery) -> Result<LogStats> {
+        self.inner.query_log_stats(query).await
+    }
+
+    /// Saves the queued visits first, so that none of the subject is saved after the erasure.
+    async fn erase_subject(&self, subject: &EraseSubject) -> Result<EraseReport> {
+        self.flush().await;
+        self.inner.erase_subject(subject).await
+    }
+
+    async fn query_fingerprints(&self, query: &FingerprintQuery) -> Result<Vec<FingerprintGroup>> {
+        self.inner.query_fingerprints(query).await
+    }
+
+    async fn prune(&self, policy: &RetentionConfig) -> Result<PruneReport> {
+        self.inner.prune(policy).await
+    }
+
+    async fn reparse_user_agents(&self, all: bool) -> Result<u64> {
+        self.flush().await;
+        self.inner.reparse_user_agents(all).await
+    }
+
+    #[cfg(feature = "geoip")]
+    async fn backfill_geoip(&self) -> Result<u64> {
+        self.flush().await;
+        self.inner.backfill_geoip().await
+    }
+
+    /// Saves everything queued, and stops the background task.
+    async fn shutdown(&self) -> Result<()> {
+        self.closed.store(true, Ordering::Release);
+        // if the task was never spawned, nothing is queued
+        let spawned = self.receiver.lock().unwrap().is_none();
+        if spawned {
+            let (ack, done) = oneshot::channel();
+            if self.sender.send(QueueMsg::Shutdown(ack)).await.is_ok() {
+                let _ = done.await;
+            }
+        }
+        self.inner.shutdown().await
+    }
+}
+
+/// The background task: collects the queued visits into batches and saves them.
+async fn run_worker(
+    inner: Arc<dyn BroInfoStore>,
+    mut receiver: mpsc::Receiver<QueueMsg>,
+    max_batch: usize,
+    max_delay: Duration,
+) {
+    let mut batch = Vec::with_capacity(max_batch);
+    let mut acks = Acks::default();
+    while let Some(msg) = receiver.recv().await {
+        push_msg(msg, &mut batch, &mut acks);
+        let deadline = tokio::time::Instant::now() + max_delay;
+        while batch.len() < max_batch && acks.is_empty() {
+            match tokio::time::timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(msg)) => push_msg(msg, &mut batch, &mut acks),
+                Ok(None) | Err(_) => break,
+            }
+        }
+        let shutdown = !acks.shutdowns.is_empty();
+        if shutdown {
+            // the saves sent before `shutdown()` are still in the channel
+            receiver.close();
+            while let Some(msg) = receiver.recv().await {
+                push_msg(msg, &mut batch, &mut acks);
+            }
+        }
+        save_batch(&*inner, std::mem::take(&mut batch)).await;
+        for ack in acks.flushes.drain(..).chain(acks.shutdowns.drain(..)) {
+            let _ = ack.send(());
+        }
+        if shutdown {
+            return;
+        }
+    }
+}
+
+fn push_msg(
+    msg: QueueMsg,
+    batch: &mut Vec<(SaveBroInfoItem, oneshot::Sender<Result<()>>)>,
+    acks: &mut Acks,
+) {
+    match msg {
+        QueueMsg::Save(item, reply) => batch.push((*item, reply)),
+        QueueMsg::Flush(ack) => acks.flushes.push(ack),
+        QueueMsg::Shutdown(ack) => acks.shutdowns.push(ack),
+    }
+}
+
+/// Saves `batch` in one transaction, and reports the result to each sender.
+///
+/// If the transaction fails, the visits are saved one by one,
+/// so that one bad visit does not lose the others.
+async fn save_batch(
+    inner: &dyn BroInfoStore,
+    batch: Vec<(SaveBroInfoItem, oneshot::Sender<Result<()>>)>,
+) {
+    if batch.is_empty() {
+        return;
+    }
+    let (items, replies): (Vec<_>, Vec<_>) = batch.into_iter().unzip();
+    match inner.save_broinfo_batch(&items).await {
+        Ok(()) => {
+            for reply in replies {
+                let _ = reply.send(Ok(()));
+            }
+        }
+        Err(e) => {
+            dioxus::logger::tracing::warn!("save_broinfo_batch: {e:#}; retrying one by one");
+            for (item, reply) in items.iter().zip(replies) {
+                let r = inner.save_broinfo(&item.req, &item.ip_address).await;
+                let _ = reply.send(r);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::db_sqlite::SqliteStore;
+    use super::*;
+
+    fn queue_cfg(max_batch: usize, max_delay_ms: u64) -> WriteQueueConfig {
+        WriteQueueConfig {
+            enabled: true,
+            capacity: 16,
+            max_batch,
+            max_delay_ms,
+        }
+    }
+
+    fn save_req(bicmid: &str) -> SaveBroInfoRequest {
+        SaveBroInfoRequest {
+            bicmid: bicmid.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_queued_saves_00() {
+        let inner = Arc::new(SqliteStore::open_in_memory().await.unwrap());
+        let store = Arc::new(QueuedStore::new(inner, &queue_cfg(10, 20)));
+        // more saves than the capacity of the queue
+        let handles: Vec<_> = (0..100)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(async move {
+                    let req = save_req(&format!("b{}", i % 7));
+                    store.save_broinfo(&req, "192.0.2.1").await
+                })
+            })
+            .collect();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 100);
+        //
+        store.shutdown().await.unwrap();
+        // saves after the shutdown go to the inner store directly
+        store.save_broinfo(&save_req("b1"), "").await.unwrap();
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 101);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_shutdown_flushes_01() {
+        let inner = Arc::new(SqliteStore::open_in_memory().await.unwrap());
+        // the batch would wait for a minute without the shutdown
+        let store = Arc::new(QueuedStore::new(inner, &queue_cfg(1000, 60_000)));
+        let handles: Vec<_> = (0..5)
+            .map(|i| {
+                let store = store.clone();
+                tokio::spawn(
+                    async move { store.save_broinfo(&save_req(&format!("b{i}")), "").await },
+                )
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 0);
+        //
+        store.shutdown().await.unwrap();
+        for h in handles {
+            h.await.unwrap().unwrap();
+        }
+        let page = store.query_logs(&LogQuery::default()).await.unwrap();
+        assert_eq!(page.total, 5);
+    }
+
+    /// Compares the throughput of the saves with and without the write queue.
+    ///
+    /// `cargo test --release --features server bench_write_queue -- --ignored --nocapture`
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    #[ignore]
+    async fn bench_write_queue_02() {
+        const SAVES: usize = 2000;
+        const CLIENTS: usize = 64;
+        //
+        async fn run(store: Arc<dyn BroInfoStore>) -> f64 {
+            let start = std::time::Instant::now();
+            let handles: Vec<_> = (0..CLIENTS)
+                .map(|c| {
+                    let store = store.clone();
+                    tokio::spawn(async move {
+                        for i in (c..SAVES).step_by(CLIENTS) {
+                            let req = save_req(&format!("b{}", i % 500));
+                            store.save_broinfo(&req, "192.0.2.1").await.unwrap();
+                        }
+                    })
+                })
+                .collect();
+            for h in handles {
+                h.await.unwrap();
+            }
+            SAVES as f64 / start.elapsed().as_secs_f64()
+        }
+        //
+        let dir = std::env::temp_dir();
+        let pid = std::process::id();
+        let direct =
+            SqliteStore::open_file(&dir.join(format!("browserinfocm-bench-{pid}-0.sqlite3")))
+                .await
+                .unwrap();
+        let direct_rate = run(Arc::new(direct)).await;
+        //
+        let inner =
+            SqliteStore::open_file(&dir.join(format!("browserinfocm-bench-{pid}-1.sqlite3")))
+                .await
+                .unwrap();
+        // every client waits for its save, so that a batch holds at most `CLIENTS` visits
+        let queued = Arc::new(QueuedStore::new(Arc::new(inner), &queue_cfg(CLIENTS, 20)));
+        let queued_rate = run(queued.clone()).await;
+        queued.shutdown().await.unwrap();
+        //
+        for i in 0..2 {
+            let _ =
+                std::fs::remove_file(dir.join(format!("browserinfocm-bench-{pid}-{i}.sqlite3")));
+        }
+        println!("without write queue: {direct_rate:>10.1} saves/s");
+        println!("with write queue:    {queued_rate:>10.1} saves/s");
+    }
+}
@@ -0,0 +1,26 @@
+//! Automation signals of the browser.
+//! `BrowserInfoCm` sends them with the visit, and the server scores the visit as a bot with them,
+//! the user agent and the request headers, see `SaveBroInfoResponse::bot_score`.
+
+use anyhow::Result;
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// The automation signals of the browser that `JsInfo` does not have.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct AutomationSignals {
+    /// `navigator.webdriver`, set by a browser under automation, ex.) Selenium.
+    pub webdriver: bool,
+    /// The number of `navigator.plugins`, `None` if it is not supported.
+    pub plugins: Option<i32>,
+}
+
+/// Gathers the automation signals of the browser.
+pub async fn automation_signals() -> Result<AutomationSignals> {
+    let js_signals: &str = concat!(
+        r#"{return {webdriver:navigator.webdriver===true,"#,
+        r#"plugins:navigator.plugins?navigator.plugins.length:null};}"#
+    );
+    let v = document::eval(js_signals).await?;
+    Ok(serde_json::from_value(v)?)
+}
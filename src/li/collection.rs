@@ -0,0 +1,213 @@
+//! Field-level collection policy of the JS information.
+//! The server applies the `[collection]` policy to each visit before it is stored, and pushes it
+//! to `BrowserInfoCm` with `collection.push_to_client`, so that the browser does not send
+//! what is not stored.
+
+use anyhow::{bail, Result};
+use browserinfo::JsInfo;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// The fields of `JsInfo` that a policy can list.
+pub const JSINFO_FIELDS: [&str; 14] = [
+    "oscpu",
+    "platform",
+    "cpu_cores",
+    "cookie_enabled",
+    "user_language",
+    "device_memory",
+    "screen_width",
+    "screen_height",
+    "screen_color_depth",
+    "device_pixel_ratio",
+    "has_local_storage",
+    "has_session_storage",
+    "is_dark_mode",
+    "timezone",
+];
+
+/// The value of a redacted string field.
+pub const REDACTED: &str = "redacted";
+
+/// The step in pixels that a coarsened screen size is rounded to.
+const SCREEN_STEP: i32 = 100;
+
+/// What is done with one field of `JsInfo` before it is stored.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldAction {
+    /// Kept as it is.
+    #[default]
+    Collect,
+    /// A non-empty string is replaced by `REDACTED`, so that only its presence is kept.
+    /// The other fields are dropped.
+    Redact,
+    /// Kept in a coarser form: the screen sizes rounded to 100 pixels, the pixel ratio to 0.5,
+    /// the CPU cores down to a power of two, the platform and the OS CPU to their first word,
+    /// the language to its primary subtag and the time zone to its area, ex.) `Asia`.
+    /// The other fields are kept.
+    Coarsen,
+    /// Cleared: an empty string, no number, or `false`.
+    Drop,
+}
+
+/// The actions of the fields of `JsInfo`. The fields not listed are collected.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct CollectionPolicy {
+    /// The action of each field, by its name in `JSINFO_FIELDS`.
+    pub fields: BTreeMap<String, FieldAction>,
+}
+
+impl CollectionPolicy {
+    /// Creates the policy, failing on a field that is not in `JSINFO_FIELDS`.
+    pub fn new(fields: BTreeMap<String, FieldAction>) -> Result<Self> {
+        if let Some(name) = fields.keys().find(|k| !JSINFO_FIELDS.contains(&k.as_str())) {
+            bail!("unknown field of the collection policy: {name:?}");
+        }
+        Ok(Self { fields })
+    }
+
+    /// Returns the action of `field`.
+    pub fn action(&self, field: &str) -> FieldAction {
+        self.fields.get(field).copied().unwrap_or_default()
+    }
+
+    /// Applies the actions to the fields of `jsinfo`.
+    pub fn apply(&self, jsinfo: &mut JsInfo) {
+        for (field, action) in &self.fields {
+            apply_field(jsinfo, field, *action);
+        }
+    }
+}
+
+fn apply_field(info: &mut JsInfo, field: &str, action: FieldAction) {
+    match field {
+        "oscpu" => apply_str(&mut info.oscpu, action, first_word),
+        "platform" => apply_str(&mut info.platform, action, first_word),
+        "user_language" => apply_str(&mut info.user_language, action, primary_subtag),
+        "timezone" => apply_str(&mut info.timezone, action, timezone_area),
+        "cpu_cores" => apply_opt(&mut info.cpu_cores, action, floor_power_of_two),
+        "device_memory" => apply_opt(&mut info.device_memory, action, |n| n),
+        "screen_width" => apply_opt(&mut info.screen_width, action, round_screen),
+        "screen_height" => apply_opt(&mut info.screen_height, action, round_screen),
+        "screen_color_depth" => apply_opt(&mut info.screen_color_depth, action, |n| n),
+        "device_pixel_ratio" => apply_opt(&mut info.device_pixel_ratio, action, |r| {
+            (r * 2.0).round() / 2.0
+        }),
+        "cookie_enabled" => apply_bool(&mut info.cookie_enabled, action),
+        "has_local_storage" => apply_bool(&mut info.has_local_storage, action),
+        "has_session_storage" => apply_bool(&mut info.has_session_storage, action),
+        "is_dark_mode" => apply_bool(&mut info.is_dark_mode, action),
+        _ => {}
+    }
+}
+
+fn apply_str(v: &mut String, action: FieldAction, coarsen: fn(&str) -> String) {
+    match action {
+        FieldAction::Collect => {}
+        FieldAction::Redact if !v.is_empty() => *v = REDACTED.to_string(),
+        FieldAction::Redact => {}
+        FieldAction::Coarsen => *v = coarsen(v),
+        FieldAction::Drop => v.clear(),
+    }
+}
+
+fn apply_opt<T: Copy>(v: &mut Option<T>, action: FieldAction, coarsen: fn(T) -> T) {
+    match action {
+        FieldAction::Collect => {}
+        FieldAction::Coarsen => *v = v.map(coarsen),
+        FieldAction::Redact | FieldAction::Drop => *v = None,
+    }
+}
+
+fn apply_bool(v: &mut bool, action: FieldAction) {
+    if matches!(action, FieldAction::Redact | FieldAction::Drop) {
+        *v = false;
+    }
+}
+
+/// ex.) `Linux x86_64` to `Linux`.
+fn first_word(s: &str) -> String {
+    s.split_whitespace().next().unwrap_or("").to_string()
+}
+
+/// ex.) `en-US` to `en`.
+fn primary_subtag(s: &str) -> String {
+    s.split(['-', '_']).next().unwrap_or("").to_string()
+}
+
+/// ex.) `America/Argentina/Buenos_Aires` to `America`. `UTC` is kept.
+fn timezone_area(s: &str) -> String {
+    s.split('/').next().unwrap_or("").to_string()
+}
+
+fn floor_power_of_two(n: i32) -> i32 {
+    if n <= 0 {
+        n
+    } else {
+        1 << (31 - n.leading_zeros())
+    }
+}
+
+fn round_screen(n: i32) -> i32 {
+    ((n + SCREEN_STEP / 2) / SCREEN_STEP) * SCREEN_STEP
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn policy(fields: &[(&str, FieldAction)]) -> Result<CollectionPolicy> {
+        let fields = fields.iter().map(|(k, v)| (k.to_string(), *v)).collect();
+        CollectionPolicy::new(fields)
+    }
+
+    #[test]
+    fn test_apply_00() {
+        let mut info = JsInfo {
+            oscpu: "Linux x86_64".to_string(),
+            platform: "Linux x86_64".to_string(),
+            cpu_cores: Some(12),
+            user_language: "en-US".to_string(),
+            screen_width: Some(1366),
+            screen_height: Some(768),
+            device_pixel_ratio: Some(1.25),
+            is_dark_mode: true,
+            timezone: "Asia/Tokyo".to_string(),
+            ..Default::default()
+        };
+        let p = policy(&[
+            ("oscpu", FieldAction::Drop),
+            ("platform", FieldAction::Redact),
+            ("cpu_cores", FieldAction::Coarsen),
+            ("user_language", FieldAction::Coarsen),
+            ("screen_width", FieldAction::Coarsen),
+            ("screen_height", FieldAction::Coarsen),
+            ("device_pixel_ratio", FieldAction::Coarsen),
+            ("is_dark_mode", FieldAction::Drop),
+            ("timezone", FieldAction::Coarsen),
+        ])
+        .unwrap();
+        p.apply(&mut info);
+        assert_eq!(info.oscpu, "");
+        assert_eq!(info.platform, REDACTED);
+        assert_eq!(info.cpu_cores, Some(8));
+        assert_eq!(info.user_language, "en");
+        assert_eq!(info.screen_width, Some(1400));
+        assert_eq!(info.screen_height, Some(800));
+        assert_eq!(info.device_pixel_ratio, Some(1.5));
+        assert!(!info.is_dark_mode);
+        assert_eq!(info.timezone, "Asia");
+        assert_eq!(p.action("timezone"), FieldAction::Coarsen);
+        assert_eq!(p.action("screen_color_depth"), FieldAction::Collect);
+    }
+
+    #[test]
+    fn test_new_01() {
+        assert!(policy(&[("screen_width", FieldAction::Drop)]).is_ok());
+        assert!(policy(&[("gpu", FieldAction::Drop)]).is_err());
+        let json = r#"{"fields":{"timezone":"coarsen"}}"#;
+        let p: CollectionPolicy = serde_json::from_str(json).unwrap();
+        assert_eq!(p.action("timezone"), FieldAction::Coarsen);
+    }
+}
@@ -0,0 +1,127 @@
+//! Consent of the user to the data collection.
+//! `BrowserInfoCm` collects nothing until the `consent` prop allows it, and
+//! `navigator.doNotTrack` and Global Privacy Control limit it to `ConsentLevel::Essential`.
+
+use anyhow::Result;
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::SaveBroInfoRequest;
+
+/// The levels of the consent, from the least to the most data.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsentLevel {
+    /// Nothing is collected, and the stored identifiers are removed.
+    #[default]
+    None,
+    /// The visit is counted with the user agent and the referrer only,
+    /// without the BICMID, the session, the user and the JS information.
+    Essential,
+    /// Also the BICMID, the session, the user and the JS information,
+    /// and the page views and the events of the hooks.
+    Analytics,
+    /// Also the browser fingerprint, if the server has it enabled.
+    Full,
+}
+
+impl ConsentLevel {
+    /// Returns the name stored in `logs.consent`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::None => "none",
+            Self::Essential => "essential",
+            Self::Analytics => "analytics",
+            Self::Full => "full",
+        }
+    }
+
+    /// Whether the BICMID, the session ID, the user and the JS information may be collected.
+    pub fn allows_identifiers(&self) -> bool {
+        *self >= Self::Analytics
+    }
+
+    /// Whether the browser fingerprint may be computed.
+    pub fn allows_fingerprint(&self) -> bool {
+        *self >= Self::Full
+    }
+
+    /// Returns the level limited by the privacy signal of the browser:
+    /// `Essential` at most with `navigator.doNotTrack` or Global Privacy Control.
+    pub fn limited_by(self, privacy_signal: bool) -> Self {
+        if privacy_signal {
+            self.min(Self::Essential)
+        } else {
+            self
+        }
+    }
+}
+
+impl SaveBroInfoRequest {
+    /// Returns the consent of the request, `ConsentLevel::Full` from a client without it.
+    pub fn consent_level(&self) -> ConsentLevel {
+        self.consent.unwrap_or(ConsentLevel::Full)
+    }
+
+    /// Drops what the consent does not allow: below `ConsentLevel::Analytics`,
+    /// the BICMID, the session ID, the user and the JS information.
+    pub fn apply_consent(&mut self) {
+        if !self.consent_level().allows_identifiers() {
+            self.bicmid.clear();
+            self.session_id.clear();
+            self.user.clear();
+            self.broinfo.jsinfo = Default::default();
+        }
+    }
+}
+
+/// Returns whether the browser sends `navigator.doNotTrack` or Global Privacy Control.
+pub async fn has_privacy_signal() -> Result<bool> {
+    let js_signal: &str = concat!(
+        r#"{return navigator.doNotTrack==='1'||window.doNotTrack==='1'"#,
+        r#"||navigator.globalPrivacyControl===true;}"#
+    );
+    let v = document::eval(js_signal).await?;
+    Ok(v.as_bool().unwrap_or(false))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_consent_level_00() {
+        assert!(ConsentLevel::None < ConsentLevel::Essential);
+        assert!(!ConsentLevel::Essential.allows_identifiers());
+        assert!(ConsentLevel::Analytics.allows_identifiers());
+        assert!(!ConsentLevel::Analytics.allows_fingerprint());
+        assert!(ConsentLevel::Full.allows_fingerprint());
+        assert_eq!(ConsentLevel::Full.limited_by(true), ConsentLevel::Essential);
+        assert_eq!(ConsentLevel::None.limited_by(true), ConsentLevel::None);
+        assert_eq!(ConsentLevel::Full.limited_by(false), ConsentLevel::Full);
+        assert_eq!(
+            serde_json::to_string(&ConsentLevel::Analytics).unwrap(),
+            r#""analytics""#
+        );
+    }
+
+    #[test]
+    fn test_apply_consent_01() {
+        let mut req = SaveBroInfoRequest {
+            bicmid: "b1".to_string(),
+            user: "u1".to_string(),
+            ..Default::default()
+        };
+        req.broinfo.jsinfo.timezone = "UTC".to_string();
+        // from a client without the consent management
+        assert_eq!(req.consent_level(), ConsentLevel::Full);
+        req.apply_consent();
+        assert_eq!(req.bicmid, "b1");
+        //
+        req.consent = Some(ConsentLevel::Essential);
+        req.apply_consent();
+        assert_eq!(req.bicmid, "");
+        assert_eq!(req.user, "");
+        assert_eq!(req.broinfo.jsinfo.timezone, "");
+    }
+}
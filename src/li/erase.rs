@@ -0,0 +1,48 @@
+//! Right-to-erasure API.
+//! Deletes everything stored about one BICMID or one user. Requires the admin token.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::backends;
+
+/// Whose data is erased.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum EraseSubject {
+    /// The anonymous browser identifier (BICMID).
+    Bicmid(String),
+    /// Custom user identifier string.
+    User(String),
+}
+
+impl EraseSubject {
+    /// Returns the identifier of the subject.
+    pub fn value(&self) -> &str {
+        match self {
+            Self::Bicmid(s) | Self::User(s) => s,
+        }
+    }
+}
+
+/// The numbers of rows removed by `erase_subject()`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct EraseReport {
+    /// The deleted visits.
+    pub logs: u64,
+    /// The deleted page views.
+    pub page_views: u64,
+    /// The deleted custom events.
+    pub events: u64,
+    /// The deleted `bicmids` or `users` row of the subject, `0` if it was not found.
+    pub subjects: u64,
+    /// The deleted lookup values no longer referenced by any visit, page view or event,
+    /// e.g. the JS information, IP addresses and referrers of the subject.
+    pub lookups: u64,
+}
+
+/// Deletes the visits, the page views and the events of `subject`,
+/// its `bicmids` or `users` row, and the lookup values no longer referenced.
+/// Requires the admin token.
+pub async fn erase_subject(admin_token: &str, subject: EraseSubject) -> Result<EraseReport> {
+    backends::erase_subject(admin_token.to_string(), subject).await
+}
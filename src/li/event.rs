@@ -0,0 +1,153 @@
+//! Custom event tracking of the application.
+//! `track_event()` and the `EventTracker` of `use_browserinfo_events()` save the domain events,
+//! ex.) `checkout_started`, to `events` with the BICMID, the session and the user.
+
+use anyhow::{bail, Result};
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{backends, get_or_create_bicmid, BrowserInfoState};
+
+/// The maximum length of a normalized event name, in characters.
+pub const EVENT_NAME_MAX: usize = 64;
+
+/// The maximum size of the JSON of the event properties, in bytes.
+pub const EVENT_PROPERTIES_MAX: usize = 8192;
+
+/// Request structure for saving one custom event.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct EventRequest {
+    /// The name of the event. It is normalized by `normalize_event_name()`.
+    pub name: String,
+    /// The properties of the event: a JSON object, or `null` for none.
+    pub properties: serde_json::Value,
+    /// The anonymous browser identifier (BICMID).
+    pub bicmid: String,
+    /// The session identifier kept in `sessionStorage`.
+    pub session_id: String,
+    /// Custom user identifier string.
+    pub user: String,
+}
+
+impl EventRequest {
+    /// Returns the JSON of the properties as it is stored, `{}` for `null`.
+    pub fn properties_json(&self) -> Result<String> {
+        let s = match &self.properties {
+            serde_json::Value::Null => "{}".to_string(),
+            v @ serde_json::Value::Object(_) => serde_json::to_string(v)?,
+            _ => bail!("the properties of an event must be a JSON object"),
+        };
+        if s.len() > EVENT_PROPERTIES_MAX {
+            bail!("the properties of an event exceed {EVENT_PROPERTIES_MAX} bytes");
+        }
+        Ok(s)
+    }
+}
+
+/// Normalizes an event name: lowercased, with every run of the other characters than letters
+/// and digits replaced by `_`, and truncated to `EVENT_NAME_MAX` characters.
+///
+/// ex.) `"Checkout Started"` and `"checkout-started"` are `"checkout_started"`.
+pub fn normalize_event_name(name: &str) -> String {
+    let mut s = String::new();
+    for c in name.chars() {
+        if c.is_alphanumeric() {
+            s.extend(c.to_lowercase());
+        } else if !s.is_empty() && !s.ends_with('_') {
+            s.push('_');
+        }
+    }
+    let s = s.trim_end_matches('_');
+    s.chars().take(EVENT_NAME_MAX).collect()
+}
+
+/// The tracker of the custom events, returned by `use_browserinfo_events()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EventTracker {
+    state: Signal<BrowserInfoState>,
+}
+
+impl EventTracker {
+    /// Saves the event `name` with `properties` in the background,
+    /// with the BICMID, the session and the user of the current `BrowserInfoState`.
+    ///
+    /// Nothing is saved until the consent of the state is `ConsentLevel::Analytics` or more.
+    pub fn track(&self, name: &str, properties: serde_json::Value) {
+        let state = self.state.peek().clone();
+        if !state.consent.allows_identifiers() {
+            return;
+        }
+        let name = name.to_string();
+        spawn(async move {
+            if let Err(e) = save_event_with_state(state, name, properties).await {
+                dioxus::logger::tracing::error!("Failed to save the event: {e}");
+            }
+        });
+    }
+}
+
+/// A hook that returns the `EventTracker` of the `state` of `BrowserInfoCm`.
+pub fn use_browserinfo_events(state: Signal<BrowserInfoState>) -> EventTracker {
+    use_hook(|| EventTracker { state })
+}
+
+/// Saves the event `name` with `properties`, with the BICMID and the session of the browser.
+///
+/// Use `EventTracker` to save the user of `BrowserInfoState` too, and to honor the consent.
+/// This one creates the BICMID if there is none, so call it only with the consent of the user.
+pub async fn track_event(name: &str, properties: serde_json::Value) -> Result<()> {
+    save_event_with_state(BrowserInfoState::default(), name.to_string(), properties).await
+}
+
+/// Saves the event, reading the BICMID and the session from the storage if `state` has none yet.
+async fn save_event_with_state(
+    state: BrowserInfoState,
+    name: String,
+    properties: serde_json::Value,
+) -> Result<()> {
+    let (bicmid, session_id) = if state.bicmid.is_empty() {
+        get_or_create_bicmid().await?
+    } else {
+        (state.bicmid, state.session_id)
+    };
+    backends::save_event(EventRequest {
+        name,
+        properties,
+        bicmid,
+        session_id,
+        user: state.user,
+    })
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_event_name_00() {
+        assert_eq!(normalize_event_name("checkout_started"), "checkout_started");
+        assert_eq!(
+            normalize_event_name(" Checkout Started "),
+            "checkout_started"
+        );
+        assert_eq!(normalize_event_name("video--played!"), "video_played");
+        assert_eq!(normalize_event_name("_-_"), "");
+        assert_eq!(normalize_event_name(&"a".repeat(100)).len(), EVENT_NAME_MAX);
+    }
+
+    #[test]
+    fn test_properties_json_01() {
+        let mut req = EventRequest::default();
+        assert_eq!(req.properties_json().unwrap(), "{}");
+        req.properties = serde_json::json!({"plan": "pro", "items": 3});
+        assert_eq!(
+            req.properties_json().unwrap(),
+            r#"{"items":3,"plan":"pro"}"#
+        );
+        req.properties = serde_json::json!([1, 2]);
+        assert!(req.properties_json().is_err());
+        req.properties = serde_json::json!({"s": "x".repeat(EVENT_PROPERTIES_MAX)});
+        assert!(req.properties_json().is_err());
+    }
+}
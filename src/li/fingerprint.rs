@@ -0,0 +1,141 @@
+//! Fingerprint linking API.
+//! Lists the BICMIDs that share a browser fingerprint, so that the visits of one browser are
+//! linked across the lost BICMIDs. Requires `fingerprint.enabled` and the admin token.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::backends;
+
+/// The default number of fingerprints of a query.
+pub const FINGERPRINTS_DEFAULT: u32 = 50;
+
+/// The maximum number of fingerprints of a query.
+pub const FINGERPRINTS_MAX: u32 = 1000;
+
+/// Filter of a fingerprint query.
+///
+/// Without a filter, the fingerprints shared by two or more BICMIDs are listed.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FingerprintQuery {
+    /// Only this fingerprint.
+    pub fingerprint: Option<String>,
+    /// Only the fingerprints of this BICMID.
+    pub bicmid: Option<String>,
+    /// The number of fingerprints. `0` means `FINGERPRINTS_DEFAULT`.
+    pub limit: u32,
+}
+
+impl FingerprintQuery {
+    /// Returns the effective number of fingerprints, clamped to `FINGERPRINTS_MAX`.
+    pub fn limit(&self) -> u32 {
+        match self.limit {
+            0 => FINGERPRINTS_DEFAULT,
+            n => n.min(FINGERPRINTS_MAX),
+        }
+    }
+
+    /// Returns the number of BICMIDs a listed fingerprint has at least.
+    pub fn min_bicmids(&self) -> u32 {
+        if self.fingerprint.is_some() || self.bicmid.is_some() {
+            1
+        } else {
+            2
+        }
+    }
+}
+
+/// The visits of one BICMID with a fingerprint.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FingerprintBicmid {
+    /// The anonymous browser identifier (BICMID).
+    pub bicmid: String,
+    /// The number of visits.
+    pub visits: i64,
+    /// The UTC timestamp of the first visit, formatted as `YYYY-MM-DD HH:MM:SS`.
+    pub first_seen: String,
+    /// The UTC timestamp of the last visit.
+    pub last_seen: String,
+}
+
+/// One fingerprint and the BICMIDs that share it, the most recently seen first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct FingerprintGroup {
+    /// The fingerprint, as stored in `fingerprints`.
+    pub fingerprint: String,
+    pub bicmids: Vec<FingerprintBicmid>,
+}
+
+impl FingerprintGroup {
+    /// Groups `(fingerprint, BICMID)` rows that are ordered by fingerprint.
+    pub fn from_rows(rows: impl IntoIterator<Item = (String, FingerprintBicmid)>) -> Vec<Self> {
+        let mut groups: Vec<Self> = Vec::new();
+        for (fingerprint, bicmid) in rows {
+            match groups.last_mut() {
+                Some(g) if g.fingerprint == fingerprint => g.bicmids.push(bicmid),
+                _ => groups.push(Self {
+                    fingerprint,
+                    bicmids: vec![bicmid],
+                }),
+            }
+        }
+        groups
+    }
+}
+
+/// Lists the fingerprints matching `query` and the BICMIDs that share them,
+/// the fingerprints of the most BICMIDs first. Requires the admin token.
+pub async fn query_fingerprints(
+    admin_token: &str,
+    query: FingerprintQuery,
+) -> Result<Vec<FingerprintGroup>> {
+    backends::query_fingerprints(admin_token.to_string(), query).await
+}
+
+/// Lists the BICMIDs that share a fingerprint with `bicmid`, including itself.
+pub async fn list_linked_bicmids(admin_token: &str, bicmid: &str) -> Result<Vec<FingerprintGroup>> {
+    query_fingerprints(
+        admin_token,
+        FingerprintQuery {
+            bicmid: Some(bicmid.to_string()),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bicmid(s: &str) -> FingerprintBicmid {
+        FingerprintBicmid {
+            bicmid: s.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_from_rows_00() {
+        let groups = FingerprintGroup::from_rows([
+            ("f1".to_string(), bicmid("b1")),
+            ("f1".to_string(), bicmid("b2")),
+            ("f2".to_string(), bicmid("b1")),
+        ]);
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].fingerprint, "f1");
+        assert_eq!(groups[0].bicmids, vec![bicmid("b1"), bicmid("b2")]);
+        assert_eq!(groups[1].bicmids, vec![bicmid("b1")]);
+        //
+        let q = FingerprintQuery::default();
+        assert_eq!(q.min_bicmids(), 2);
+        assert_eq!(q.limit(), FINGERPRINTS_DEFAULT);
+        let q = FingerprintQuery {
+            bicmid: Some("b1".to_string()),
+            limit: 100_000,
+            ..Default::default()
+        };
+        assert_eq!(q.min_bicmids(), 1);
+        assert_eq!(q.limit(), FINGERPRINTS_MAX);
+    }
+}
@@ -9,11 +9,51 @@ use serde::{Deserialize, Serialize};
 #[cfg(feature = "backend_user_agent")]
 use browserinfo::{user_agent_js, UserAgent};
 
+mod admin;
 mod backends;
+mod bot;
+mod collection;
+mod consent;
+mod erase;
+mod event;
+mod fingerprint;
+mod outbox;
+mod pageview;
+mod query;
+
+pub use admin::BrowserInfoAdmin;
+pub use bot::*;
+pub use collection::*;
+pub use consent::*;
+pub use erase::*;
+pub use event::*;
+pub use fingerprint::*;
+pub use outbox::*;
+pub use pageview::*;
+pub use query::*;
 
 #[cfg(feature = "server")]
 pub use backends::backend_init;
 
+#[cfg(feature = "server")]
+pub use backends::backend_router;
+
+#[cfg(feature = "server")]
+pub use backends::backend_migrate_undo;
+
+#[cfg(feature = "server")]
+pub use backends::backend_shutdown;
+
+#[cfg(feature = "server")]
+pub use backends::backend_prune;
+
+#[cfg(feature = "server")]
+pub use backends::backend_reparse_user_agents;
+
+#[cfg(feature = "geoip")]
+#[cfg(feature = "server")]
+pub use backends::backend_geoip_backfill;
+
 /// Request structure for saving browser information to the backend.
 #[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
 pub struct SaveBroInfoRequest {
@@ -25,6 +65,35 @@ pub struct SaveBroInfoRequest {
     pub user: String,
     /// Whether to return the parsed Browser struct in the response.
     pub return_browser: bool,
+    /// The session identifier kept in `sessionStorage`, empty without it.
+    #[serde(default)]
+    pub session_id: String,
+    /// The client-generated ID of the request, the same in its retries. Empty without it.
+    ///
+    /// A retry of a stored request is not stored again, and gets the same response.
+    #[serde(default)]
+    pub request_id: String,
+    /// The consent of the user, `None` from a client without the consent management.
+    #[serde(default)]
+    pub consent: Option<ConsentLevel>,
+    /// The automation signals of the browser.
+    #[serde(default)]
+    pub automation: AutomationSignals,
+    /// The bot score of the visit. The server sets it, ignoring the one from a client.
+    #[serde(default)]
+    pub bot_score: u8,
+}
+
+/// Response structure of saving browser information.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct SaveBroInfoResponse {
+    /// The parsed Browser struct, if `return_browser` was set.
+    pub browser: Option<Browser>,
+    /// The browser fingerprint, empty if `fingerprint.enabled` is not set.
+    pub fingerprint: String,
+    /// The bot score of the visit, from `0`, a person, to `100`, surely automated.
+    #[serde(default)]
+    pub bot_score: u8,
 }
 
 /// Request structure for saving only the user agent string.
@@ -33,6 +102,11 @@ pub struct SaveBroInfoRequest {
 pub struct SaveUserAgentRequest {
     /// The user agent information.
     pub ua: UserAgent,
+    /// The client-generated ID of the request, the same in its retries. Empty without it.
+    ///
+    /// It is stored in `user_agent_requests`, and a retried request with the same ID is not stored again.
+    #[serde(default)]
+    pub request_id: String,
 }
 
 #[cfg(feature = "server")]
@@ -48,8 +122,16 @@ pub struct BrowserInfoState {
     pub browser: Browser,
     /// The anonymous browser identifier (BICMID).
     pub bicmid: String,
+    /// The session identifier kept in `sessionStorage`.
+    pub session_id: String,
     /// Custom user identifier string.
     pub user: String,
+    /// The browser fingerprint computed by the server, empty if it is not enabled.
+    pub fingerprint: String,
+    /// The effective consent, limited by the privacy signal of the browser.
+    pub consent: ConsentLevel,
+    /// The bot score of the visit computed by the server.
+    pub bot_score: u8,
 }
 
 /// Properties for the `BrowserInfoCm` component.
@@ -57,37 +139,101 @@ pub struct BrowserInfoState {
 pub struct BrowserInfoProps {
     /// Signal to store the gathered browser information and user identifier.
     state: Signal<BrowserInfoState>,
+    /// Signal of the consent of the user. Without it, `ConsentLevel::Full`.
+    consent: Option<Signal<ConsentLevel>>,
 }
 
-/// A Dioxus component that automatically gathers browser information and an anonymous ID (BICMID).
-/// It persists this data to the configured backend on mount.
+/// A Dioxus component that automatically gathers browser information, an anonymous ID (BICMID)
+/// and a session ID. It persists this data to the configured backend once the `consent` allows it.
+/// A failed save is queued in `localStorage` and retried, see `send_unsent_visits()`.
+///
+/// Nothing is collected with `ConsentLevel::None`, and `navigator.doNotTrack` or Global Privacy
+/// Control limit the consent to `ConsentLevel::Essential`. The visit is saved once, at the first
+/// level that allows it. Below `ConsentLevel::Analytics`, the stored identifiers are removed.
+///
+/// `clear_bicmid()` forgets the BICMID, so that the next mount creates a new one.
 #[component]
-pub fn BrowserInfoCm(mut props: BrowserInfoProps) -> Element {
-    use_future(move || async move {
-        match get_or_create_bicmid().await {
-            Ok(bicmid) => {
-                props.state.write().bicmid = bicmid.clone();
-                let user = props.state.read().user.clone();
-                match get_browserinfo(bicmid, user).await {
-                    Ok((broinfo, browser)) => {
-                        let mut state = props.state.write();
-                        state.broinfo = broinfo;
-                        state.browser = browser;
-                    }
-                    Err(e) => dioxus::logger::tracing::error!("Failed to get browser info: {e}"),
-                }
+pub fn BrowserInfoCm(props: BrowserInfoProps) -> Element {
+    let saved = use_signal(|| false);
+    use_effect(move || {
+        // subscribes to the changes of the consent
+        let consent = match props.consent {
+            Some(sig) => *sig.read(),
+            None => ConsentLevel::Full,
+        };
+        spawn(async move {
+            if let Err(e) = collect_with_consent(props.state, saved, consent).await {
+                dioxus::logger::tracing::error!("Failed to collect the browser info: {e}");
             }
-            Err(e) => dioxus::logger::tracing::error!("Failed to get or create bicmid: {e}"),
-        }
+        });
     });
 
     rsx! {}
 }
 
+/// Applies the `requested` consent, limited by the privacy signal, to `state`,
+/// and saves the visit unless `saved` or the consent does not allow it.
+async fn collect_with_consent(
+    mut state: Signal<BrowserInfoState>,
+    mut saved: Signal<bool>,
+    requested: ConsentLevel,
+) -> Result<()> {
+    let consent = requested.limited_by(has_privacy_signal().await?);
+    state.write().consent = consent;
+    if !consent.allows_identifiers() {
+        // not given, or withdrawn
+        clear_bicmid().await?;
+        let mut state = state.write();
+        state.bicmid.clear();
+        state.session_id.clear();
+        state.fingerprint.clear();
+    }
+    if consent == ConsentLevel::None || *saved.peek() {
+        return Ok(());
+    }
+    saved.set(true);
+    let (bicmid, session_id, user) = if consent.allows_identifiers() {
+        let (bicmid, session_id) = get_or_create_bicmid().await?;
+        let mut state = state.write();
+        state.bicmid = bicmid.clone();
+        state.session_id = session_id.clone();
+        (bicmid, session_id, state.user.clone())
+    } else {
+        Default::default()
+    };
+    match save_browserinfo(bicmid, session_id, user, Some(consent)).await {
+        Ok((broinfo, res)) => {
+            let mut state = state.write();
+            state.broinfo = broinfo;
+            state.browser = res.browser.unwrap_or_default();
+            state.fingerprint = res.fingerprint;
+            state.bot_score = res.bot_score;
+        }
+        Err(e) => dioxus::logger::tracing::error!("Failed to get browser info: {e}"),
+    }
+    // retries the visits whose save failed, this one or the ones of the former mounts
+    outbox::run_outbox().await
+}
+
 /// Gathers browser information using JavaScript execution and saves it to the backend.
 ///
-/// Returns a tuple of `(BroInfo, Browser)` on success.
+/// Returns a tuple of `(BroInfo, Browser)` on success. On failure to save, the visit is queued
+/// to be retried by `send_unsent_visits()`, unless the server rejected it.
+/// Without a session ID, the visits of `bicmid` are sessionized by `session.timeout_secs` only.
 pub async fn get_browserinfo(bicmid: String, user: String) -> Result<(BroInfo, Browser)> {
+    let (broinfo, res) = save_browserinfo(bicmid, String::new(), user, None).await?;
+    Ok((broinfo, res.browser.unwrap_or_default()))
+}
+
+/// Gathers browser information and saves it, returning the response of the backend.
+/// The JS information is dropped unless `consent` allows it, and the collection policy
+/// of the server is applied to it.
+async fn save_browserinfo(
+    bicmid: String,
+    session_id: String,
+    user: String,
+    consent: Option<ConsentLevel>,
+) -> Result<(BroInfo, SaveBroInfoResponse)> {
     use browserinfo::FromJsonStr;
     //
     #[cfg(feature = "backend_user_agent")]
@@ -97,40 +243,83 @@ pub async fn get_browserinfo(bicmid: String, user: String) -> Result<(BroInfo, B
         let s = v.to_string();
         dioxus::logger::tracing::debug!("Raw JSON from JS: {s}");
         let user_agent = UserAgent::from_json_str(&s)?;
-        let _ = backends::save_user_agent(SaveUserAgentRequest { ua: user_agent }).await;
+        let _ = backends::save_user_agent(SaveUserAgentRequest {
+            ua: user_agent,
+            request_id: new_request_id(),
+        })
+        .await;
     }
     //
     let js_bro: &str = broinfo_js();
     let v = document::eval(js_bro).await?;
     let s = v.to_string();
     dioxus::logger::tracing::debug!("Raw JSON from JS: {s}");
-    let broinfo = BroInfo::from_json_str(&s)?;
-    let browser = backends::save_broinfo(SaveBroInfoRequest {
+    let mut broinfo = BroInfo::from_json_str(&s)?;
+    if consent.is_some_and(|c| !c.allows_identifiers()) {
+        broinfo.jsinfo = Default::default();
+    } else {
+        // the server applies it again, this is not to send what is not stored
+        match backends::get_collection_policy().await {
+            Ok(policy) => policy.apply(&mut broinfo.jsinfo),
+            Err(e) => dioxus::logger::tracing::debug!("Failed to get the collection policy: {e}"),
+        }
+    }
+    let automation = match automation_signals().await {
+        Ok(automation) => automation,
+        Err(e) => {
+            dioxus::logger::tracing::debug!("Failed to get the automation signals: {e}");
+            AutomationSignals::default()
+        }
+    };
+    let req = SaveBroInfoRequest {
         broinfo: broinfo.clone(),
         bicmid,
         user,
         return_browser: true,
-    })
-    .await?
-    .unwrap();
-    Ok((broinfo, browser))
+        session_id,
+        request_id: new_request_id(),
+        consent,
+        automation,
+        bot_score: 0,
+    };
+    match backends::save_broinfo(req.clone()).await {
+        Ok(res) => Ok((broinfo, res)),
+        Err(e) => {
+            // keeps it to retry later, unless the server rejected it
+            if outbox::is_retryable(&e) {
+                outbox::push_outbox(&req).await?;
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Retrieves or creates an anonymous browser identifier (BICMID) from `localStorage`,
+/// and a session identifier from `sessionStorage`, which lasts as long as the tab.
+///
+/// Returns a tuple of `(bicmid, session_id)`.
+async fn get_or_create_bicmid() -> Result<(String, String)> {
+    let bicmid = get_or_create_id("localStorage", "anon_bicmid").await?;
+    let session_id = get_or_create_id("sessionStorage", "anon_session_id").await?;
+    Ok((bicmid, session_id))
 }
 
-/// Retrieves or creates an anonymous browser identifier (BICMID) from `localStorage`.
+/// Retrieves or creates the identifier `key` in the web storage `storage`.
 ///
 /// If it doesn't exist, a new UUID (V4) is generated and stored.
-async fn get_or_create_bicmid() -> Result<String> {
+/// Returns an empty string if the storage is not supported.
+async fn get_or_create_id(storage: &str, key: &str) -> Result<String> {
     use base64::Engine;
 
-    // check 'localStrage' support
-    let v =
-        document::eval(r#"{var r=false;if('localStorage' in window){r=true}return r;}"#).await?;
+    // check the storage support
+    let js_has = format!(r#"{{var r=false;if('{storage}' in window){{r=true}}return r;}}"#);
+    let v = document::eval(&js_has).await?;
     if !v.as_bool().unwrap_or(false) {
         return Ok("".to_string());
     }
-    // get from localStrage
-    let js_get: &str = r#"{return window.localStorage.getItem('anon_bicmid');}"#;
-    let v = document::eval(js_get).await?;
+    // get from the storage
+    let js_get = format!(r#"{{return window.{storage}.getItem('{key}');}}"#);
+    let v = document::eval(&js_get).await?;
     let ss = v.as_str().unwrap_or("");
     if !ss.is_empty() {
         Ok(ss.to_string())
@@ -138,17 +327,31 @@ async fn get_or_create_bicmid() -> Result<String> {
         // generate a uuid (128bits:16byte)
         let uuid = uuid::Uuid::new_v4();
         let uuid_s = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(uuid.as_bytes());
-        // set into localStrage
-        let js_set =
-            format!(r#"{{window.localStorage.setItem('anon_bicmid','{uuid_s}');return '';}}"#);
+        // set into the storage
+        let js_set = format!(r#"{{window.{storage}.setItem('{key}','{uuid_s}');return '';}}"#);
         let _v = document::eval(&js_set).await?;
         Ok(uuid_s)
     }
 }
 
-/// Server function to retrieve the current database path.
-pub async fn get_db_path() -> Result<String> {
-    backends::get_db_path().await
+/// Removes the anonymous browser identifier (BICMID) and the unsent visits from `localStorage`,
+/// and the session identifier from `sessionStorage`.
+///
+/// Use it after `erase_subject()`, so that the browser is not linked to the erased visits again.
+pub async fn clear_bicmid() -> Result<()> {
+    let js_remove: &str = concat!(
+        r#"{if('localStorage' in window){window.localStorage.removeItem('anon_bicmid');"#,
+        r#"window.localStorage.removeItem('anon_outbox');}"#,
+        r#"if('sessionStorage' in window){window.sessionStorage.removeItem('anon_session_id');}"#,
+        r#"return '';}"#
+    );
+    let _v = document::eval(js_remove).await?;
+    Ok(())
+}
+
+/// Server function to retrieve the current database path. Requires the admin token.
+pub async fn get_db_path(admin_token: &str) -> Result<String> {
+    backends::get_db_path(admin_token.to_string()).await
 }
 
 #[cfg(test)]
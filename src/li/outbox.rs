@@ -0,0 +1,269 @@
+//! Offline queue of the unsent visits.
+//! A visit whose save fails, offline or by an error of the server, is kept in `localStorage`
+//! (`anon_outbox`), and retried with exponential backoff on the next mount of `BrowserInfoCm`
+//! and when the browser is back online. A visit the server rejects, with a 4xx status, is dropped.
+//! Each visit has a client-generated `request_id`, so that its retries can be deduplicated.
+
+use anyhow::Result;
+use dioxus::fullstack::{RequestError, ServerFnError};
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{backends, SaveBroInfoRequest};
+
+/// The maximum number of queued visits. The oldest ones are dropped beyond it.
+pub const OUTBOX_MAX: usize = 20;
+
+/// The maximum number of the retries of a queued visit. It is dropped after them.
+pub const OUTBOX_MAX_ATTEMPTS: u32 = 10;
+
+/// The first delay of the retries, in milliseconds. It doubles after each failed retry.
+const RETRY_DELAY_MIN_MS: i32 = 1_000;
+
+/// The maximum delay of the retries, in milliseconds.
+const RETRY_DELAY_MAX_MS: i32 = 300_000;
+
+/// Generates a new request ID, a UUID (V4) in URL-safe Base64 like the BICMID.
+pub fn new_request_id() -> String {
+    use base64::Engine;
+
+    let uuid = uuid::Uuid::new_v4();
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(uuid.as_bytes())
+}
+
+/// A queued visit.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+struct OutboxEntry {
+    #[serde(flatten)]
+    req: SaveBroInfoRequest,
+    /// The number of the failed retries.
+    #[serde(default)]
+    attempts: u32,
+}
+
+/// What became of the retry of a queued visit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Resend {
+    /// It is saved.
+    Sent,
+    /// The server rejected it, so it is dropped.
+    Rejected,
+    /// It failed, and may succeed later.
+    Failed,
+}
+
+/// Returns `true` if the save that failed with `e` may succeed later: a transport error,
+/// or an error of the server. A 4xx response, e.g. an authentication failure, a rate limit,
+/// or a too large request, is a rejection of the request itself, so it is not retried.
+pub(super) fn is_retryable(e: &anyhow::Error) -> bool {
+    let status = match e.downcast_ref::<ServerFnError>() {
+        Some(ServerFnError::ServerError { code, .. }) => Some(*code),
+        Some(ServerFnError::Request(e)) => e.status_code(),
+        Some(_) => None,
+        None => e
+            .downcast_ref::<RequestError>()
+            .and_then(|e| e.status_code()),
+    };
+    !status.is_some_and(|code| (400..500).contains(&code))
+}
+
+/// Adds `req` to `queue` unless its request ID is already there,
+/// and drops the oldest ones beyond `OUTBOX_MAX`.
+fn enqueue(queue: &mut Vec<OutboxEntry>, req: &SaveBroInfoRequest) {
+    if queue.iter().any(|q| q.req.request_id == req.request_id) {
+        return;
+    }
+    queue.push(OutboxEntry {
+        req: req.clone(),
+        attempts: 0,
+    });
+    if queue.len() > OUTBOX_MAX {
+        let n = queue.len() - OUTBOX_MAX;
+        queue.drain(..n);
+    }
+}
+
+/// Applies the `results` of the retries by request ID to `queue`: removes the sent and
+/// the rejected visits, and counts the failure of the others, dropping them after
+/// `OUTBOX_MAX_ATTEMPTS`. The visits without a result are kept as they are.
+fn apply_results(queue: &mut Vec<OutboxEntry>, results: &[(String, Resend)]) {
+    queue.retain_mut(|q| {
+        let Some((_, r)) = results.iter().find(|(id, _)| *id == q.req.request_id) else {
+            return true;
+        };
+        match r {
+            Resend::Sent | Resend::Rejected => false,
+            Resend::Failed => {
+                q.attempts += 1;
+                q.attempts < OUTBOX_MAX_ATTEMPTS
+            }
+        }
+    });
+}
+
+/// Loads the queued visits. Empty if `localStorage` is not supported.
+async fn load_outbox() -> Result<Vec<OutboxEntry>> {
+    let js_get: &str = concat!(
+        r#"{if(!('localStorage' in window)){return '';}"#,
+        r#"return window.localStorage.getItem('anon_outbox') || '';}"#
+    );
+    let v = document::eval(js_get).await?;
+    let s = v.as_str().unwrap_or("");
+    if s.is_empty() {
+        return Ok(Vec::new());
+    }
+    Ok(serde_json::from_str(s)?)
+}
+
+/// Stores the queued visits, removing the key if there are none.
+async fn store_outbox(queue: &[OutboxEntry]) -> Result<()> {
+    let js_set: &str = concat!(
+        r#"{let v=await dioxus.recv();if(!('localStorage' in window)){return '';}"#,
+        r#"if(v===''){window.localStorage.removeItem('anon_outbox');}"#,
+        r#"else{window.localStorage.setItem('anon_outbox',v);}return '';}"#
+    );
+    let s = if queue.is_empty() {
+        String::new()
+    } else {
+        serde_json::to_string(queue)?
+    };
+    let e = document::eval(js_set);
+    e.send(s)?;
+    let _v = e.await?;
+    Ok(())
+}
+
+/// Queues the visit `req` whose save failed.
+pub(super) async fn push_outbox(req: &SaveBroInfoRequest) -> Result<()> {
+    let mut queue = load_outbox().await?;
+    enqueue(&mut queue, req);
+    store_outbox(&queue).await
+}
+
+/// Saves the queued visits, oldest first, and returns the number of the visits still unsent.
+///
+/// A failed visit is skipped, so that it does not hold back the ones after it.
+pub async fn send_unsent_visits() -> Result<usize> {
+    let queue = load_outbox().await?;
+    let mut results = Vec::new();
+    for q in &queue {
+        let r = match backends::save_broinfo(q.req.clone()).await {
+            Ok(_) => Resend::Sent,
+            Err(e) if is_retryable(&e) => {
+                dioxus::logger::tracing::debug!("Failed to resend the visit: {e}");
+                Resend::Failed
+            }
+            Err(e) => {
+                dioxus::logger::tracing::error!("The visit is rejected, and dropped: {e}");
+                Resend::Rejected
+            }
+        };
+        results.push((q.req.request_id.clone(), r));
+    }
+    // reloads it, since another visit may have been queued meanwhile
+    let mut queue = load_outbox().await?;
+    apply_results(&mut queue, &results);
+    store_outbox(&queue).await?;
+    Ok(queue.len())
+}
+
+/// Waits `millis` milliseconds, or less if the browser comes back online.
+/// While the browser is offline, it waits until it is online.
+async fn wait_for_retry(millis: i32) -> Result<()> {
+    let js_wait = format!(
+        concat!(
+            r#"{{await new Promise((r)=>{{"#,
+            r#"let t=navigator.onLine?setTimeout(r,{}):null;"#,
+            r#"window.addEventListener('online',()=>{{if(t){{clearTimeout(t);}}r();}},{{once:true}});"#,
+            r#"}});return '';}}"#
+        ),
+        millis
+    );
+    let _v = document::eval(&js_wait).await?;
+    Ok(())
+}
+
+/// Retries the queued visits with exponential backoff until none is left.
+pub(super) async fn run_outbox() -> Result<()> {
+    let mut delay = RETRY_DELAY_MIN_MS;
+    while !load_outbox().await?.is_empty() {
+        wait_for_retry(delay).await?;
+        send_unsent_visits().await?;
+        delay = (delay * 2).min(RETRY_DELAY_MAX_MS);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn req(request_id: &str) -> SaveBroInfoRequest {
+        SaveBroInfoRequest {
+            request_id: request_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_enqueue_00() {
+        let mut queue = Vec::new();
+        enqueue(&mut queue, &req("r1"));
+        enqueue(&mut queue, &req("r1"));
+        assert_eq!(queue.len(), 1);
+        for i in 0..OUTBOX_MAX {
+            enqueue(&mut queue, &req(&format!("x{i}")));
+        }
+        assert_eq!(queue.len(), OUTBOX_MAX);
+        assert_eq!(queue[0].req.request_id, "x0");
+        assert_ne!(new_request_id(), new_request_id());
+        assert_eq!(new_request_id().len(), 22);
+    }
+
+    #[test]
+    fn test_apply_results_01() {
+        let mut queue = Vec::new();
+        for id in ["r1", "r2", "r3", "r4"] {
+            enqueue(&mut queue, &req(id));
+        }
+        queue[3].attempts = OUTBOX_MAX_ATTEMPTS - 1;
+        let results = [
+            ("r1".to_string(), Resend::Failed),
+            ("r2".to_string(), Resend::Sent),
+            ("r3".to_string(), Resend::Rejected),
+            ("r4".to_string(), Resend::Failed),
+        ];
+        enqueue(&mut queue, &req("r5"));
+        apply_results(&mut queue, &results);
+        // the failed one is kept until its last attempt, and the one queued meanwhile too
+        let ids: Vec<_> = queue.iter().map(|q| q.req.request_id.as_str()).collect();
+        assert_eq!(ids, ["r1", "r5"]);
+        assert_eq!(queue[0].attempts, 1);
+        assert_eq!(queue[1].attempts, 0);
+    }
+
+    #[test]
+    fn test_outbox_entry_02() {
+        // the visits queued by the former versions, without `attempts`
+        let old = serde_json::to_string(&[req("r1")]).unwrap();
+        let queue: Vec<OutboxEntry> = serde_json::from_str(&old).unwrap();
+        assert_eq!(queue[0].req.request_id, "r1");
+        assert_eq!(queue[0].attempts, 0);
+    }
+
+    #[test]
+    fn test_is_retryable_03() {
+        let server_error = |code: u16| {
+            anyhow::Error::from(ServerFnError::ServerError {
+                message: String::new(),
+                code,
+                details: None,
+            })
+        };
+        assert!(is_retryable(&server_error(500)));
+        assert!(is_retryable(&server_error(503)));
+        assert!(!is_retryable(&server_error(401)));
+        assert!(!is_retryable(&server_error(429)));
+        assert!(is_retryable(&anyhow::anyhow!("offline")));
+    }
+}
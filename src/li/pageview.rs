@@ -0,0 +1,84 @@
+//! Page view tracking of a single page app.
+//! `use_browserinfo_pageview()` saves a page view on each route change of the Dioxus router,
+//! tied to the BICMID and the session of `BrowserInfoCm`.
+
+use anyhow::Result;
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::{backends, get_or_create_bicmid, BrowserInfoState};
+
+/// Request structure for saving one page view.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct PageViewRequest {
+    /// The route of the page, ex.) `/blog/1?tab=comments`.
+    pub path: String,
+    /// The `document.title` of the page.
+    pub title: String,
+    /// The previous route, or `document.referrer` for the first page view.
+    pub referrer: String,
+    /// The anonymous browser identifier (BICMID).
+    pub bicmid: String,
+    /// The session identifier kept in `sessionStorage`.
+    pub session_id: String,
+    /// Custom user identifier string.
+    pub user: String,
+}
+
+/// A hook that saves a page view on mount and on each route change.
+///
+/// Call it in a component inside the `Router`, ex.) the layout. It is a no-op outside a router,
+/// and until the consent of `state` is `ConsentLevel::Analytics` or more.
+/// The user is read from `state`.
+pub fn use_browserinfo_pageview(state: Signal<BrowserInfoState>) {
+    let mut previous = use_signal(|| None::<String>);
+    use_effect(move || {
+        let Some(router) = dioxus::router::try_router() else {
+            return;
+        };
+        // subscribes the effect to the route changes, and to the consent
+        let path = router.full_route_string();
+        if !state.read().consent.allows_identifiers() {
+            return;
+        }
+        let referrer = previous.peek().clone();
+        if referrer.as_deref() == Some(path.as_str()) {
+            return;
+        }
+        previous.set(Some(path.clone()));
+        let user = state.peek().user.clone();
+        spawn(async move {
+            if let Err(e) = save_current_pageview(path, referrer, user).await {
+                dioxus::logger::tracing::error!("Failed to save the page view: {e}");
+            }
+        });
+    });
+}
+
+/// Saves the page view of `path`, reading the title and the first referrer from the document.
+async fn save_current_pageview(path: String, referrer: Option<String>, user: String) -> Result<()> {
+    let (bicmid, session_id) = get_or_create_bicmid().await?;
+    let v = document::eval(r#"{return document.title;}"#).await?;
+    let title = v.as_str().unwrap_or("").to_string();
+    let referrer = match referrer {
+        Some(s) => s,
+        None => {
+            let v = document::eval(r#"{return document.referrer;}"#).await?;
+            v.as_str().unwrap_or("").to_string()
+        }
+    };
+    save_pageview(PageViewRequest {
+        path,
+        title,
+        referrer,
+        bicmid,
+        session_id,
+        user,
+    })
+    .await
+}
+
+/// Saves one page view to the backend.
+pub async fn save_pageview(req: PageViewRequest) -> Result<()> {
+    backends::save_pageview(req).await
+}
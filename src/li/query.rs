@@ -0,0 +1,335 @@
+//! Read-side API for the stored visit logs.
+//! Provides the query types shared by the client and the server, and convenience wrappers
+//! around the `query_logs` server function. Every query requires the admin token.
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::backends;
+
+/// The default number of entries per page.
+pub const LOGS_PER_PAGE_DEFAULT: u32 = 50;
+
+/// The maximum number of entries per page.
+pub const LOGS_PER_PAGE_MAX: u32 = 1000;
+
+/// One stored visit, joined with its normalized values.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LogEntry {
+    /// The `logs.id` of the visit.
+    pub id: i64,
+    /// The UTC timestamp of the visit, formatted as `YYYY-MM-DD HH:MM:SS`.
+    pub create_at: String,
+    /// The anonymous browser identifier (BICMID).
+    pub bicmid: String,
+    /// Custom user identifier string.
+    pub user: String,
+    /// The user agent string.
+    pub user_agent: String,
+    /// The referrer URL.
+    pub referrer: String,
+    /// The client's IP address, anonymized as `ip_address_mode` says.
+    pub ip_address: String,
+    /// How the IP address was anonymized: `full`, `truncate`, `hash` or `drop`.
+    pub ip_address_mode: String,
+    /// The JS information as stored in the database, a JSON object of `JsInfo`.
+    pub jsinfo: String,
+    /// The bot score of the visit, from `0`, a person, to `100`, surely automated.
+    #[serde(default)]
+    pub bot_score: i64,
+}
+
+impl LogEntry {
+    /// Parses the JS information.
+    pub fn parse_jsinfo(&self) -> anyhow::Result<browserinfo::JsInfo> {
+        Ok(serde_json::from_str(&self.jsinfo)?)
+    }
+
+    /// Returns the JS information as an indented JSON document.
+    pub fn jsinfo_pretty(&self) -> String {
+        serde_json::from_str::<serde_json::Value>(&self.jsinfo)
+            .and_then(|v| serde_json::to_string_pretty(&v))
+            .unwrap_or_else(|_| self.jsinfo.clone())
+    }
+}
+
+/// Filter and pagination of a logs query.
+///
+/// Every filter that is `None` is not applied.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LogQuery {
+    /// Only visits of this BICMID.
+    pub bicmid: Option<String>,
+    /// Only visits of this user.
+    pub user: Option<String>,
+    /// Only visits at or after this UTC timestamp (`YYYY-MM-DD HH:MM:SS`).
+    pub since: Option<String>,
+    /// Only visits before this UTC timestamp (`YYYY-MM-DD HH:MM:SS`).
+    pub until: Option<String>,
+    /// The zero-based page number.
+    pub page: u32,
+    /// The number of entries per page. `0` means `LOGS_PER_PAGE_DEFAULT`.
+    pub per_page: u32,
+}
+
+impl LogQuery {
+    /// Returns the effective page size, clamped to `LOGS_PER_PAGE_MAX`.
+    pub fn limit(&self) -> u32 {
+        match self.per_page {
+            0 => LOGS_PER_PAGE_DEFAULT,
+            n => n.min(LOGS_PER_PAGE_MAX),
+        }
+    }
+
+    /// Returns the number of entries to skip.
+    pub fn offset(&self) -> u64 {
+        self.page as u64 * self.limit() as u64
+    }
+}
+
+/// One page of a logs query, newest first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LogPage {
+    /// The entries of this page.
+    pub entries: Vec<LogEntry>,
+    /// The total number of entries matching the filter.
+    pub total: i64,
+    /// The zero-based page number.
+    pub page: u32,
+    /// The effective number of entries per page.
+    pub per_page: u32,
+}
+
+impl LogPage {
+    /// Returns the number of pages.
+    pub fn page_count(&self) -> u32 {
+        if self.per_page == 0 {
+            return 0;
+        }
+        (self.total.max(0) as u64).div_ceil(self.per_page as u64) as u32
+    }
+}
+
+/// The number of visits of one browser or one operating system.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LogCount {
+    /// The name of the browser or the operating system.
+    pub name: String,
+    /// The number of visits.
+    pub count: i64,
+}
+
+/// The numbers of visits by browser and by operating system, largest first.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct LogStats {
+    /// The visits by browser name.
+    pub browsers: Vec<LogCount>,
+    /// The visits by operating system name.
+    pub oses: Vec<LogCount>,
+    /// The total number of visits.
+    pub total: i64,
+}
+
+/// The numbers of the save requests accepted and rejected by `[limits]` since the server started.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct SaveMetrics {
+    /// The accepted requests.
+    pub accepted: u64,
+    /// The requests rejected by the rate limit of the client IP address.
+    pub rate_limited_ip: u64,
+    /// The requests rejected by the rate limit of the BICMID.
+    pub rate_limited_bicmid: u64,
+    /// The requests rejected by `limits.max_payload_bytes`.
+    pub payload_too_large: u64,
+    /// The requests rejected by `limits.max_field_len`.
+    pub field_too_long: u64,
+    /// The requests rejected for the malformed JS information.
+    pub malformed_jsinfo: u64,
+}
+
+impl LogStats {
+    /// The name used when the user agent could not be parsed.
+    pub const UNKNOWN: &'static str = "(unknown)";
+
+    /// Aggregates `(user agent, number of visits)` pairs by parsing each user agent.
+    pub fn from_user_agent_counts<'a>(counts: impl IntoIterator<Item = (&'a str, i64)>) -> Self {
+        use browserinfo::BroInfo;
+        use std::collections::BTreeMap;
+        //
+        let mut browsers: BTreeMap<String, i64> = BTreeMap::new();
+        let mut oses: BTreeMap<String, i64> = BTreeMap::new();
+        let mut total = 0;
+        for (ua, n) in counts {
+            let mut broinfo = BroInfo::default();
+            broinfo.basic.user_agent = ua.into();
+            let browser = broinfo.to_browser().unwrap_or_default();
+            let os_name = browser.os.map(|os| os.name).unwrap_or_default();
+            *browsers.entry(unknown_if_empty(browser.name)).or_default() += n;
+            *oses.entry(unknown_if_empty(os_name)).or_default() += n;
+            total += n;
+        }
+        Self {
+            browsers: sorted_counts(browsers),
+            oses: sorted_counts(oses),
+            total,
+        }
+    }
+}
+
+fn unknown_if_empty(name: String) -> String {
+    if name.is_empty() || name == "Other" {
+        LogStats::UNKNOWN.to_string()
+    } else {
+        name
+    }
+}
+
+fn sorted_counts(map: std::collections::BTreeMap<String, i64>) -> Vec<LogCount> {
+    let mut v: Vec<LogCount> = map
+        .into_iter()
+        .map(|(name, count)| LogCount { name, count })
+        .collect();
+    // stable sort keeps the names in order for equal counts
+    v.sort_by_key(|c| std::cmp::Reverse(c.count));
+    v
+}
+
+/// Queries the stored visits. Requires the admin token.
+pub async fn query_logs(admin_token: &str, query: LogQuery) -> Result<LogPage> {
+    backends::query_logs(admin_token.to_string(), query).await
+}
+
+/// Returns the numbers of the save requests accepted and rejected by the limits of the server.
+/// Requires the admin token.
+pub async fn query_save_metrics(admin_token: &str) -> Result<SaveMetrics> {
+    backends::query_save_metrics(admin_token.to_string()).await
+}
+
+/// Counts the stored visits by browser and by operating system. Requires the admin token.
+pub async fn query_log_stats(admin_token: &str, query: LogQuery) -> Result<LogStats> {
+    backends::query_log_stats(admin_token.to_string(), query).await
+}
+
+/// Lists the most recent visits.
+pub async fn list_recent_logs(admin_token: &str, page: u32, per_page: u32) -> Result<LogPage> {
+    query_logs(
+        admin_token,
+        LogQuery {
+            page,
+            per_page,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Lists the visits of the given BICMID.
+pub async fn list_logs_by_bicmid(
+    admin_token: &str,
+    bicmid: &str,
+    page: u32,
+    per_page: u32,
+) -> Result<LogPage> {
+    query_logs(
+        admin_token,
+        LogQuery {
+            bicmid: Some(bicmid.to_string()),
+            page,
+            per_page,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Lists the visits of the given user.
+pub async fn list_logs_by_user(
+    admin_token: &str,
+    user: &str,
+    page: u32,
+    per_page: u32,
+) -> Result<LogPage> {
+    query_logs(
+        admin_token,
+        LogQuery {
+            user: Some(user.to_string()),
+            page,
+            per_page,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Lists the visits in the UTC time range `since <= create_at < until`.
+pub async fn list_logs_in_range(
+    admin_token: &str,
+    since: &str,
+    until: &str,
+    page: u32,
+    per_page: u32,
+) -> Result<LogPage> {
+    query_logs(
+        admin_token,
+        LogQuery {
+            since: Some(since.to_string()),
+            until: Some(until.to_string()),
+            page,
+            per_page,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_limit_offset_00() {
+        let q = LogQuery::default();
+        assert_eq!(q.limit(), LOGS_PER_PAGE_DEFAULT);
+        assert_eq!(q.offset(), 0);
+        let q = LogQuery {
+            page: 3,
+            per_page: 20,
+            ..Default::default()
+        };
+        assert_eq!(q.limit(), 20);
+        assert_eq!(q.offset(), 60);
+        let q = LogQuery {
+            per_page: 100_000,
+            ..Default::default()
+        };
+        assert_eq!(q.limit(), LOGS_PER_PAGE_MAX);
+    }
+
+    #[test]
+    fn test_page_count_01() {
+        let mut p = LogPage {
+            per_page: 50,
+            ..Default::default()
+        };
+        assert_eq!(p.page_count(), 0);
+        p.total = 50;
+        assert_eq!(p.page_count(), 1);
+        p.total = 51;
+        assert_eq!(p.page_count(), 2);
+    }
+
+    #[test]
+    fn test_log_stats_02() {
+        let firefox = "Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0";
+        let stats = LogStats::from_user_agent_counts([(firefox, 3), ("", 1), ("curl/8.0", 5)]);
+        assert_eq!(stats.total, 9);
+        assert_eq!(stats.browsers[0].name, "curl");
+        assert_eq!(stats.browsers[0].count, 5);
+        assert_eq!(stats.browsers[1].name, "Firefox");
+        assert_eq!(stats.browsers[1].count, 3);
+        assert_eq!(stats.browsers[2].name, LogStats::UNKNOWN);
+        assert_eq!(stats.browsers[2].count, 1);
+        assert_eq!(stats.oses[0].name, LogStats::UNKNOWN);
+        assert_eq!(stats.oses[0].count, 6);
+    }
+}
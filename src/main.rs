@@ -3,11 +3,21 @@
 
 use dioxus::prelude::*;
 
-mod li;
+use browserinfocm as li;
 
+use li::BrowserInfoAdmin;
 use li::BrowserInfoCm;
 use li::BrowserInfoState;
 
+/// The routes of the application.
+#[derive(Routable, Clone, PartialEq)]
+enum Route {
+    #[route("/")]
+    BroInfoHome {},
+    #[route("/admin")]
+    BrowserInfoAdmin {},
+}
+
 fn main() {
     #[cfg(feature = "server")]
     li::backend_init().expect("faile to init backend");
@@ -34,30 +44,76 @@ fn main() {
         dioxus::fullstack::set_server_url(static_url);
     }
 
+    #[cfg(feature = "server")]
+    serve();
+    #[cfg(not(feature = "server"))]
     dioxus::launch(App);
 }
 
+/// Serves the app with the connect info, so that the socket peer of a request is known,
+/// until ctrl-c or SIGTERM, and then shuts the backend down to save the queued visits.
+#[cfg(feature = "server")]
+fn serve() {
+    use dioxus::fullstack::axum;
+    use std::net::SocketAddr;
+
+    let rt = tokio::runtime::Runtime::new().expect("failed to build the runtime");
+    rt.block_on(async {
+        let addr = dioxus::cli_config::fullstack_address_or_localhost();
+        let listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .unwrap_or_else(|e| panic!("failed to bind to {addr}: {e}"));
+        let router = li::backend_router(dioxus::server::router(App));
+        axum::serve(
+            listener,
+            router.into_make_service_with_connect_info::<SocketAddr>(),
+        )
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .expect("failed to serve");
+        if let Err(e) = li::backend_shutdown().await {
+            dioxus::logger::tracing::error!("failed to shut down the backend: {e:#}");
+        }
+    });
+}
+
+/// Waits for ctrl-c, or SIGTERM on unix.
+#[cfg(feature = "server")]
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to listen for ctrl-c");
+    };
+    #[cfg(unix)]
+    let terminate = async {
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM")
+            .recv()
+            .await;
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+    dioxus::logger::tracing::info!("shutting down");
+}
+
 /// The root component of the application.
 #[component]
 fn App() -> Element {
     rsx! {
-        BroInfoHome {}
+        Router::<Route> {}
     }
 }
 
 /// A demonstration component that displays gathered browser information.
 #[component]
 fn BroInfoHome() -> Element {
-    let mut db_path_sig = use_signal(String::new);
-    use_future(move || async move {
-        match li::get_db_path().await {
-            Ok(s) => db_path_sig.set(s),
-            Err(e) => dioxus::logger::tracing::error!("Failed to get DB path: {e}"),
-        }
-    });
-    let db_path_s = db_path_sig.read().clone();
-
     let state_sig = use_signal(BrowserInfoState::default);
+    li::use_browserinfo_pageview(state_sig);
 
     let state = state_sig.read();
     let brg_s = format!("{:?}", state.browser);
@@ -71,7 +127,6 @@ fn BroInfoHome() -> Element {
         }
         div {
             h3 { "System Information" }
-            div { "Database: {db_path_s}" }
             div { "Browser Info (Condensed): {brg_s}" }
             hr {}
             h3 { "Session Details" }
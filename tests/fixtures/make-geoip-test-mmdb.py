@@ -0,0 +1,120 @@
+#!/usr/bin/env python3
+"""Writes `geoip-test.mmdb`, a tiny MaxMind DB with GeoIP2 City and ASN fields.
+
+    192.0.2.0/24     JP, Tokyo, Tokyo, AS64500 "Example Net JP"
+    198.51.100.0/24  US only
+    2001:db8::/32    DE, Berlin, Berlin, AS64501 "Example Net DE"
+
+The networks are the documentation ranges of RFC 5737 and RFC 3849.
+"""
+import ipaddress
+import os
+import struct
+
+RECORDS = [
+    ("192.0.2.0/24", {
+        "country": {"iso_code": "JP", "names": {"en": "Japan"}},
+        "subdivisions": [{"iso_code": "13", "names": {"en": "Tokyo"}}],
+        "city": {"names": {"en": "Tokyo"}},
+        "autonomous_system_number": 64500,
+        "autonomous_system_organization": "Example Net JP",
+    }),
+    ("198.51.100.0/24", {
+        "country": {"iso_code": "US", "names": {"en": "United States"}},
+    }),
+    ("2001:db8::/32", {
+        "country": {"iso_code": "DE", "names": {"en": "Germany"}},
+        "subdivisions": [{"iso_code": "BE", "names": {"en": "Berlin"}}],
+        "city": {"names": {"en": "Berlin"}},
+        "autonomous_system_number": 64501,
+        "autonomous_system_organization": "Example Net DE",
+    }),
+]
+
+
+def ctrl(type_, size):
+    if size < 29:
+        head, ext = size, b""
+    elif size < 285:
+        head, ext = 29, bytes([size - 29])
+    else:
+        head, ext = 30, struct.pack(">H", size - 285)
+    if type_ <= 7:
+        return bytes([(type_ << 5) | head]) + ext
+    return bytes([head, type_ - 7]) + ext
+
+
+def encode(v):
+    if isinstance(v, str):
+        b = v.encode()
+        return ctrl(2, len(b)) + b
+    if isinstance(v, bool):
+        return ctrl(14, int(v))
+    if isinstance(v, int):
+        b = v.to_bytes((v.bit_length() + 7) // 8, "big")
+        type_ = 5 if v < 1 << 16 else 6 if v < 1 << 32 else 9
+        return ctrl(type_, len(b)) + b
+    if isinstance(v, dict):
+        return ctrl(7, len(v)) + b"".join(encode(k) + encode(x) for k, x in v.items())
+    if isinstance(v, list):
+        return ctrl(11, len(v)) + b"".join(encode(x) for x in v)
+    raise TypeError(v)
+
+
+def main():
+    data = b""
+    root = [None, None]
+    for net_s, rec in RECORDS:
+        net = ipaddress.ip_network(net_s)
+        if net.version == 4:
+            bits = 96 + net.prefixlen
+            value = int(net.network_address)
+        else:
+            bits = net.prefixlen
+            value = int(net.network_address)
+        node = root
+        for i in range(bits - 1):
+            bit = (value >> (127 - i)) & 1
+            if node[bit] is None:
+                node[bit] = [None, None]
+            node = node[bit]
+        node[(value >> (128 - bits)) & 1] = len(data)
+        data += encode(rec)
+    # numbers the nodes breadth first, the root is 0
+    nodes, queue = [], [root]
+    while queue:
+        node = queue.pop(0)
+        nodes.append(node)
+        queue.extend(c for c in node if isinstance(c, list))
+    index = {id(n): i for i, n in enumerate(nodes)}
+    count = len(nodes)
+
+    def record(c):
+        if c is None:
+            return count
+        if isinstance(c, list):
+            return index[id(c)]
+        return count + 16 + c
+
+    tree = b"".join(
+        record(n[0]).to_bytes(3, "big") + record(n[1]).to_bytes(3, "big") for n in nodes
+    )
+    metadata = {
+        "node_count": count,
+        "record_size": 24,
+        "ip_version": 6,
+        "database_type": "browserinfocm-test",
+        "languages": ["en"],
+        "binary_format_major_version": 2,
+        "binary_format_minor_version": 0,
+        "build_epoch": 1760745600,
+        "description": {"en": "browserinfocm test fixture"},
+    }
+    out = tree + b"\0" * 16 + data + b"\xab\xcd\xefMaxMind.com" + encode(metadata)
+    path = os.path.join(os.path.dirname(os.path.abspath(__file__)), "geoip-test.mmdb")
+    with open(path, "wb") as f:
+        f.write(out)
+
+
+if __name__ == "__main__":
+    main()